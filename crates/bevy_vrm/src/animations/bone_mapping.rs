@@ -0,0 +1,745 @@
+//! Mapping of skeleton joint names to humanoid bones.
+//!
+//! Built-in profiles cover common rigs, and [fuzzy_bone_name] handles the rest.
+//! Used to retarget animations of any glTF scene, such as Mixamo clips converted from FBX,
+//! onto a VRM avatar.
+
+use std::{collections::BTreeMap, f32::consts::PI};
+
+use bevy::{
+    animation::{AnimationTarget, AnimationTargetId},
+    ecs::system::SystemState,
+    prelude::*,
+    utils::HashMap,
+};
+use serde::{Deserialize, Serialize};
+use serde_vrm::vrm0::BoneName;
+
+use crate::animations::{
+    humanoid::HumanoidRig,
+    retarget::{HumanoidRestPose, Retargeter},
+    vrm::VRM_ANIMATION_TARGETS,
+};
+
+pub(crate) const LEFT_FINGERS: [[BoneName; 3]; 5] = [
+    [
+        BoneName::LeftThumbProximal,
+        BoneName::LeftThumbIntermediate,
+        BoneName::LeftThumbDistal,
+    ],
+    [
+        BoneName::LeftIndexProximal,
+        BoneName::LeftIndexIntermediate,
+        BoneName::LeftIndexDistal,
+    ],
+    [
+        BoneName::LeftMiddleProximal,
+        BoneName::LeftMiddleIntermediate,
+        BoneName::LeftMiddleDistal,
+    ],
+    [
+        BoneName::LeftRingProximal,
+        BoneName::LeftRingIntermediate,
+        BoneName::LeftRingDistal,
+    ],
+    [
+        BoneName::LeftLittleProximal,
+        BoneName::LeftLittleIntermediate,
+        BoneName::LeftLittleDistal,
+    ],
+];
+
+pub(crate) const RIGHT_FINGERS: [[BoneName; 3]; 5] = [
+    [
+        BoneName::RightThumbProximal,
+        BoneName::RightThumbIntermediate,
+        BoneName::RightThumbDistal,
+    ],
+    [
+        BoneName::RightIndexProximal,
+        BoneName::RightIndexIntermediate,
+        BoneName::RightIndexDistal,
+    ],
+    [
+        BoneName::RightMiddleProximal,
+        BoneName::RightMiddleIntermediate,
+        BoneName::RightMiddleDistal,
+    ],
+    [
+        BoneName::RightRingProximal,
+        BoneName::RightRingIntermediate,
+        BoneName::RightRingDistal,
+    ],
+    [
+        BoneName::RightLittleProximal,
+        BoneName::RightLittleIntermediate,
+        BoneName::RightLittleDistal,
+    ],
+];
+
+/// Limbs shared by every profile, as `(part, left, right)`.
+const LIMBS: [(&str, BoneName, BoneName); 8] = [
+    ("shoulder", BoneName::LeftShoulder, BoneName::RightShoulder),
+    ("upper_arm", BoneName::LeftUpperArm, BoneName::RightUpperArm),
+    ("lower_arm", BoneName::LeftLowerArm, BoneName::RightLowerArm),
+    ("hand", BoneName::LeftHand, BoneName::RightHand),
+    ("upper_leg", BoneName::LeftUpperLeg, BoneName::RightUpperLeg),
+    ("lower_leg", BoneName::LeftLowerLeg, BoneName::RightLowerLeg),
+    ("foot", BoneName::LeftFoot, BoneName::RightFoot),
+    ("toes", BoneName::LeftToes, BoneName::RightToes),
+];
+
+/// Maps joint names of a rig to humanoid bones.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct BoneMappingProfile {
+    pub name: String,
+    /// Prefixes removed from joint names before lookup, such as `mixamorig`.
+    /// Separators and digits following a prefix are also removed.
+    #[serde(default)]
+    pub prefixes: Vec<String>,
+    /// Joint name to bone. Names are matched case insensitively,
+    /// after removing namespaces (`namespace:Joint`) and [Self::prefixes].
+    pub bones: BTreeMap<String, BoneName>,
+}
+
+impl BoneMappingProfile {
+    fn from_limbs(
+        name: &str,
+        prefixes: &[&str],
+        center: &[(&str, BoneName)],
+        limb: impl Fn(&str, bool) -> String,
+        finger: impl Fn(usize, usize, bool) -> String,
+    ) -> Self {
+        let mut bones = center
+            .iter()
+            .map(|(joint, bone)| (joint.to_string(), *bone))
+            .collect::<BTreeMap<_, _>>();
+
+        for (part, left, right) in LIMBS {
+            bones.insert(limb(part, true), left);
+            bones.insert(limb(part, false), right);
+        }
+
+        for (is_left, fingers) in [(true, LEFT_FINGERS), (false, RIGHT_FINGERS)] {
+            for (i, finger_bones) in fingers.iter().enumerate() {
+                for (j, bone) in finger_bones.iter().enumerate() {
+                    bones.insert(finger(i, j, is_left), *bone);
+                }
+            }
+        }
+
+        Self {
+            name: name.to_string(),
+            prefixes: prefixes.iter().map(|p| p.to_string()).collect(),
+            bones,
+        }
+    }
+
+    /// Mixamo rigs, with or without the `mixamorig:` prefix.
+    pub fn mixamo() -> Self {
+        Self::from_limbs(
+            "Mixamo",
+            &["mixamorig"],
+            &[
+                ("Hips", BoneName::Hips),
+                ("Spine", BoneName::Spine),
+                ("Spine1", BoneName::Chest),
+                ("Spine2", BoneName::UpperChest),
+                ("Neck", BoneName::Neck),
+                ("Head", BoneName::Head),
+                ("LeftEye", BoneName::LeftEye),
+                ("RightEye", BoneName::RightEye),
+            ],
+            |part, left| {
+                let side = if left { "Left" } else { "Right" };
+                let part = match part {
+                    "shoulder" => "Shoulder",
+                    "upper_arm" => "Arm",
+                    "lower_arm" => "ForeArm",
+                    "hand" => "Hand",
+                    "upper_leg" => "UpLeg",
+                    "lower_leg" => "Leg",
+                    "foot" => "Foot",
+                    _ => "ToeBase",
+                };
+                format!("{}{}", side, part)
+            },
+            |finger, joint, left| {
+                let side = if left { "Left" } else { "Right" };
+                let finger = ["Thumb", "Index", "Middle", "Ring", "Pinky"][finger];
+                format!("{}Hand{}{}", side, finger, joint + 1)
+            },
+        )
+    }
+
+    /// Avatars exported from VRoid Studio.
+    pub fn vroid() -> Self {
+        Self::from_limbs(
+            "VRoid",
+            &[],
+            &[
+                ("J_Bip_C_Hips", BoneName::Hips),
+                ("J_Bip_C_Spine", BoneName::Spine),
+                ("J_Bip_C_Chest", BoneName::Chest),
+                ("J_Bip_C_UpperChest", BoneName::UpperChest),
+                ("J_Bip_C_Neck", BoneName::Neck),
+                ("J_Bip_C_Head", BoneName::Head),
+                ("J_Adj_L_FaceEye", BoneName::LeftEye),
+                ("J_Adj_R_FaceEye", BoneName::RightEye),
+            ],
+            |part, left| {
+                let side = if left { "L" } else { "R" };
+                let part = match part {
+                    "shoulder" => "Shoulder",
+                    "upper_arm" => "UpperArm",
+                    "lower_arm" => "LowerArm",
+                    "hand" => "Hand",
+                    "upper_leg" => "UpperLeg",
+                    "lower_leg" => "LowerLeg",
+                    "foot" => "Foot",
+                    _ => "ToeBase",
+                };
+                format!("J_Bip_{}_{}", side, part)
+            },
+            |finger, joint, left| {
+                let side = if left { "L" } else { "R" };
+                let finger = ["Thumb", "Index", "Middle", "Ring", "Little"][finger];
+                format!("J_Bip_{}_{}{}", side, finger, joint + 1)
+            },
+        )
+    }
+
+    /// Unity Humanoid (`HumanBodyBones`) names, which are also the VRM 0.0 bone names.
+    pub fn unity() -> Self {
+        let bones = VRM_ANIMATION_TARGETS
+            .keys()
+            .filter_map(|bone| match serde_json::to_value(bone) {
+                Ok(serde_json::Value::String(name)) => Some((name, *bone)),
+                _ => None,
+            })
+            .collect();
+
+        Self {
+            name: "Unity".to_string(),
+            prefixes: Vec::new(),
+            bones,
+        }
+    }
+
+    /// Blender Rigify deform bones, or the bones of the metarig.
+    pub fn rigify() -> Self {
+        Self::from_limbs(
+            "Rigify",
+            &["DEF", "ORG"],
+            &[
+                ("spine", BoneName::Hips),
+                ("spine.001", BoneName::Spine),
+                ("spine.002", BoneName::Chest),
+                ("spine.003", BoneName::UpperChest),
+                ("spine.004", BoneName::Neck),
+                ("spine.006", BoneName::Head),
+                ("eye.L", BoneName::LeftEye),
+                ("eye.R", BoneName::RightEye),
+                ("jaw", BoneName::Jaw),
+            ],
+            |part, left| {
+                let side = if left { "L" } else { "R" };
+                let part = match part {
+                    "shoulder" => "shoulder",
+                    "upper_arm" => "upper_arm",
+                    "lower_arm" => "forearm",
+                    "hand" => "hand",
+                    "upper_leg" => "thigh",
+                    "lower_leg" => "shin",
+                    "foot" => "foot",
+                    _ => "toe",
+                };
+                format!("{}.{}", part, side)
+            },
+            |finger, joint, left| {
+                let side = if left { "L" } else { "R" };
+                let finger = ["thumb", "f_index", "f_middle", "f_ring", "f_pinky"][finger];
+                format!("{}.0{}.{}", finger, joint + 1, side)
+            },
+        )
+    }
+
+    pub fn builtin() -> [Self; 4] {
+        [Self::mixamo(), Self::vroid(), Self::unity(), Self::rigify()]
+    }
+
+    /// Builds a profile from the given joint names using [fuzzy_bone_name].
+    pub fn fuzzy<'a>(joints: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut bones = BTreeMap::new();
+
+        for joint in joints {
+            if let Some(bone) = fuzzy_bone_name(joint) {
+                if !bones.values().any(|b| *b == bone) {
+                    bones.insert(joint.to_string(), bone);
+                }
+            }
+        }
+
+        Self {
+            name: "Auto".to_string(),
+            prefixes: Vec::new(),
+            bones,
+        }
+    }
+
+    /// Picks the built-in profile that maps the most bones,
+    /// or a [fuzzy](Self::fuzzy) profile if it maps more.
+    pub fn detect<'a>(joints: impl IntoIterator<Item = &'a str>) -> Self {
+        let joints = joints.into_iter().collect::<Vec<_>>();
+
+        let fuzzy = Self::fuzzy(joints.iter().copied());
+        let fuzzy_count = fuzzy.bones.len();
+
+        Self::builtin()
+            .into_iter()
+            .map(|profile| {
+                let count = profile.bones_of(joints.iter().copied()).len();
+                (count, profile)
+            })
+            .filter(|(count, _)| *count >= fuzzy_count && *count > 0)
+            .max_by_key(|(count, _)| *count)
+            .map(|(_, profile)| profile)
+            .unwrap_or(fuzzy)
+    }
+
+    fn normalize(&self, joint: &str) -> String {
+        let mut name = joint.rsplit(':').next().unwrap_or(joint).to_lowercase();
+
+        for prefix in self.prefixes.iter() {
+            if let Some(rest) = name.strip_prefix(&prefix.to_lowercase()) {
+                name = rest
+                    .trim_start_matches(|c: char| c.is_ascii_digit() || matches!(c, '_' | '-'))
+                    .to_string();
+                break;
+            }
+        }
+
+        name
+    }
+
+    pub fn bone_name(&self, joint: &str) -> Option<BoneName> {
+        let name = self.normalize(joint);
+
+        self.bones
+            .iter()
+            .find(|(key, _)| key.to_lowercase() == name)
+            .map(|(_, bone)| *bone)
+    }
+
+    /// Maps joints to bones, keeping the first joint found for each bone.
+    fn bones_of<'a, T>(&self, joints: impl IntoIterator<Item = T>) -> Vec<(T, BoneName)>
+    where
+        T: NamedJoint<'a>,
+    {
+        let mut out = Vec::<(T, BoneName)>::new();
+
+        for joint in joints {
+            let Some(bone) = self.bone_name(joint.joint_name()) else {
+                continue;
+            };
+
+            if out.iter().all(|(_, b)| *b != bone) {
+                out.push((joint, bone));
+            }
+        }
+
+        out
+    }
+
+    /// Maps named entities to bones, keeping the first entity found for each bone.
+    pub fn bone_entities<'a>(
+        &self,
+        entities: impl IntoIterator<Item = (Entity, &'a Name)>,
+    ) -> Vec<(Entity, BoneName)> {
+        self.bones_of(entities)
+            .into_iter()
+            .map(|((entity, _), bone)| (entity, bone))
+            .collect()
+    }
+
+    /// Maps the animation targets of named entities to bones,
+    /// for use with [Retargeter::clip].
+    pub fn target_bones<'a>(
+        &self,
+        targets: impl IntoIterator<Item = (&'a AnimationTarget, &'a Name)>,
+    ) -> HashMap<AnimationTargetId, BoneName> {
+        self.bones_of(targets)
+            .into_iter()
+            .map(|((target, _), bone)| (target.id, bone))
+            .collect()
+    }
+}
+
+trait NamedJoint<'a> {
+    fn joint_name(&self) -> &'a str;
+}
+
+impl<'a> NamedJoint<'a> for &'a str {
+    fn joint_name(&self) -> &'a str {
+        self
+    }
+}
+
+impl<'a, T> NamedJoint<'a> for (T, &'a Name) {
+    fn joint_name(&self) -> &'a str {
+        self.1.as_str()
+    }
+}
+
+/// Splits a joint name into lowercase words, at separators, case changes and digits.
+fn words(name: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut prev: Option<char> = None;
+
+    for c in name.chars() {
+        if !c.is_alphanumeric() {
+            if !current.is_empty() {
+                words.push(std::mem::take(&mut current));
+            }
+            prev = None;
+            continue;
+        }
+
+        if let Some(p) = prev {
+            let boundary = (p.is_lowercase() && c.is_uppercase())
+                || (p.is_ascii_digit() != c.is_ascii_digit());
+
+            if boundary && !current.is_empty() {
+                words.push(std::mem::take(&mut current));
+            }
+        }
+
+        current.push(c.to_ascii_lowercase());
+        prev = Some(c);
+    }
+
+    if !current.is_empty() {
+        words.push(current);
+    }
+
+    words
+}
+
+/// Words that carry no bone information, such as rig prefixes.
+const IGNORED_WORDS: [&str; 10] = [
+    "mixamorig",
+    "def",
+    "org",
+    "j",
+    "bip",
+    "adj",
+    "c",
+    "f",
+    "face",
+    "bone",
+];
+
+/// Guesses the [BoneName] of a joint from common naming conventions,
+/// including Mixamo, CMU, VRoid, Unity, Rigify and 3ds Max Biped.
+pub fn fuzzy_bone_name(joint: &str) -> Option<BoneName> {
+    let name = joint.rsplit(':').next().unwrap_or(joint);
+
+    let mut side = None;
+    let mut parts = Vec::new();
+
+    for word in words(name) {
+        match word.as_str() {
+            "l" | "left" if side.is_none() => side = Some(true),
+            "r" | "right" if side.is_none() => side = Some(false),
+            w if IGNORED_WORDS.contains(&w) => {}
+            // Numbers before the bone name, such as `Bip01`.
+            w if parts.is_empty() && w.chars().all(|c| c.is_ascii_digit()) => {}
+            _ => parts.push(word),
+        }
+    }
+
+    let part = parts.concat();
+
+    let Some(left) = side else {
+        return match part.as_str() {
+            "hips" | "hip" | "pelvis" => Some(BoneName::Hips),
+            "spine" | "abdomen" => Some(BoneName::Spine),
+            "spine1" | "chest" => Some(BoneName::Chest),
+            "spine2" | "upperchest" => Some(BoneName::UpperChest),
+            "neck" => Some(BoneName::Neck),
+            "head" => Some(BoneName::Head),
+            "jaw" => Some(BoneName::Jaw),
+            _ => None,
+        };
+    };
+
+    let limb = match part.as_str() {
+        "shoulder" | "collar" | "clavicle" => Some("shoulder"),
+        "arm" | "upperarm" | "shldr" => Some("upper_arm"),
+        "forearm" | "lowerarm" => Some("lower_arm"),
+        "hand" => Some("hand"),
+        "upleg" | "upperleg" | "thigh" => Some("upper_leg"),
+        "leg" | "lowerleg" | "shin" | "calf" => Some("lower_leg"),
+        "foot" => Some("foot"),
+        "toebase" | "toes" | "toe" | "toe0" => Some("toes"),
+        _ => None,
+    };
+
+    if let Some(limb) = limb {
+        return LIMBS
+            .iter()
+            .find(|(p, _, _)| *p == limb)
+            .map(|(_, l, r)| if left { *l } else { *r });
+    }
+
+    if part == "eye" {
+        return Some(if left {
+            BoneName::LeftEye
+        } else {
+            BoneName::RightEye
+        });
+    }
+
+    let finger_part = part.strip_prefix("hand").unwrap_or(&part);
+    let split = finger_part.find(|c: char| c.is_ascii_digit()).or_else(|| {
+        ["proximal", "intermediate", "distal"]
+            .iter()
+            .find_map(|j| finger_part.find(j))
+    })?;
+    let (finger, joint) = finger_part.split_at(split);
+
+    let finger = match finger {
+        "thumb" => 0,
+        "index" => 1,
+        "middle" => 2,
+        "ring" => 3,
+        "pinky" | "little" => 4,
+        _ => return None,
+    };
+
+    let joint = match joint {
+        "1" | "01" | "proximal" => 0,
+        "2" | "02" | "intermediate" => 1,
+        "3" | "03" | "distal" => 2,
+        _ => return None,
+    };
+
+    let fingers = if left { LEFT_FINGERS } else { RIGHT_FINGERS };
+    Some(fingers[finger][joint])
+}
+
+/// Retargets a clip of a spawned glTF scene onto a VRM avatar with a [HumanoidRig].
+///
+/// The glTF scene must be in its rest pose and face +Z, as is usual for glTF.
+/// The profile is [detected](BoneMappingProfile::detect) from the node names if not given.
+pub fn retarget_scene_clip(
+    world: &mut World,
+    source: Entity,
+    vrm: Entity,
+    clip: &AnimationClip,
+    profile: Option<&BoneMappingProfile>,
+) -> Option<AnimationClip> {
+    let mut state = SystemState::<(
+        Query<&Children>,
+        Query<(&Name, Option<&AnimationTarget>)>,
+        Query<(&Transform, Option<&Parent>)>,
+        Query<&HumanoidRig>,
+    )>::new(world);
+    let (children, names, transforms, rigs) = state.get(world);
+
+    let rig = rigs.get(vrm).ok()?;
+
+    let joints = children
+        .iter_descendants(source)
+        .filter_map(|entity| names.get(entity).ok().map(|(name, _)| (entity, name)))
+        .collect::<Vec<_>>();
+
+    let detected;
+    let profile = match profile {
+        Some(profile) => profile,
+        None => {
+            detected = BoneMappingProfile::detect(joints.iter().map(|(_, name)| name.as_str()));
+            &detected
+        }
+    };
+
+    let bones = profile.bone_entities(joints.iter().copied());
+
+    let rest = HumanoidRestPose::from_hierarchy(source, bones.iter().copied(), &transforms)
+        .rotated(Quat::from_rotation_y(PI));
+
+    let targets = bones
+        .iter()
+        .filter_map(|(entity, bone)| {
+            let (_, target) = names.get(*entity).ok()?;
+            Some((target?.id, *bone))
+        })
+        .collect::<HashMap<_, _>>();
+
+    let retargeter = Retargeter::new(rest, rig.rest.clone());
+    Some(retargeter.clip(clip, &targets))
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::animation::{Keyframes, VariableCurve};
+
+    use super::*;
+
+    #[test]
+    fn builtin_profiles() {
+        let mixamo = BoneMappingProfile::mixamo();
+        assert_eq!(mixamo.bone_name("mixamorig:Hips"), Some(BoneName::Hips));
+        assert_eq!(
+            mixamo.bone_name("mixamorig1:Spine2"),
+            Some(BoneName::UpperChest)
+        );
+        assert_eq!(
+            mixamo.bone_name("mixamorig_LeftForeArm"),
+            Some(BoneName::LeftLowerArm)
+        );
+        assert_eq!(
+            mixamo.bone_name("mixamorigRightHandPinky3"),
+            Some(BoneName::RightLittleDistal)
+        );
+
+        let vroid = BoneMappingProfile::vroid();
+        assert_eq!(
+            vroid.bone_name("J_Bip_L_UpperArm"),
+            Some(BoneName::LeftUpperArm)
+        );
+
+        let unity = BoneMappingProfile::unity();
+        assert_eq!(
+            unity.bone_name("LeftThumbProximal"),
+            Some(BoneName::LeftThumbProximal)
+        );
+
+        let rigify = BoneMappingProfile::rigify();
+        assert_eq!(rigify.bone_name("DEF-spine"), Some(BoneName::Hips));
+        assert_eq!(
+            rigify.bone_name("DEF-forearm.R"),
+            Some(BoneName::RightLowerArm)
+        );
+        assert_eq!(
+            rigify.bone_name("DEF-f_index.02.L"),
+            Some(BoneName::LeftIndexIntermediate)
+        );
+        assert_eq!(rigify.bone_name("DEF-upper_arm.L.001"), None);
+    }
+
+    #[test]
+    fn detect_profile() {
+        let joints = [
+            "mixamorig:Hips",
+            "mixamorig:Spine",
+            "mixamorig:Spine1",
+            "mixamorig:LeftUpLeg",
+            "mixamorig:LeftLeg",
+        ];
+        assert_eq!(BoneMappingProfile::detect(joints).name, "Mixamo");
+
+        let joints = ["DEF-spine", "DEF-spine.001", "DEF-thigh.L", "DEF-shin.L"];
+        assert_eq!(BoneMappingProfile::detect(joints).name, "Rigify");
+
+        let joints = ["Bip01 Pelvis", "Bip01 L Thigh", "Bip01 L Calf"];
+        let profile = BoneMappingProfile::detect(joints);
+        assert_eq!(profile.name, "Auto");
+        assert_eq!(
+            profile.bone_name("Bip01 L Calf"),
+            Some(BoneName::LeftLowerLeg)
+        );
+    }
+
+    #[test]
+    fn fuzzy_names() {
+        assert_eq!(
+            fuzzy_bone_name("mixamorig:LeftHandIndex2"),
+            Some(BoneName::LeftIndexIntermediate)
+        );
+        assert_eq!(fuzzy_bone_name("rShldr"), Some(BoneName::RightUpperArm));
+        assert_eq!(fuzzy_bone_name("RightUpLeg"), Some(BoneName::RightUpperLeg));
+        assert_eq!(fuzzy_bone_name("Spine1"), Some(BoneName::Chest));
+        assert_eq!(
+            fuzzy_bone_name("leftUpperArm"),
+            Some(BoneName::LeftUpperArm)
+        );
+        assert_eq!(
+            fuzzy_bone_name("J_Bip_R_Little3"),
+            Some(BoneName::RightLittleDistal)
+        );
+        assert_eq!(fuzzy_bone_name("J_Adj_L_FaceEye"), Some(BoneName::LeftEye));
+        assert_eq!(fuzzy_bone_name("upper_arm.L"), Some(BoneName::LeftUpperArm));
+        assert_eq!(
+            fuzzy_bone_name("RightThumbIntermediate"),
+            Some(BoneName::RightThumbIntermediate)
+        );
+        assert_eq!(fuzzy_bone_name("LowerBack"), None);
+        assert_eq!(fuzzy_bone_name("LeftHandThumb4"), None);
+        assert_eq!(fuzzy_bone_name("Root"), None);
+    }
+
+    #[test]
+    fn retarget_gltf_scene() {
+        let mut world = World::new();
+
+        let hips_id = AnimationTargetId::from_name(&Name::new("mixamorig:Hips"));
+        let leg_id = AnimationTargetId::from_name(&Name::new("mixamorig:LeftUpLeg"));
+
+        let source = world.spawn(TransformBundle::default()).id();
+        let hips = world
+            .spawn((
+                TransformBundle::from_transform(Transform::from_xyz(0.0, 1.0, 0.0)),
+                Name::new("mixamorig:Hips"),
+                AnimationTarget {
+                    id: hips_id,
+                    player: source,
+                },
+            ))
+            .set_parent(source)
+            .id();
+        world
+            .spawn((
+                TransformBundle::from_transform(Transform::from_xyz(0.1, 0.0, 0.0)),
+                Name::new("mixamorig:LeftUpLeg"),
+                AnimationTarget {
+                    id: leg_id,
+                    player: source,
+                },
+            ))
+            .set_parent(hips);
+
+        let vrm = world
+            .spawn(HumanoidRig {
+                bones: HashMap::default(),
+                rest: HumanoidRestPose::normalized([
+                    (BoneName::Hips, Vec3::new(0.0, 1.0, 0.0)),
+                    (BoneName::LeftUpperLeg, Vec3::new(-0.1, 1.0, 0.0)),
+                ]),
+            })
+            .id();
+
+        let mut clip = AnimationClip::default();
+        clip.add_curve_to_target(
+            leg_id,
+            VariableCurve {
+                keyframe_timestamps: vec![0.0],
+                keyframes: Keyframes::Rotation(vec![Quat::from_rotation_x(0.5)]),
+                interpolation: bevy::animation::Interpolation::Linear,
+            },
+        );
+
+        let out = retarget_scene_clip(&mut world, source, vrm, &clip, None).unwrap();
+
+        let curves = out
+            .curves_for_target(VRM_ANIMATION_TARGETS[&BoneName::LeftUpperLeg])
+            .unwrap();
+        let Keyframes::Rotation(rotations) = &curves[0].keyframes else {
+            panic!("Expected rotation keyframes");
+        };
+
+        // The +Z facing clip is turned around to face -Z.
+        assert!(rotations[0].abs_diff_eq(Quat::from_rotation_x(-0.5), 1e-5));
+    }
+}
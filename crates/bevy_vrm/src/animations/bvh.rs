@@ -0,0 +1,586 @@
+//! Loader for [BVH](https://research.cs.wisc.edu/graphics/Courses/cs-838-1999/Jeff/BVH.html)
+//! motion capture files.
+//!
+//! Joints are mapped to humanoid bones by name, see [fuzzy_bone_name].
+//! The BVH rest pose is expected to be a T-pose facing +Z, with identity rotations.
+//! Clips are converted to normalized rotations facing -Z, keyed by [VRM_ANIMATION_TARGETS],
+//! so they play on VRM avatars like VRMA clips.
+
+use std::collections::BTreeMap;
+
+use bevy::{
+    animation::{Interpolation, Keyframes, VariableCurve},
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+use serde_vrm::vrm0::BoneName;
+use thiserror::Error;
+
+use crate::animations::{
+    bone_mapping::fuzzy_bone_name,
+    retarget::{to_vrm0_rotation, to_vrm0_vec, HumanoidRestPose},
+    vrm::VRM_ANIMATION_TARGETS,
+};
+
+#[derive(Asset, TypePath, Debug)]
+pub struct BvhAnimation {
+    pub clip: Handle<AnimationClip>,
+    /// Normalized rest pose of the mapped bones, converted to face -Z.
+    /// Use with a [Retargeter](super::retarget::Retargeter) to scale hips translation.
+    pub rest: HumanoidRestPose,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BvhSettings {
+    /// Scale from BVH units to meters.
+    pub scale: f32,
+    /// Joint name to bone mappings, checked before [fuzzy_bone_name].
+    pub joint_names: BTreeMap<String, BoneName>,
+}
+
+impl Default for BvhSettings {
+    fn default() -> Self {
+        Self {
+            scale: 0.01,
+            joint_names: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct BvhLoader;
+
+#[derive(Debug, Error)]
+pub enum BvhError {
+    #[error("Failed to read file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to read file as UTF-8: {0}")]
+    Utf8(#[from] std::string::FromUtf8Error),
+    #[error("Expected {expected}, found {found}")]
+    UnexpectedToken { expected: String, found: String },
+    #[error("Unexpected end of file")]
+    UnexpectedEnd,
+    #[error("Invalid number: {0}")]
+    InvalidNumber(String),
+    #[error("Unknown channel: {0}")]
+    UnknownChannel(String),
+}
+
+impl AssetLoader for BvhLoader {
+    type Asset = BvhAnimation;
+    type Settings = BvhSettings;
+    type Error = BvhError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        settings: &'a Self::Settings,
+        load_context: &'a mut LoadContext,
+    ) -> impl bevy::utils::ConditionalSendFuture<Output = Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+
+            let bvh = Bvh::parse(&String::from_utf8(bytes)?)?;
+
+            let (clip, rest) = bvh.to_clip(settings);
+            let clip = load_context.add_labeled_asset("Animation".to_string(), clip);
+
+            Ok(BvhAnimation { clip, rest })
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["bvh"]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BvhChannel {
+    Xposition,
+    Yposition,
+    Zposition,
+    Xrotation,
+    Yrotation,
+    Zrotation,
+}
+
+impl BvhChannel {
+    fn parse(name: &str) -> Result<Self, BvhError> {
+        match name {
+            "Xposition" => Ok(Self::Xposition),
+            "Yposition" => Ok(Self::Yposition),
+            "Zposition" => Ok(Self::Zposition),
+            "Xrotation" => Ok(Self::Xrotation),
+            "Yrotation" => Ok(Self::Yrotation),
+            "Zrotation" => Ok(Self::Zrotation),
+            _ => Err(BvhError::UnknownChannel(name.to_string())),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BvhJoint {
+    pub name: String,
+    pub parent: Option<usize>,
+    pub offset: Vec3,
+    pub channels: Vec<BvhChannel>,
+    /// Index of the first channel of this joint within each frame.
+    pub channel_offset: usize,
+}
+
+/// A parsed BVH file.
+#[derive(Clone, Debug, Default)]
+pub struct Bvh {
+    /// Joints in file order, parents before children. End sites are not included.
+    pub joints: Vec<BvhJoint>,
+    pub frame_time: f32,
+    pub frames: Vec<Vec<f32>>,
+}
+
+struct Tokens<'a>(std::str::SplitWhitespace<'a>);
+
+impl<'a> Tokens<'a> {
+    fn next(&mut self) -> Result<&'a str, BvhError> {
+        self.0.next().ok_or(BvhError::UnexpectedEnd)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), BvhError> {
+        let found = self.next()?;
+
+        if found != expected {
+            return Err(BvhError::UnexpectedToken {
+                expected: expected.to_string(),
+                found: found.to_string(),
+            });
+        }
+
+        Ok(())
+    }
+
+    fn number<T: std::str::FromStr>(&mut self) -> Result<T, BvhError> {
+        let token = self.next()?;
+        token
+            .parse()
+            .map_err(|_| BvhError::InvalidNumber(token.to_string()))
+    }
+
+    fn vec3(&mut self) -> Result<Vec3, BvhError> {
+        Ok(Vec3::new(self.number()?, self.number()?, self.number()?))
+    }
+}
+
+impl Bvh {
+    pub fn parse(text: &str) -> Result<Self, BvhError> {
+        let mut tokens = Tokens(text.split_whitespace());
+        let mut bvh = Self::default();
+
+        tokens.expect("HIERARCHY")?;
+        tokens.expect("ROOT")?;
+        let mut channel_count = 0;
+        bvh.parse_joint(&mut tokens, None, &mut channel_count)?;
+
+        tokens.expect("MOTION")?;
+        tokens.expect("Frames:")?;
+        let frame_count = tokens.number::<usize>()?;
+        tokens.expect("Frame")?;
+        tokens.expect("Time:")?;
+        bvh.frame_time = tokens.number()?;
+
+        for _ in 0..frame_count {
+            let frame = (0..channel_count)
+                .map(|_| tokens.number())
+                .collect::<Result<Vec<f32>, _>>()?;
+            bvh.frames.push(frame);
+        }
+
+        Ok(bvh)
+    }
+
+    fn parse_joint(
+        &mut self,
+        tokens: &mut Tokens,
+        parent: Option<usize>,
+        channel_count: &mut usize,
+    ) -> Result<(), BvhError> {
+        let name = tokens.next()?.to_string();
+        tokens.expect("{")?;
+        tokens.expect("OFFSET")?;
+        let offset = tokens.vec3()?;
+
+        tokens.expect("CHANNELS")?;
+        let channels = (0..tokens.number::<usize>()?)
+            .map(|_| BvhChannel::parse(tokens.next()?))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let index = self.joints.len();
+        self.joints.push(BvhJoint {
+            name,
+            parent,
+            offset,
+            channel_offset: *channel_count,
+            channels,
+        });
+        *channel_count += self.joints[index].channels.len();
+
+        loop {
+            match tokens.next()? {
+                "JOINT" => self.parse_joint(tokens, Some(index), channel_count)?,
+                "End" => {
+                    tokens.expect("Site")?;
+                    tokens.expect("{")?;
+                    tokens.expect("OFFSET")?;
+                    tokens.vec3()?;
+                    tokens.expect("}")?;
+                }
+                "}" => return Ok(()),
+                found => {
+                    return Err(BvhError::UnexpectedToken {
+                        expected: "JOINT, End or }".to_string(),
+                        found: found.to_string(),
+                    })
+                }
+            }
+        }
+    }
+
+    /// Local transform of a joint at a frame.
+    /// Position channels replace the joint offset, rotations are applied in channel order.
+    pub fn local_transform(&self, joint: usize, frame: usize) -> Transform {
+        let joint = &self.joints[joint];
+        let values = &self.frames[frame][joint.channel_offset..];
+
+        let mut transform = Transform::from_translation(joint.offset);
+        let mut has_position = false;
+
+        for (channel, value) in joint.channels.iter().zip(values) {
+            match channel {
+                BvhChannel::Xposition | BvhChannel::Yposition | BvhChannel::Zposition => {
+                    if !has_position {
+                        transform.translation = Vec3::ZERO;
+                        has_position = true;
+                    }
+
+                    match channel {
+                        BvhChannel::Xposition => transform.translation.x = *value,
+                        BvhChannel::Yposition => transform.translation.y = *value,
+                        _ => transform.translation.z = *value,
+                    }
+                }
+                BvhChannel::Xrotation => {
+                    transform.rotation *= Quat::from_rotation_x(value.to_radians())
+                }
+                BvhChannel::Yrotation => {
+                    transform.rotation *= Quat::from_rotation_y(value.to_radians())
+                }
+                BvhChannel::Zrotation => {
+                    transform.rotation *= Quat::from_rotation_z(value.to_radians())
+                }
+            }
+        }
+
+        transform
+    }
+
+    /// Maps each joint to a humanoid bone.
+    pub fn bone_names(&self, settings: &BvhSettings) -> Vec<Option<BoneName>> {
+        let mut used = Vec::new();
+
+        self.joints
+            .iter()
+            .map(|joint| {
+                let name = settings
+                    .joint_names
+                    .get(&joint.name)
+                    .copied()
+                    .or_else(|| fuzzy_bone_name(&joint.name))?;
+
+                // Only the first joint mapped to a bone is used.
+                if used.contains(&name) {
+                    return None;
+                }
+
+                used.push(name);
+                Some(name)
+            })
+            .collect()
+    }
+
+    /// Converts the motion to an [AnimationClip] keyed by [VRM_ANIMATION_TARGETS].
+    ///
+    /// Rotations of unmapped joints are folded into their nearest mapped descendants,
+    /// which is exact because every joint has an identity rest rotation.
+    pub fn to_clip(&self, settings: &BvhSettings) -> (AnimationClip, HumanoidRestPose) {
+        let bones = self.bone_names(settings);
+
+        // Nearest mapped ancestor of each joint.
+        let mapped_parent = |mut joint: usize| {
+            while let Some(parent) = self.joints[joint].parent {
+                if bones[parent].is_some() {
+                    return Some(parent);
+                }
+                joint = parent;
+            }
+            None
+        };
+
+        let mut positions = Vec::<Vec3>::with_capacity(self.joints.len());
+        for joint in self.joints.iter() {
+            let parent = joint.parent.map(|p| positions[p]).unwrap_or_default();
+            positions.push(parent + joint.offset * settings.scale);
+        }
+
+        // Root offsets are often zero, with the height in the position channels.
+        // Lift the rest pose so its lowest bone is on the ground.
+        let lift = match self.joints.first() {
+            Some(root) if root.offset.length_squared() < f32::EPSILON => bones
+                .iter()
+                .zip(positions.iter())
+                .filter(|(bone, _)| bone.is_some())
+                .map(|(_, p)| -p.y)
+                .fold(0.0, f32::max),
+            _ => 0.0,
+        };
+
+        let rest = HumanoidRestPose::normalized(
+            bones
+                .iter()
+                .zip(positions.iter())
+                .filter_map(|(bone, p)| Some((((*bone)?), to_vrm0_vec(*p + Vec3::Y * lift)))),
+        );
+
+        let keyframe_timestamps = (0..self.frames.len())
+            .map(|i| i as f32 * self.frame_time)
+            .collect::<Vec<_>>();
+
+        let mut clip = AnimationClip::default();
+
+        for (joint, bone) in bones.iter().enumerate() {
+            let Some(bone) = bone else {
+                continue;
+            };
+
+            let stop = mapped_parent(joint);
+
+            let mut rotations = Vec::with_capacity(self.frames.len());
+            let mut translations = Vec::with_capacity(self.frames.len());
+
+            for frame in 0..self.frames.len() {
+                let mut rotation = Quat::IDENTITY;
+                let mut current = Some(joint);
+
+                while let Some(j) = current.filter(|j| Some(*j) != stop) {
+                    rotation = self.local_transform(j, frame).rotation * rotation;
+                    current = self.joints[j].parent;
+                }
+
+                rotations.push(to_vrm0_rotation(rotation));
+
+                if *bone == BoneName::Hips {
+                    let mut global = Transform::IDENTITY;
+                    let mut current = Some(joint);
+
+                    while let Some(j) = current {
+                        global = self.local_transform(j, frame) * global;
+                        current = self.joints[j].parent;
+                    }
+
+                    translations.push(to_vrm0_vec(global.translation * settings.scale));
+                }
+            }
+            let target = VRM_ANIMATION_TARGETS[bone];
+
+            clip.add_curve_to_target(
+                target,
+                VariableCurve {
+                    keyframe_timestamps: keyframe_timestamps.clone(),
+                    keyframes: Keyframes::Rotation(rotations),
+                    interpolation: Interpolation::Linear,
+                },
+            );
+
+            if *bone == BoneName::Hips {
+                clip.add_curve_to_target(
+                    target,
+                    VariableCurve {
+                        keyframe_timestamps: keyframe_timestamps.clone(),
+                        keyframes: Keyframes::Translation(translations),
+                        interpolation: Interpolation::Linear,
+                    },
+                );
+            }
+        }
+
+        (clip, rest)
+    }
+}
+
+/// Maps common Mixamo, CMU and VRM joint names to a [BoneName].
+/// Namespaces such as `mixamorig:` are ignored, and matching is case insensitive.
+///
+/// Same as [fuzzy_bone_name].
+pub fn default_bone_name(joint: &str) -> Option<BoneName> {
+    fuzzy_bone_name(joint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BVH: &str = "
+HIERARCHY
+ROOT mixamorig:Hips
+{
+    OFFSET 0.0 0.0 0.0
+    CHANNELS 6 Xposition Yposition Zposition Zrotation Xrotation Yrotation
+    JOINT LowerBack
+    {
+        OFFSET 0.0 10.0 0.0
+        CHANNELS 3 Zrotation Xrotation Yrotation
+        JOINT Spine
+        {
+            OFFSET 0.0 10.0 0.0
+            CHANNELS 3 Zrotation Xrotation Yrotation
+            End Site
+            {
+                OFFSET 0.0 10.0 0.0
+            }
+        }
+    }
+    JOINT LeftUpLeg
+    {
+        OFFSET 10.0 0.0 0.0
+        CHANNELS 3 Zrotation Xrotation Yrotation
+        JOINT LeftLeg
+        {
+            OFFSET 0.0 -45.0 0.0
+            CHANNELS 3 Zrotation Xrotation Yrotation
+            JOINT LeftFoot
+            {
+                OFFSET 0.0 -45.0 0.0
+                CHANNELS 3 Zrotation Xrotation Yrotation
+                End Site
+                {
+                    OFFSET 0.0 0.0 10.0
+                }
+            }
+        }
+    }
+}
+MOTION
+Frames: 2
+Frame Time: 0.5
+0 90 0 0 0 0 0 90 0 0 0 0 0 0 0 0 0 0 0 0 0
+10 80 0 0 0 0 0 90 0 0 0 0 0 0 0 0 0 0 0 0 0
+";
+
+    #[test]
+    fn parse() {
+        let bvh = Bvh::parse(BVH).unwrap();
+
+        assert_eq!(bvh.joints.len(), 6);
+        assert_eq!(bvh.joints[2].name, "Spine");
+        assert_eq!(bvh.joints[2].parent, Some(1));
+        assert_eq!(bvh.joints[3].channel_offset, 12);
+        assert_eq!(bvh.frames.len(), 2);
+        assert_eq!(bvh.frame_time, 0.5);
+
+        let hips = bvh.local_transform(0, 1);
+        assert_eq!(hips.translation, Vec3::new(10.0, 80.0, 0.0));
+
+        let lower_back = bvh.local_transform(1, 0);
+        assert!(lower_back
+            .rotation
+            .abs_diff_eq(Quat::from_rotation_x(90f32.to_radians()), 1e-5));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(matches!(
+            Bvh::parse("HIERARCHY ROOT Hips {"),
+            Err(BvhError::UnexpectedEnd)
+        ));
+        assert!(matches!(
+            Bvh::parse("HIERARCHY ROOT Hips { OFFSET 0 0 0 CHANNELS 1 Wrotation }"),
+            Err(BvhError::UnknownChannel(_))
+        ));
+    }
+
+    #[test]
+    fn to_clip() {
+        let bvh = Bvh::parse(BVH).unwrap();
+        let (clip, rest) = bvh.to_clip(&BvhSettings::default());
+
+        assert_eq!(
+            bvh.bone_names(&BvhSettings::default()),
+            vec![
+                Some(BoneName::Hips),
+                None,
+                Some(BoneName::Spine),
+                Some(BoneName::LeftUpperLeg),
+                Some(BoneName::LeftLowerLeg),
+                Some(BoneName::LeftFoot),
+            ]
+        );
+
+        // Zero root offset is lifted so the feet are on the ground.
+        assert!((rest.bones[&BoneName::Hips].global.translation.y - 0.9).abs() < 1e-5);
+        // Facing -Z mirrors X.
+        assert!((rest.bones[&BoneName::LeftUpperLeg].global.translation.x + 0.1).abs() < 1e-5);
+
+        // The unmapped LowerBack rotation is folded into the spine.
+        let curves = clip
+            .curves_for_target(VRM_ANIMATION_TARGETS[&BoneName::Spine])
+            .unwrap();
+        let Keyframes::Rotation(rotations) = &curves[0].keyframes else {
+            panic!("Expected rotation keyframes");
+        };
+        let expected = to_vrm0_rotation(Quat::from_rotation_x(90f32.to_radians()));
+        assert!(rotations[0].abs_diff_eq(expected, 1e-5));
+
+        let curves = clip
+            .curves_for_target(VRM_ANIMATION_TARGETS[&BoneName::Hips])
+            .unwrap();
+        assert_eq!(curves.len(), 2);
+        assert_eq!(curves[1].keyframe_timestamps, vec![0.0, 0.5]);
+        let Keyframes::Translation(translations) = &curves[1].keyframes else {
+            panic!("Expected translation keyframes");
+        };
+        assert!(translations[1].abs_diff_eq(Vec3::new(-0.1, 0.8, 0.0), 1e-5));
+    }
+
+    #[test]
+    fn custom_joint_names() {
+        let bvh = Bvh::parse(BVH).unwrap();
+
+        let mut settings = BvhSettings::default();
+        settings
+            .joint_names
+            .insert("LowerBack".to_string(), BoneName::Spine);
+
+        let bones = bvh.bone_names(&settings);
+        assert_eq!(bones[1], Some(BoneName::Spine));
+        assert_eq!(bones[2], None);
+    }
+
+    #[test]
+    fn default_names() {
+        assert_eq!(
+            default_bone_name("mixamorig:LeftHandIndex2"),
+            Some(BoneName::LeftIndexIntermediate)
+        );
+        assert_eq!(default_bone_name("rShldr"), Some(BoneName::RightUpperArm));
+        assert_eq!(
+            default_bone_name("RightUpLeg"),
+            Some(BoneName::RightUpperLeg)
+        );
+        assert_eq!(default_bone_name("Spine1"), Some(BoneName::Chest));
+        assert_eq!(
+            default_bone_name("leftUpperArm"),
+            Some(BoneName::LeftUpperArm)
+        );
+        assert_eq!(default_bone_name("LowerBack"), None);
+        assert_eq!(default_bone_name("Root"), None);
+    }
+}
@@ -0,0 +1,165 @@
+use std::sync::LazyLock;
+
+#[cfg(feature = "vrm0")]
+use bevy::animation::AnimationTarget;
+use bevy::{
+    animation::{AnimationTargetId, Interpolation, Keyframes, VariableCurve},
+    prelude::*,
+    utils::HashMap,
+};
+
+use crate::{
+    animations::target_chain::TargetChain,
+    expressions::{ExpressionName, PresetName, VrmExpressions},
+};
+
+const PRESETS: [PresetName; 17] = [
+    PresetName::Neutral,
+    PresetName::A,
+    PresetName::I,
+    PresetName::U,
+    PresetName::E,
+    PresetName::O,
+    PresetName::Blink,
+    PresetName::Joy,
+    PresetName::Angry,
+    PresetName::Sorrow,
+    PresetName::Fun,
+    PresetName::LookUp,
+    PresetName::LookDown,
+    PresetName::LookLeft,
+    PresetName::LookRight,
+    PresetName::BlinkLeft,
+    PresetName::BlinkRight,
+];
+
+pub static VRM_EXPRESSION_TARGETS: LazyLock<HashMap<PresetName, AnimationTargetId>> =
+    LazyLock::new(|| {
+        PRESETS
+            .into_iter()
+            .map(|preset| (preset, expression_target_id(&preset.into())))
+            .collect()
+    });
+
+/// Get the [AnimationTargetId] for an expression.
+/// Custom expressions do not collide with presets of the same name.
+pub fn expression_target_id(name: &ExpressionName) -> AnimationTargetId {
+    let mut chain = TargetChain::default();
+    chain.push_target("expressions".to_string());
+
+    match name {
+        ExpressionName::Preset(preset) => {
+            chain.push_target("preset".to_string());
+            chain.push_target(preset.to_string())
+        }
+        ExpressionName::Custom(name) => {
+            chain.push_target("custom".to_string());
+            chain.push_target(name.clone())
+        }
+    }
+}
+
+/// Creates a curve animating the weight of an expression.
+///
+/// For [Interpolation::CubicSpline], each keyframe is `[in_tangent, weight, out_tangent]`.
+pub fn expression_curve(
+    keyframe_timestamps: Vec<f32>,
+    weights: Vec<f32>,
+    interpolation: Interpolation,
+) -> VariableCurve {
+    VariableCurve {
+        keyframe_timestamps,
+        keyframes: Keyframes::Weights(weights),
+        interpolation,
+    }
+}
+
+/// Adds an expression curve to an [AnimationClip].
+pub fn add_expression_curve(
+    clip: &mut AnimationClip,
+    name: impl Into<ExpressionName>,
+    curve: VariableCurve,
+) {
+    clip.add_curve_to_target(expression_target_id(&name.into()), curve);
+}
+
+/// Animation target for a single expression.
+///
+/// Bevy can only animate transforms and morph weights, so each expression gets
+/// a child entity of the VRM root with a single [MorphWeights] value.
+/// The animated value is copied to [VrmExpressions].
+#[derive(Component, Reflect)]
+#[reflect(Component)]
+pub struct ExpressionTarget(pub ExpressionName);
+
+/// Spawns an [ExpressionTarget] for each expression of the VRM scene root.
+#[cfg(feature = "vrm0")]
+pub(crate) fn spawn_expression_targets(world: &mut World) {
+    let mut roots = world.query_filtered::<(Entity, &VrmExpressions), Without<Parent>>();
+
+    let Ok((root, expressions)) = roots.get_single(world) else {
+        return;
+    };
+
+    let names = expressions
+        .0
+        .iter()
+        .map(|e| e.name.clone())
+        .collect::<Vec<_>>();
+
+    if world.get::<AnimationPlayer>(root).is_none() {
+        world.entity_mut(root).insert(AnimationPlayer::default());
+    }
+
+    for name in names {
+        let target = world
+            .spawn((
+                Name::new(format!("Expression {}", name)),
+                AnimationTarget {
+                    id: expression_target_id(&name),
+                    player: root,
+                },
+                MorphWeights::new(vec![0.0], None).unwrap(),
+                ExpressionTarget(name),
+            ))
+            .id();
+
+        world.entity_mut(root).add_child(target);
+    }
+}
+
+/// Copies animated [ExpressionTarget] weights to [VrmExpressions].
+pub(crate) fn apply_expression_targets(
+    targets: Query<(&ExpressionTarget, &MorphWeights, &Parent), Changed<MorphWeights>>,
+    mut expressions: Query<&mut VrmExpressions>,
+) {
+    for (target, weights, parent) in targets.iter() {
+        let Ok(mut expressions) = expressions.get_mut(parent.get()) else {
+            continue;
+        };
+
+        let weight = weights.weights().first().copied().unwrap_or_default();
+        expressions.set_weight(target.0.clone(), weight);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preset_targets_are_unique() {
+        let mut ids = VRM_EXPRESSION_TARGETS.values().collect::<Vec<_>>();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), PRESETS.len());
+    }
+
+    #[test]
+    fn custom_targets_do_not_collide_with_presets() {
+        let preset = expression_target_id(&PresetName::Joy.into());
+        let custom = expression_target_id(&ExpressionName::Custom("joy".to_string()));
+        assert_ne!(preset, custom);
+        assert_eq!(VRM_EXPRESSION_TARGETS[&PresetName::Joy], preset);
+    }
+}
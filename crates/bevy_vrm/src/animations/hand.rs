@@ -0,0 +1,432 @@
+//! Hand poses for VRM humanoids.
+//!
+//! Fingers are posed with a curl and splay value, which are converted to normalized
+//! rotations around axes taken from the rest pose of each finger. This keeps poses
+//! consistent between avatars, regardless of how their finger bones are rolled.
+
+use bevy::{prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
+use serde_vrm::vrm0::BoneName;
+
+use crate::animations::{
+    bone_mapping::{LEFT_FINGERS, RIGHT_FINGERS},
+    humanoid::HumanoidRig,
+    retarget::HumanoidRestPose,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Reflect, Serialize, Deserialize)]
+pub enum Finger {
+    Thumb,
+    Index,
+    Middle,
+    Ring,
+    Little,
+}
+
+impl Finger {
+    pub const ALL: [Finger; 5] = [
+        Finger::Thumb,
+        Finger::Index,
+        Finger::Middle,
+        Finger::Ring,
+        Finger::Little,
+    ];
+
+    /// Proximal, intermediate and distal bones of the finger.
+    pub fn bones(&self, left: bool) -> [BoneName; 3] {
+        let fingers = if left { LEFT_FINGERS } else { RIGHT_FINGERS };
+        fingers[*self as usize]
+    }
+
+    /// Maximum curl of each bone, in radians.
+    fn max_curl(&self) -> [f32; 3] {
+        let degrees = match self {
+            Finger::Thumb => [30.0, 40.0, 60.0],
+            _ => [90.0, 100.0, 70.0],
+        };
+        degrees.map(f32::to_radians)
+    }
+
+    /// Maximum splay of the proximal bone, in radians.
+    fn max_splay(&self) -> f32 {
+        match self {
+            Finger::Thumb => 30f32.to_radians(),
+            _ => 15f32.to_radians(),
+        }
+    }
+
+    /// Direction a positive splay moves the finger, towards the index side or the little side.
+    fn splay_side(&self) -> f32 {
+        match self {
+            Finger::Thumb | Finger::Index => 1.0,
+            Finger::Middle => 0.5,
+            Finger::Ring | Finger::Little => -1.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Reflect, Serialize, Deserialize)]
+pub struct FingerPose {
+    /// Bend towards the palm, from 0 (straight) to 1 (fully bent).
+    pub curl: f32,
+    /// Spread away from the middle finger, from -1 to 1.
+    pub splay: f32,
+}
+
+impl FingerPose {
+    pub fn new(curl: f32, splay: f32) -> Self {
+        Self { curl, splay }
+    }
+
+    pub fn lerp(self, other: Self, t: f32) -> Self {
+        Self {
+            curl: self.curl + (other.curl - self.curl) * t,
+            splay: self.splay + (other.splay - self.splay) * t,
+        }
+    }
+}
+
+/// Pose of the fingers of one hand, ordered thumb, index, middle, ring, little.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Reflect, Serialize, Deserialize)]
+pub struct HandPose {
+    pub fingers: [FingerPose; 5],
+}
+
+impl HandPose {
+    /// Straight fingers, slightly spread.
+    pub fn open() -> Self {
+        Self {
+            fingers: [FingerPose::new(0.0, 0.3); 5],
+        }
+    }
+
+    pub fn fist() -> Self {
+        let mut pose = Self {
+            fingers: [FingerPose::new(1.0, 0.0); 5],
+        };
+        pose.fingers[Finger::Thumb as usize] = FingerPose::new(0.7, -0.3);
+        pose
+    }
+
+    pub fn point() -> Self {
+        Self::fist().with_finger(Finger::Index, FingerPose::default())
+    }
+
+    pub fn thumbs_up() -> Self {
+        Self::fist().with_finger(Finger::Thumb, FingerPose::new(0.0, 0.3))
+    }
+
+    pub fn finger(&self, finger: Finger) -> FingerPose {
+        self.fingers[finger as usize]
+    }
+
+    pub fn set_finger(&mut self, finger: Finger, pose: FingerPose) {
+        self.fingers[finger as usize] = pose;
+    }
+
+    pub fn with_finger(mut self, finger: Finger, pose: FingerPose) -> Self {
+        self.set_finger(finger, pose);
+        self
+    }
+
+    pub fn lerp(self, other: Self, t: f32) -> Self {
+        let mut out = self;
+
+        for (a, b) in out.fingers.iter_mut().zip(other.fingers) {
+            *a = a.lerp(b, t);
+        }
+
+        out
+    }
+
+    /// Normalized rotations of the finger bones of one hand.
+    pub fn rotations(&self, left: bool, rest: &HumanoidRestPose) -> HashMap<BoneName, Quat> {
+        let mut rotations = HashMap::default();
+
+        let position = |bone| rest.bones.get(&bone).map(|b| b.global.translation);
+
+        let hand = if left {
+            BoneName::LeftHand
+        } else {
+            BoneName::RightHand
+        };
+        let [_, index, middle, _, little] = Finger::ALL.map(|f| position(f.bones(left)[0]));
+
+        // VRM avatars are in a T-pose, with palms facing down.
+        let side = index
+            .zip(little)
+            .and_then(|(index, little)| (index - little).try_normalize())
+            .unwrap_or(Vec3::NEG_Z);
+        let forward = position(hand)
+            .zip(middle)
+            .and_then(|(hand, middle)| (middle - hand).try_normalize())
+            .unwrap_or(if left { Vec3::X } else { Vec3::NEG_X });
+        let palm = if left {
+            side.cross(forward)
+        } else {
+            forward.cross(side)
+        }
+        .try_normalize()
+        .unwrap_or(Vec3::NEG_Y);
+
+        for finger in Finger::ALL {
+            let pose = self.finger(finger);
+            let bones = finger.bones(left);
+            let positions = bones.map(position);
+
+            // The thumb curls across the palm, towards the little finger.
+            let bend = match finger {
+                Finger::Thumb => (palm - side).normalize(),
+                _ => palm,
+            };
+
+            for (i, bone) in bones.into_iter().enumerate() {
+                // Direction along the bone, the distal bone continues its parent.
+                let dir = match (positions[i], positions.get(i + 1).copied().flatten()) {
+                    (Some(start), Some(end)) => end - start,
+                    _ => positions[i]
+                        .zip(i.checked_sub(1).and_then(|p| positions[p]))
+                        .map(|(end, start)| end - start)
+                        .unwrap_or(forward),
+                };
+                let dir = dir.try_normalize().unwrap_or(forward);
+
+                let curl_axis = dir.cross(bend).try_normalize().unwrap_or(Vec3::Z);
+                let mut rotation =
+                    Quat::from_axis_angle(curl_axis, pose.curl * finger.max_curl()[i]);
+
+                if i == 0 {
+                    let spread = side * finger.splay_side();
+                    if let Some(splay_axis) = dir.cross(spread).try_normalize() {
+                        rotation = Quat::from_axis_angle(
+                            splay_axis,
+                            pose.splay * finger.max_splay() * finger.splay_side().abs(),
+                        ) * rotation;
+                    }
+                }
+
+                rotations.insert(bone, rotation);
+            }
+        }
+
+        rotations
+    }
+}
+
+/// Hand poses of a VRM avatar, blended over the animated pose.
+/// Fingers that nothing animates are blended from their rest pose each frame.
+/// Insert on the root entity of a VRM scene with a [HumanoidRig].
+#[derive(Component, Clone, Debug, Reflect)]
+#[reflect(Component)]
+pub struct HandPoses {
+    pub left: Option<HandPose>,
+    pub right: Option<HandPose>,
+    pub left_weight: f32,
+    pub right_weight: f32,
+}
+
+impl Default for HandPoses {
+    fn default() -> Self {
+        Self {
+            left: None,
+            right: None,
+            left_weight: 1.0,
+            right_weight: 1.0,
+        }
+    }
+}
+
+impl HandPoses {
+    pub fn both(pose: HandPose) -> Self {
+        Self {
+            left: Some(pose),
+            right: Some(pose),
+            ..default()
+        }
+    }
+}
+
+/// Blends [HandPoses] into the finger bones.
+/// Finger transforms written by [HandPoses] last frame, with the transforms they replaced.
+/// Inserted on the root entity of a VRM scene.
+#[derive(Component, Default)]
+pub(crate) struct HandsWritten(HashMap<Entity, (Transform, Transform)>);
+
+pub(crate) fn apply_hand_poses(
+    mut commands: Commands,
+    mut written: Query<&mut HandsWritten>,
+    avatars: Query<(Entity, &HumanoidRig, &HandPoses)>,
+    mut transforms: Query<&mut Transform>,
+) {
+    // Undo last frame's poses on fingers that were not animated since, so poses blend
+    // with the animated pose instead of their own result.
+    for mut written in written.iter_mut() {
+        for (entity, (written, original)) in written.0.drain() {
+            if let Ok(mut transform) = transforms.get_mut(entity) {
+                if *transform == written {
+                    *transform = original;
+                }
+            }
+        }
+    }
+
+    for (root, rig, hands) in avatars.iter() {
+        let mut changed = HashMap::default();
+
+        for (left, pose, weight) in [
+            (true, hands.left, hands.left_weight),
+            (false, hands.right, hands.right_weight),
+        ] {
+            let Some(pose) = pose else {
+                continue;
+            };
+
+            if weight <= 0.0 {
+                continue;
+            }
+
+            for (bone, normalized) in pose.rotations(left, &rig.rest) {
+                let Some(entity) = rig.bones.get(&bone) else {
+                    continue;
+                };
+
+                let Some(rotation) = rig.rest.denormalize_rotation(bone, normalized) else {
+                    continue;
+                };
+
+                if let Ok(mut transform) = transforms.get_mut(*entity) {
+                    let original = *transform;
+                    transform.rotation = original.rotation.slerp(rotation, weight);
+                    changed.insert(*entity, (*transform, original));
+                }
+            }
+        }
+
+        match written.get_mut(root) {
+            Ok(mut written) => written.0 = changed,
+            Err(_) if !changed.is_empty() => {
+                commands.entity(root).insert(HandsWritten(changed));
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::animations::humanoid::test_rig::spawn_flat_rig;
+
+    /// Left hand in a T-pose, pointing +X with the index finger towards -Z.
+    fn left_hand() -> HumanoidRestPose {
+        let mut positions = vec![(BoneName::LeftHand, Vec3::new(0.5, 1.4, 0.0))];
+
+        for (finger, z) in Finger::ALL
+            .into_iter()
+            .zip([-0.04, -0.03, -0.01, 0.01, 0.03])
+        {
+            let x = if finger == Finger::Thumb { 0.52 } else { 0.58 };
+
+            for (i, bone) in finger.bones(true).into_iter().enumerate() {
+                positions.push((bone, Vec3::new(x + i as f32 * 0.03, 1.4, z)));
+            }
+        }
+
+        HumanoidRestPose::normalized(positions)
+    }
+
+    /// Model space direction of the distal bone of a finger.
+    fn tip_direction(rotations: &HashMap<BoneName, Quat>, finger: Finger) -> Vec3 {
+        finger
+            .bones(true)
+            .iter()
+            .map(|bone| rotations[bone])
+            .fold(Quat::IDENTITY, |acc, r| acc * r)
+            * Vec3::X
+    }
+
+    #[test]
+    fn presets_curl_towards_palm() {
+        let rest = left_hand();
+
+        let open = HandPose::default().rotations(true, &rest);
+        assert!(tip_direction(&open, Finger::Index).abs_diff_eq(Vec3::X, 1e-5));
+
+        let fist = HandPose::fist().rotations(true, &rest);
+        let index = tip_direction(&fist, Finger::Index);
+        // Curled past the palm, back towards the wrist.
+        assert!(index.x < 0.0);
+
+        let point = HandPose::point().rotations(true, &rest);
+        assert!(tip_direction(&point, Finger::Index).abs_diff_eq(Vec3::X, 1e-5));
+        assert!(tip_direction(&point, Finger::Middle).x < 0.0);
+
+        // Splay spreads the index finger towards the thumb side.
+        let splay = HandPose::default()
+            .with_finger(Finger::Index, FingerPose::new(0.0, 1.0))
+            .with_finger(Finger::Little, FingerPose::new(0.0, 1.0))
+            .rotations(true, &rest);
+        assert!(tip_direction(&splay, Finger::Index).z < 0.0);
+        assert!(tip_direction(&splay, Finger::Little).z > 0.0);
+    }
+
+    #[test]
+    fn blend_with_animation() {
+        let mut world = World::new();
+        let rest = left_hand();
+
+        let (root, bones) = spawn_flat_rig(&mut world, rest);
+        let proximal = bones[&BoneName::LeftIndexProximal];
+
+        let animated = Quat::from_rotation_y(0.3);
+        world.get_mut::<Transform>(proximal).unwrap().rotation = animated;
+
+        world.entity_mut(root).insert(HandPoses {
+            left: Some(HandPose::fist()),
+            left_weight: 0.0,
+            ..default()
+        });
+
+        world.run_system_once(apply_hand_poses);
+        assert_eq!(world.get::<Transform>(proximal).unwrap().rotation, animated);
+
+        world.get_mut::<HandPoses>(root).unwrap().left_weight = 0.5;
+        world.run_system_once(apply_hand_poses);
+
+        let rotation = world.get::<Transform>(proximal).unwrap().rotation;
+        let fist = HandPose::fist().rotations(true, &left_hand())[&BoneName::LeftIndexProximal];
+        assert!(rotation.abs_diff_eq(animated.slerp(fist, 0.5), 1e-5));
+    }
+
+    #[test]
+    fn blend_without_animation() {
+        let mut world = World::new();
+        let (root, bones) = spawn_flat_rig(&mut world, left_hand());
+        let proximal = bones[&BoneName::LeftIndexProximal];
+
+        world.entity_mut(root).insert(HandPoses {
+            left: Some(HandPose::fist()),
+            left_weight: 0.5,
+            ..default()
+        });
+
+        let fist = HandPose::fist().rotations(true, &left_hand())[&BoneName::LeftIndexProximal];
+        let half = Quat::IDENTITY.slerp(fist, 0.5);
+
+        // No clip resets the fingers, so each frame must blend from the rest pose.
+        for _ in 0..2 {
+            world.run_system_once(apply_hand_poses);
+            let rotation = world.get::<Transform>(proximal).unwrap().rotation;
+            assert!(rotation.abs_diff_eq(half, 1e-5));
+        }
+
+        world.entity_mut(root).remove::<HandPoses>();
+        world.run_system_once(apply_hand_poses);
+        assert_eq!(
+            world.get::<Transform>(proximal).unwrap().rotation,
+            Quat::IDENTITY
+        );
+    }
+}
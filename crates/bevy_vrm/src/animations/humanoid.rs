@@ -0,0 +1,461 @@
+//! Normalized humanoid pose of a VRM avatar.
+//!
+//! Like the VRM 1.0 normalized humanoid, every bone of the pose has an identity rest rotation,
+//! so the same pose can be applied to avatars with different bone rolls.
+
+use bevy::{
+    ecs::{
+        entity::MapEntities,
+        reflect::ReflectMapEntities,
+        system::{RunSystemOnce, SystemState},
+    },
+    prelude::*,
+    utils::HashMap,
+};
+use serde_vrm::vrm0::BoneName;
+use thiserror::Error;
+
+use crate::animations::{
+    bone_mapping::{LEFT_FINGERS, RIGHT_FINGERS},
+    retarget::HumanoidRestPose,
+};
+
+/// Humanoid bones of a VRM avatar and their rest pose.
+/// Inserted on the root entity of the VRM scene.
+#[derive(Component, Default, Reflect)]
+#[reflect(Component, MapEntities)]
+pub struct HumanoidRig {
+    pub bones: HashMap<BoneName, Entity>,
+    pub rest: HumanoidRestPose,
+}
+
+impl MapEntities for HumanoidRig {
+    fn map_entities<M: EntityMapper>(&mut self, entity_mapper: &mut M) {
+        for entity in self.bones.values_mut() {
+            *entity = entity_mapper.map_entity(*entity);
+        }
+    }
+}
+
+impl HumanoidRig {
+    /// Reads the normalized local rotation of a bone from its current [Transform].
+    pub fn normalized_rotation(
+        &self,
+        bone: BoneName,
+        transforms: &Query<&Transform>,
+    ) -> Option<Quat> {
+        let entity = self.bones.get(&bone)?;
+        let transform = transforms.get(*entity).ok()?;
+        self.rest.normalize_rotation(bone, transform.rotation)
+    }
+
+    /// Reads the current pose of every bone.
+    pub fn read_pose(&self, transforms: &Query<&Transform>) -> HumanoidPose {
+        let mut pose = HumanoidPose::default();
+
+        for bone in self.bones.keys() {
+            if let Some(rotation) = self.normalized_rotation(*bone, transforms) {
+                pose.rotations.insert(*bone, rotation);
+            }
+        }
+
+        pose.hips_translation = self
+            .bones
+            .get(&BoneName::Hips)
+            .zip(self.rest.bones.get(&BoneName::Hips))
+            .and_then(|(entity, rest)| {
+                let transform = transforms.get(*entity).ok()?;
+                Some(rest.parent_global().transform_point3(transform.translation))
+            });
+
+        pose
+    }
+
+    /// Writes a pose to the bones.
+    /// Bones without a rotation in the pose are left unchanged.
+    pub fn write_pose(&self, pose: &HumanoidPose, transforms: &mut Query<&mut Transform>) {
+        for (bone, normalized) in pose.rotations.iter() {
+            let Some(entity) = self.bones.get(bone) else {
+                continue;
+            };
+
+            let Some(rotation) = self.rest.denormalize_rotation(*bone, *normalized) else {
+                continue;
+            };
+
+            if let Ok(mut transform) = transforms.get_mut(*entity) {
+                transform.rotation = rotation;
+            }
+        }
+
+        let Some(translation) = pose.hips_translation else {
+            return;
+        };
+
+        let (Some(entity), Some(rest)) = (
+            self.bones.get(&BoneName::Hips),
+            self.rest.bones.get(&BoneName::Hips),
+        ) else {
+            return;
+        };
+
+        if let Ok(mut transform) = transforms.get_mut(*entity) {
+            transform.translation = rest.parent_global().inverse().transform_point3(translation);
+        }
+    }
+}
+
+/// Normalized pose of a VRM avatar.
+///
+/// Insert on the root entity of a VRM scene to pose the avatar.
+/// Each frame, after animations are evaluated, the rotations are converted to local
+/// rotations and written to the bones. Bones without a rotation are left unchanged.
+#[derive(Component, Clone, Debug, Default, Reflect)]
+#[reflect(Component)]
+pub struct HumanoidPose {
+    /// Normalized local rotation of each bone.
+    pub rotations: HashMap<BoneName, Quat>,
+    /// Position of the hips in model space.
+    pub hips_translation: Option<Vec3>,
+}
+
+impl HumanoidPose {
+    pub fn rotation(&self, bone: BoneName) -> Option<Quat> {
+        self.rotations.get(&bone).copied()
+    }
+
+    pub fn set_rotation(&mut self, bone: BoneName, rotation: Quat) {
+        self.rotations.insert(bone, rotation);
+    }
+
+    /// Stops overriding the rotation of a bone.
+    pub fn clear_rotation(&mut self, bone: BoneName) {
+        self.rotations.remove(&bone);
+    }
+}
+
+/// Bones every VRM 1.0 humanoid must have.
+pub const REQUIRED_BONES: [BoneName; 15] = [
+    BoneName::Hips,
+    BoneName::Spine,
+    BoneName::Head,
+    BoneName::LeftUpperLeg,
+    BoneName::LeftLowerLeg,
+    BoneName::LeftFoot,
+    BoneName::RightUpperLeg,
+    BoneName::RightLowerLeg,
+    BoneName::RightFoot,
+    BoneName::LeftUpperArm,
+    BoneName::LeftLowerArm,
+    BoneName::LeftHand,
+    BoneName::RightUpperArm,
+    BoneName::RightLowerArm,
+    BoneName::RightHand,
+];
+
+pub fn is_required_bone(bone: BoneName) -> bool {
+    REQUIRED_BONES.contains(&bone)
+}
+
+/// Parent of a bone in the VRM humanoid hierarchy.
+/// Optional bones, such as `UpperChest`, may be missing from an avatar.
+pub fn humanoid_parent(bone: BoneName) -> Option<BoneName> {
+    let parent = match bone {
+        BoneName::Hips => return None,
+        BoneName::Spine | BoneName::LeftUpperLeg | BoneName::RightUpperLeg => BoneName::Hips,
+        BoneName::Chest => BoneName::Spine,
+        BoneName::UpperChest => BoneName::Chest,
+        BoneName::Neck | BoneName::LeftShoulder | BoneName::RightShoulder => BoneName::UpperChest,
+        BoneName::Head => BoneName::Neck,
+        BoneName::LeftEye | BoneName::RightEye | BoneName::Jaw => BoneName::Head,
+        BoneName::LeftUpperArm => BoneName::LeftShoulder,
+        BoneName::LeftLowerArm => BoneName::LeftUpperArm,
+        BoneName::LeftHand => BoneName::LeftLowerArm,
+        BoneName::RightUpperArm => BoneName::RightShoulder,
+        BoneName::RightLowerArm => BoneName::RightUpperArm,
+        BoneName::RightHand => BoneName::RightLowerArm,
+        BoneName::LeftLowerLeg => BoneName::LeftUpperLeg,
+        BoneName::LeftFoot => BoneName::LeftLowerLeg,
+        BoneName::LeftToes => BoneName::LeftFoot,
+        BoneName::RightLowerLeg => BoneName::RightUpperLeg,
+        BoneName::RightFoot => BoneName::RightLowerLeg,
+        BoneName::RightToes => BoneName::RightFoot,
+        _ => {
+            let (hand, fingers) = if LEFT_FINGERS.iter().flatten().any(|b| *b == bone) {
+                (BoneName::LeftHand, LEFT_FINGERS)
+            } else {
+                (BoneName::RightHand, RIGHT_FINGERS)
+            };
+
+            fingers
+                .iter()
+                .find_map(|finger| {
+                    let i = finger.iter().position(|b| *b == bone)?;
+                    Some(i.checked_sub(1).map(|p| finger[p]).unwrap_or(hand))
+                })
+                .unwrap_or(hand)
+        }
+    };
+
+    Some(parent)
+}
+
+/// Nearest ancestor of a bone in the VRM humanoid hierarchy that passes `has_bone`.
+pub fn humanoid_ancestor(bone: BoneName, has_bone: impl Fn(BoneName) -> bool) -> Option<BoneName> {
+    let mut parent = humanoid_parent(bone);
+
+    while let Some(p) = parent {
+        if has_bone(p) {
+            return Some(p);
+        }
+
+        parent = humanoid_parent(p);
+    }
+
+    None
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum HumanoidError {
+    #[error("Missing required bone {0}")]
+    MissingBone(BoneName),
+    #[error("Bone {bone} is a child of {found:?}, expected {expected:?}")]
+    InvalidParent {
+        bone: BoneName,
+        expected: Option<BoneName>,
+        found: Option<BoneName>,
+    },
+}
+
+/// Nearest ancestor of an entity that is a humanoid bone.
+fn parent_bone(
+    entity: Entity,
+    parents: &Query<&Parent>,
+    bones: &Query<&BoneName>,
+) -> Option<BoneName> {
+    parents
+        .iter_ancestors(entity)
+        .find_map(|ancestor| bones.get(ancestor).ok().copied())
+}
+
+/// Checks the humanoid of an avatar for missing required bones, and for bones
+/// that are not placed under their parent in the VRM humanoid hierarchy.
+/// Skipped optional bones are allowed, a bone may be a child of any of their ancestors.
+pub fn validate_humanoid(world: &mut World, root: Entity) -> Vec<HumanoidError> {
+    world.run_system_once_with(
+        root,
+        |In(root): In<Entity>,
+         rigs: Query<&HumanoidRig>,
+         parents: Query<&Parent>,
+         bones: Query<&BoneName>| {
+            let Ok(rig) = rigs.get(root) else {
+                return Vec::new();
+            };
+
+            let mut errors = REQUIRED_BONES
+                .into_iter()
+                .filter(|bone| !rig.bones.contains_key(bone))
+                .map(HumanoidError::MissingBone)
+                .collect::<Vec<_>>();
+
+            let mut present = rig.bones.iter().collect::<Vec<_>>();
+            present.sort_by_key(|(bone, _)| **bone as u8);
+
+            for (bone, entity) in present {
+                let expected = humanoid_ancestor(*bone, |b| rig.bones.contains_key(&b));
+                let found = parent_bone(*entity, &parents, &bones);
+
+                if expected != found {
+                    errors.push(HumanoidError::InvalidParent {
+                        bone: *bone,
+                        expected,
+                        found,
+                    });
+                }
+            }
+
+            errors
+        },
+    )
+}
+
+/// Inserts a [HumanoidRig] on the root of the VRM scene.
+/// Must run while the bones are in their rest pose.
+#[cfg_attr(not(feature = "vrm0"), allow(dead_code))]
+pub(crate) fn build_humanoid_rig(world: &mut World) {
+    let mut roots = world.query_filtered::<Entity, Without<Parent>>();
+    let Ok(root) = roots.get_single(world) else {
+        return;
+    };
+
+    let mut state = SystemState::<(
+        Query<(Entity, &BoneName)>,
+        Query<(&Transform, Option<&Parent>)>,
+    )>::new(world);
+    let (bones, transforms) = state.get(world);
+
+    let rest =
+        HumanoidRestPose::from_hierarchy(root, bones.iter().map(|(e, n)| (e, *n)), &transforms);
+    let bones = bones.iter().map(|(e, name)| (*name, e)).collect();
+
+    world.entity_mut(root).insert(HumanoidRig { bones, rest });
+}
+
+/// Writes [HumanoidPose] rotations to the bones of the avatar.
+pub(crate) fn apply_humanoid_pose(
+    rigs: Query<(&HumanoidRig, &HumanoidPose)>,
+    mut transforms: Query<&mut Transform>,
+) {
+    for (rig, pose) in rigs.iter() {
+        rig.write_pose(pose, &mut transforms);
+    }
+}
+
+/// Writes a pose to the bones of an avatar, for use outside of systems.
+pub fn write_humanoid_pose(world: &mut World, root: Entity, pose: HumanoidPose) {
+    world.run_system_once_with(
+        (root, pose),
+        |In((root, pose)): In<(Entity, HumanoidPose)>,
+         rigs: Query<&HumanoidRig>,
+         mut transforms: Query<&mut Transform>| {
+            if let Ok(rig) = rigs.get(root) {
+                rig.write_pose(&pose, &mut transforms);
+            }
+        },
+    );
+}
+
+/// Reads the current pose of an avatar, for use outside of systems.
+pub fn read_humanoid_pose(world: &mut World, root: Entity) -> Option<HumanoidPose> {
+    world.run_system_once_with(
+        root,
+        |In(root): In<Entity>, rigs: Query<&HumanoidRig>, transforms: Query<&Transform>| {
+            rigs.get(root).ok().map(|rig| rig.read_pose(&transforms))
+        },
+    )
+}
+
+/// Avatars shared by the tests of the humanoid modules.
+#[cfg(test)]
+pub(crate) mod test_rig {
+    use super::*;
+
+    /// Spawns an avatar with the given bones and builds its [HumanoidRig].
+    /// Bones are listed with their parent, `None` for the root, and their local translation.
+    /// Parents must be listed before their children.
+    pub(crate) fn spawn_rig(
+        world: &mut World,
+        bones: &[(BoneName, Option<BoneName>, Vec3)],
+    ) -> (Entity, HashMap<BoneName, Entity>) {
+        let root = world.spawn(TransformBundle::default()).id();
+        let mut entities = HashMap::<BoneName, Entity>::default();
+
+        for (bone, parent, translation) in bones {
+            let parent = parent.map_or(root, |parent| entities[&parent]);
+            let entity = world
+                .spawn((
+                    TransformBundle::from_transform(Transform::from_translation(*translation)),
+                    *bone,
+                ))
+                .set_parent(parent)
+                .id();
+            entities.insert(*bone, entity);
+        }
+
+        build_humanoid_rig(world);
+
+        (root, entities)
+    }
+
+    /// Spawns a [HumanoidRig] with the given rest pose, without a bone hierarchy.
+    /// Every bone starts with an identity transform.
+    pub(crate) fn spawn_flat_rig(
+        world: &mut World,
+        rest: HumanoidRestPose,
+    ) -> (Entity, HashMap<BoneName, Entity>) {
+        let bones = rest
+            .bones
+            .keys()
+            .map(|bone| (*bone, world.spawn(TransformBundle::default()).id()))
+            .collect::<HashMap<_, _>>();
+
+        let root = world
+            .spawn(HumanoidRig {
+                bones: bones.clone(),
+                rest,
+            })
+            .id();
+
+        (root, bones)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spawn_avatar(world: &mut World, roll: Quat) -> (Entity, Entity) {
+        let root = world.spawn(TransformBundle::default()).id();
+        let armature = world
+            .spawn(TransformBundle::from_transform(Transform::from_rotation(
+                Quat::from_rotation_x(-0.3),
+            )))
+            .set_parent(root)
+            .id();
+        world
+            .spawn((
+                TransformBundle::from_transform(Transform::from_xyz(0.0, 1.0, 0.0)),
+                BoneName::Hips,
+            ))
+            .set_parent(armature);
+        let arm = world
+            .spawn((
+                TransformBundle::from_transform(Transform {
+                    translation: Vec3::new(0.2, 0.5, 0.0),
+                    rotation: roll,
+                    ..default()
+                }),
+                BoneName::LeftUpperArm,
+            ))
+            .set_parent(armature)
+            .id();
+
+        (root, arm)
+    }
+
+    #[test]
+    fn pose_round_trip() {
+        let mut world = World::new();
+        let roll = Quat::from_rotation_z(0.7);
+        let (root, arm) = spawn_avatar(&mut world, roll);
+
+        build_humanoid_rig(&mut world);
+
+        // Rest pose is the identity.
+        let pose = read_humanoid_pose(&mut world, root).unwrap();
+        assert!(pose
+            .rotation(BoneName::LeftUpperArm)
+            .unwrap()
+            .abs_diff_eq(Quat::IDENTITY, 1e-5));
+        assert!(pose
+            .hips_translation
+            .unwrap()
+            .abs_diff_eq(Quat::from_rotation_x(-0.3) * Vec3::Y, 1e-5));
+
+        let raise = Quat::from_rotation_z(1.0);
+        let mut pose = HumanoidPose::default();
+        pose.set_rotation(BoneName::LeftUpperArm, raise);
+        world.entity_mut(root).insert(pose);
+
+        world.run_system_once(apply_humanoid_pose);
+
+        let rotation = world.get::<Transform>(arm).unwrap().rotation;
+        assert!(!rotation.abs_diff_eq(roll, 1e-3));
+
+        let pose = read_humanoid_pose(&mut world, root).unwrap();
+        assert!(pose
+            .rotation(BoneName::LeftUpperArm)
+            .unwrap()
+            .abs_diff_eq(raise, 1e-5));
+    }
+}
@@ -0,0 +1,456 @@
+//! Procedural idle motion for VRM humanoids.
+//!
+//! Breathing, hip sway and head movement are layered on top of the animated pose,
+//! so avatars keep moving slightly when no animation is playing.
+
+use std::f32::consts::TAU;
+
+use bevy::{
+    ecs::{
+        component::{ComponentHooks, ComponentId, StorageType},
+        world::DeferredWorld,
+    },
+    prelude::*,
+    utils::HashMap,
+};
+use serde_vrm::vrm0::BoneName;
+
+use crate::animations::humanoid::HumanoidRig;
+
+#[derive(Clone, Copy, Debug, PartialEq, Reflect)]
+pub struct Oscillation {
+    /// In radians for rotations, meters for translations.
+    pub amplitude: f32,
+    /// Cycles per second.
+    pub frequency: f32,
+}
+
+impl Oscillation {
+    pub fn new(amplitude: f32, frequency: f32) -> Self {
+        Self {
+            amplitude,
+            frequency,
+        }
+    }
+}
+
+/// Procedural idle motion of a VRM avatar, added to the animated pose.
+/// Insert on the root entity of a VRM scene with a [HumanoidRig].
+///
+/// The motion only depends on [IdleMotion::seed] and the elapsed time, so two avatars
+/// with the same seed move in sync.
+/// Setting the weight to 0 or removing the component removes the motion from the pose.
+#[derive(Clone, Debug, Reflect)]
+#[reflect(Component)]
+pub struct IdleMotion {
+    pub seed: u64,
+    pub weight: f32,
+    /// Pitch of the spine and chest.
+    pub breathing: Oscillation,
+    /// Sideways movement of the hips.
+    pub sway: Oscillation,
+    /// Random rotation of the neck and head.
+    pub head: Oscillation,
+    pub elapsed: f32,
+    /// Rotations and hips translation written last frame, with the motion that was added.
+    /// Used to remove the motion again when no animation overwrites the bones.
+    #[reflect(ignore)]
+    written: HashMap<BoneName, (Quat, Quat)>,
+    #[reflect(ignore)]
+    written_hips: Option<(Vec3, Vec3)>,
+}
+
+impl Component for IdleMotion {
+    const STORAGE_TYPE: StorageType = StorageType::Table;
+
+    fn register_component_hooks(hooks: &mut ComponentHooks) {
+        hooks.on_remove(remove_idle_motion);
+    }
+}
+
+impl Default for IdleMotion {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            weight: 1.0,
+            breathing: Oscillation::new(1.5f32.to_radians(), 0.25),
+            sway: Oscillation::new(0.01, 0.1),
+            head: Oscillation::new(3f32.to_radians(), 0.3),
+            elapsed: 0.0,
+            written: HashMap::default(),
+            written_hips: None,
+        }
+    }
+}
+
+impl IdleMotion {
+    pub fn with_seed(seed: u64) -> Self {
+        Self { seed, ..default() }
+    }
+
+    /// Normalized rotations to add to the pose at a time, in the local space of each bone.
+    pub fn rotations(&self, time: f32) -> Vec<(BoneName, Quat)> {
+        let phase = |channel| hash(self.seed, channel, 0) * TAU;
+
+        let breath = self.weight
+            * self.breathing.amplitude
+            * (TAU * self.breathing.frequency * time + phase(0)).sin();
+
+        let head = |channel| {
+            self.weight
+                * self.head.amplitude
+                * noise(self.seed, channel, time * self.head.frequency)
+        };
+        let head = Quat::from_euler(EulerRot::YXZ, head(1), head(2), head(3) * 0.5);
+
+        vec![
+            (BoneName::Spine, Quat::from_rotation_x(breath * 0.5)),
+            (BoneName::Chest, Quat::from_rotation_x(breath)),
+            (BoneName::UpperChest, Quat::from_rotation_x(breath * 0.5)),
+            (BoneName::Neck, Quat::IDENTITY.slerp(head, 0.4)),
+            (BoneName::Head, Quat::IDENTITY.slerp(head, 0.6)),
+        ]
+    }
+
+    /// Model space offset of the hips at a time.
+    pub fn hips_offset(&self, time: f32) -> Vec3 {
+        let phase = hash(self.seed, 4, 0) * TAU;
+        let sway = (TAU * self.sway.frequency * time + phase).sin();
+
+        Vec3::new(self.weight * self.sway.amplitude * sway, 0.0, 0.0)
+    }
+}
+
+/// Pseudo-random value between 0 and 1.
+fn hash(seed: u64, channel: u64, index: i64) -> f32 {
+    let mut x = seed
+        .wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        .wrapping_add(channel.wrapping_mul(0xBF58_476D_1CE4_E5B9))
+        .wrapping_add((index as u64).wrapping_mul(0x94D0_49BB_1331_11EB));
+
+    x ^= x >> 30;
+    x = x.wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x ^= x >> 27;
+    x = x.wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^= x >> 31;
+
+    (x >> 40) as f32 / (1u64 << 24) as f32
+}
+
+/// Smooth value noise between -1 and 1, with one random value per unit of `t`.
+fn noise(seed: u64, channel: u64, t: f32) -> f32 {
+    let index = t.floor();
+    let a = hash(seed, channel, index as i64);
+    let b = hash(seed, channel, index as i64 + 1);
+
+    let f = t - index;
+    let f = f * f * (3.0 - 2.0 * f);
+
+    (a + (b - a) * f) * 2.0 - 1.0
+}
+
+/// Removes the rotation added last frame, if the bone was not written since.
+fn undo_rotation(
+    rig: &HumanoidRig,
+    bone: BoneName,
+    transform: &mut Transform,
+    (written, previous): (Quat, Quat),
+) {
+    if transform.rotation != written {
+        return;
+    }
+
+    if let Some(rotation) = rig
+        .rest
+        .normalize_rotation(bone, written)
+        .and_then(|normalized| {
+            rig.rest
+                .denormalize_rotation(bone, normalized * previous.inverse())
+        })
+    {
+        transform.rotation = rotation;
+    }
+}
+
+/// Removes the hips offset added last frame, if the hips were not written since.
+fn undo_translation(transform: &mut Transform, (written, previous): (Vec3, Vec3)) {
+    if transform.translation == written {
+        transform.translation -= previous;
+    }
+}
+
+/// Adds [IdleMotion] to the bones of avatars.
+pub(crate) fn apply_idle_motion(
+    time: Res<Time>,
+    mut avatars: Query<(&HumanoidRig, &mut IdleMotion)>,
+    mut transforms: Query<&mut Transform>,
+) {
+    for (rig, mut idle) in avatars.iter_mut() {
+        idle.elapsed += time.delta_seconds();
+
+        let idle = idle.as_mut();
+
+        if idle.weight <= 0.0 {
+            for (bone, written) in idle.written.drain() {
+                if let Some(mut transform) = rig
+                    .bones
+                    .get(&bone)
+                    .and_then(|entity| transforms.get_mut(*entity).ok())
+                {
+                    undo_rotation(rig, bone, &mut transform, written);
+                }
+            }
+
+            if let Some(written) = idle.written_hips.take() {
+                if let Some(mut transform) = rig
+                    .bones
+                    .get(&BoneName::Hips)
+                    .and_then(|entity| transforms.get_mut(*entity).ok())
+                {
+                    undo_translation(&mut transform, written);
+                }
+            }
+
+            continue;
+        }
+
+        for (bone, delta) in idle.rotations(idle.elapsed) {
+            let Some(entity) = rig.bones.get(&bone) else {
+                continue;
+            };
+
+            let Ok(mut transform) = transforms.get_mut(*entity) else {
+                continue;
+            };
+
+            if let Some(written) = idle.written.get(&bone) {
+                undo_rotation(rig, bone, &mut transform, *written);
+            }
+
+            let Some(normalized) = rig.rest.normalize_rotation(bone, transform.rotation) else {
+                continue;
+            };
+
+            if let Some(rotation) = rig.rest.denormalize_rotation(bone, normalized * delta) {
+                transform.rotation = rotation;
+                idle.written.insert(bone, (rotation, delta));
+            }
+        }
+
+        let (Some(hips), Some(rest)) = (
+            rig.bones.get(&BoneName::Hips),
+            rig.rest.bones.get(&BoneName::Hips),
+        ) else {
+            continue;
+        };
+
+        if let Ok(mut transform) = transforms.get_mut(*hips) {
+            let offset = rest
+                .parent_global()
+                .inverse()
+                .transform_vector3(idle.hips_offset(idle.elapsed));
+
+            if let Some(written) = idle.written_hips {
+                undo_translation(&mut transform, written);
+            }
+
+            transform.translation += offset;
+            idle.written_hips = Some((transform.translation, offset));
+        }
+    }
+}
+
+/// Removes the motion of an [IdleMotion] from the pose when the component is removed.
+fn remove_idle_motion(mut world: DeferredWorld, entity: Entity, _: ComponentId) {
+    let (Some(idle), Some(rig)) = (
+        world.get::<IdleMotion>(entity),
+        world.get::<HumanoidRig>(entity),
+    ) else {
+        return;
+    };
+
+    let mut restored = Vec::new();
+
+    for (bone, written) in idle.written.iter() {
+        let Some(bone_entity) = rig.bones.get(bone) else {
+            continue;
+        };
+
+        if let Some(transform) = world.get::<Transform>(*bone_entity) {
+            let mut restore = *transform;
+            undo_rotation(rig, *bone, &mut restore, *written);
+            restored.push((*bone_entity, restore));
+        }
+    }
+
+    if let (Some(written), Some(hips)) = (idle.written_hips, rig.bones.get(&BoneName::Hips)) {
+        if let Some(transform) = world.get::<Transform>(*hips) {
+            let mut restore = *transform;
+            undo_translation(&mut restore, written);
+            restored.push((*hips, restore));
+        }
+    }
+
+    for (bone_entity, transform) in restored {
+        if let Some(mut t) = world.get_mut::<Transform>(bone_entity) {
+            *t = transform;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::animations::humanoid::test_rig::spawn_rig;
+
+    fn spawn_torso(world: &mut World) -> (Entity, Entity, Entity) {
+        let (root, bones) = spawn_rig(
+            world,
+            &[
+                (BoneName::Hips, None, Vec3::new(0.0, 1.0, 0.0)),
+                (
+                    BoneName::Chest,
+                    Some(BoneName::Hips),
+                    Vec3::new(0.0, 0.3, 0.0),
+                ),
+            ],
+        );
+
+        (root, bones[&BoneName::Hips], bones[&BoneName::Chest])
+    }
+
+    #[test]
+    fn seeded_motion() {
+        let a = IdleMotion::with_seed(1);
+        let b = IdleMotion::with_seed(2);
+
+        for time in [0.0, 0.7, 3.2] {
+            assert_eq!(a.rotations(time), IdleMotion::with_seed(1).rotations(time));
+            assert_eq!(
+                a.hips_offset(time),
+                IdleMotion::with_seed(1).hips_offset(time)
+            );
+        }
+
+        assert_ne!(a.rotations(1.3), b.rotations(1.3));
+
+        let still = IdleMotion {
+            weight: 0.0,
+            ..a.clone()
+        };
+        assert!(still
+            .rotations(1.3)
+            .iter()
+            .all(|(_, r)| r.abs_diff_eq(Quat::IDENTITY, 1e-6)));
+        assert_eq!(still.hips_offset(1.3), Vec3::ZERO);
+
+        // Noise is continuous.
+        for i in 0..100 {
+            let t = i as f32 * 0.05;
+            let (x, y) = (noise(1, 0, t), noise(1, 0, t + 0.01));
+            assert!((-1.0..=1.0).contains(&x));
+            assert!((x - y).abs() < 0.1);
+        }
+    }
+
+    #[test]
+    fn add_to_animated_pose() {
+        let mut world = World::new();
+        let (root, hips, chest) = spawn_torso(&mut world);
+        world.entity_mut(root).insert(IdleMotion {
+            breathing: Oscillation::new(0.1, 0.25),
+            ..IdleMotion::with_seed(3)
+        });
+
+        // Animated pose, written again each frame.
+        let animated = Quat::from_rotation_y(0.5);
+        world.get_mut::<Transform>(chest).unwrap().rotation = animated;
+
+        let mut time = Time::<()>::default();
+        time.advance_by(Duration::from_secs_f32(1.0));
+        world.insert_resource(time);
+        world.run_system_once(apply_idle_motion);
+
+        let idle = world.get::<IdleMotion>(root).unwrap().clone();
+        assert_eq!(idle.elapsed, 1.0);
+
+        let delta = idle
+            .rotations(1.0)
+            .into_iter()
+            .find(|(bone, _)| *bone == BoneName::Chest)
+            .unwrap()
+            .1;
+        assert!(!delta.abs_diff_eq(Quat::IDENTITY, 1e-4));
+
+        let rotation = world.get::<Transform>(chest).unwrap().rotation;
+        assert!(rotation.abs_diff_eq(animated * delta, 1e-5));
+
+        let translation = world.get::<Transform>(hips).unwrap().translation;
+        assert!(translation.abs_diff_eq(Vec3::new(0.0, 1.0, 0.0) + idle.hips_offset(1.0), 1e-6));
+
+        // Without animation, the motion does not build up over frames.
+        let written = *world.get::<Transform>(chest).unwrap();
+        let written_hips = translation;
+        world.run_system_once(apply_idle_motion);
+        world.get_mut::<IdleMotion>(root).unwrap().elapsed = 0.0;
+        world.run_system_once(apply_idle_motion);
+        assert!(world
+            .get::<Transform>(chest)
+            .unwrap()
+            .rotation
+            .abs_diff_eq(written.rotation, 1e-5));
+        assert!(world
+            .get::<Transform>(hips)
+            .unwrap()
+            .translation
+            .abs_diff_eq(written_hips, 1e-6));
+    }
+
+    #[test]
+    fn weight_and_removal_restore_pose() {
+        let mut world = World::new();
+        let (root, hips, chest) = spawn_torso(&mut world);
+
+        let pose = Quat::from_rotation_y(0.5);
+        world.get_mut::<Transform>(chest).unwrap().rotation = pose;
+
+        let mut time = Time::<()>::default();
+        time.advance_by(Duration::from_secs_f32(1.0));
+        world.insert_resource(time);
+
+        let assert_pose = |world: &World| {
+            assert!(world
+                .get::<Transform>(chest)
+                .unwrap()
+                .rotation
+                .abs_diff_eq(pose, 1e-5));
+            assert!(world
+                .get::<Transform>(hips)
+                .unwrap()
+                .translation
+                .abs_diff_eq(Vec3::new(0.0, 1.0, 0.0), 1e-6));
+        };
+
+        // Pose written once, with nothing overwriting the motion.
+        world.entity_mut(root).insert(IdleMotion::with_seed(3));
+        world.run_system_once(apply_idle_motion);
+        assert!(!world
+            .get::<Transform>(chest)
+            .unwrap()
+            .rotation
+            .abs_diff_eq(pose, 1e-4));
+
+        world.get_mut::<IdleMotion>(root).unwrap().weight = 0.0;
+        world.run_system_once(apply_idle_motion);
+        assert_pose(&world);
+
+        world.get_mut::<IdleMotion>(root).unwrap().weight = 1.0;
+        world.run_system_once(apply_idle_motion);
+        world.entity_mut(root).remove::<IdleMotion>();
+        assert_pose(&world);
+    }
+}
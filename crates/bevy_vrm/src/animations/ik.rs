@@ -0,0 +1,1011 @@
+//! Inverse kinematics for VRM humanoids.
+//!
+//! Runs after animations, the [HumanoidPose](crate::animations::humanoid::HumanoidPose),
+//! idle motion and hand poses are applied, and before the twist of the limbs is distributed
+//! and spring bones are simulated, so hair and clothing follow the solved pose.
+
+use bevy::{
+    ecs::{
+        entity::MapEntities,
+        reflect::ReflectMapEntities,
+        system::{SystemId, SystemState},
+    },
+    prelude::*,
+    transform::TransformSystem,
+    utils::HashMap,
+};
+use serde_vrm::vrm0::BoneName;
+
+use crate::animations::{
+    hand::apply_hand_poses,
+    humanoid::{apply_humanoid_pose, HumanoidRig},
+    idle::apply_idle_motion,
+    params::{distribute_twist, HumanoidParams},
+    root_motion::apply_root_motion,
+};
+
+pub struct HumanoidIkPlugin;
+
+impl Plugin for HumanoidIkPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<HumanoidIk>().add_systems(
+            PostUpdate,
+            solve_humanoid_ik
+                .after(apply_humanoid_pose)
+                .after(apply_root_motion)
+                .after(apply_idle_motion)
+                .after(apply_hand_poses)
+                .before(distribute_twist)
+                .before(crate::look_at::update_look_at)
+                .before(TransformSystem::TransformPropagate),
+        );
+    }
+}
+
+/// Where an IK chain should reach.
+#[derive(Clone, Copy, Debug, PartialEq, Reflect)]
+pub enum IkTarget {
+    Entity(Entity),
+    /// A transform in world space.
+    Transform(Transform),
+}
+
+impl IkTarget {
+    pub fn point(point: Vec3) -> Self {
+        Self::Transform(Transform::from_translation(point))
+    }
+
+    fn resolve(&self, globals: &Query<&GlobalTransform>) -> Option<Transform> {
+        match self {
+            Self::Entity(entity) => globals.get(*entity).ok().map(|t| t.compute_transform()),
+            Self::Transform(transform) => Some(*transform),
+        }
+    }
+
+    fn map_entities<M: EntityMapper>(&mut self, entity_mapper: &mut M) {
+        if let Self::Entity(entity) = self {
+            *entity = entity_mapper.map_entity(*entity);
+        }
+    }
+}
+
+/// Two-bone IK for an arm or leg, from the upper bone to the hand or foot.
+#[derive(Clone, Copy, Debug, PartialEq, Reflect)]
+pub struct LimbIk {
+    pub target: IkTarget,
+    /// Point the elbow or knee bends towards.
+    /// Defaults to the current bend of the limb.
+    pub pole: Option<IkTarget>,
+    pub weight: f32,
+    /// How much the hand or foot matches the rotation of the target.
+    /// A target with an identity rotation gives the bone its rest rotation.
+    pub rotation_weight: f32,
+}
+
+impl LimbIk {
+    pub fn new(target: IkTarget) -> Self {
+        Self {
+            target,
+            pole: None,
+            weight: 1.0,
+            rotation_weight: 0.0,
+        }
+    }
+}
+
+/// Turns the neck and head towards a target.
+#[derive(Clone, Copy, Debug, PartialEq, Reflect)]
+pub struct HeadIk {
+    pub target: IkTarget,
+    pub weight: f32,
+    /// Fraction of the rotation applied to the neck, the head takes the rest.
+    pub neck_weight: f32,
+    /// Maximum rotation from the animated pose, in radians.
+    pub max_angle: f32,
+}
+
+impl HeadIk {
+    pub fn new(target: IkTarget) -> Self {
+        Self {
+            target,
+            weight: 1.0,
+            neck_weight: 0.4,
+            max_angle: 80f32.to_radians(),
+        }
+    }
+}
+
+/// Moves the feet to the ground below them using [FootRaycast].
+#[derive(Clone, Copy, Debug, PartialEq, Reflect)]
+pub struct FootPlanting {
+    pub weight: f32,
+    /// How far above or below the root the ground is searched for.
+    pub max_step: f32,
+    /// How much the feet align to the ground normal.
+    pub align_weight: f32,
+}
+
+impl Default for FootPlanting {
+    fn default() -> Self {
+        Self {
+            weight: 1.0,
+            max_step: 0.5,
+            align_weight: 1.0,
+        }
+    }
+}
+
+/// IK goals of a VRM avatar.
+/// Insert on the root entity of a VRM scene with a [HumanoidRig].
+///
+/// IK is solved from the animated pose each frame, so weights and angle limits hold
+/// when nothing animates the bones. Removing the component removes the IK from the pose.
+#[derive(Component, Clone, Debug, Default, Reflect)]
+#[reflect(Component, MapEntities)]
+pub struct HumanoidIk {
+    pub left_hand: Option<LimbIk>,
+    pub right_hand: Option<LimbIk>,
+    /// Overrides foot planting for the left foot.
+    pub left_foot: Option<LimbIk>,
+    /// Overrides foot planting for the right foot.
+    pub right_foot: Option<LimbIk>,
+    pub head: Option<HeadIk>,
+    pub foot_planting: Option<FootPlanting>,
+}
+
+impl MapEntities for HumanoidIk {
+    fn map_entities<M: EntityMapper>(&mut self, entity_mapper: &mut M) {
+        for limb in [
+            &mut self.left_hand,
+            &mut self.right_hand,
+            &mut self.left_foot,
+            &mut self.right_foot,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            limb.target.map_entities(entity_mapper);
+
+            if let Some(pole) = &mut limb.pole {
+                pole.map_entities(entity_mapper);
+            }
+        }
+
+        if let Some(head) = &mut self.head {
+            head.target.map_entities(entity_mapper);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RaycastHit {
+    pub point: Vec3,
+    pub normal: Vec3,
+}
+
+/// Raycast used for [FootPlanting], given a world space ray and its maximum distance.
+///
+/// Register a system that casts against your physics or collision world:
+///
+/// ```ignore
+/// let raycast = app.world_mut().register_system(
+///     |In((ray, max_distance)): In<(Ray3d, f32)>, query: SpatialQuery| {
+///         // ...
+///     },
+/// );
+/// app.insert_resource(FootRaycast(raycast));
+/// ```
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct FootRaycast(pub SystemId<(Ray3d, f32), Option<RaycastHit>>);
+
+/// Solves a two-bone chain from `a` through `b` to `c`, bending towards `pole`.
+///
+/// Returns the world space rotations to apply to the upper and lower bones.
+pub fn solve_two_bone(a: Vec3, b: Vec3, c: Vec3, target: Vec3, pole: Vec3) -> (Quat, Quat) {
+    let upper = a.distance(b);
+    let lower = b.distance(c);
+
+    let to_target = target - a;
+    let Some(dir) = to_target.try_normalize() else {
+        return (Quat::IDENTITY, Quat::IDENTITY);
+    };
+
+    if upper <= f32::EPSILON || lower <= f32::EPSILON {
+        return (Quat::IDENTITY, Quat::IDENTITY);
+    }
+
+    let min = (upper - lower).abs() + 1e-4;
+    let max = (upper + lower - 1e-4).max(min);
+    let distance = to_target.length().clamp(min, max);
+
+    let bend = (pole - a)
+        .reject_from_normalized(dir)
+        .try_normalize()
+        .or_else(|| (b - a).reject_from_normalized(dir).try_normalize())
+        .unwrap_or_else(|| dir.any_orthonormal_vector());
+
+    let cos = ((upper * upper + distance * distance - lower * lower) / (2.0 * upper * distance))
+        .clamp(-1.0, 1.0);
+    let sin = (1.0 - cos * cos).sqrt();
+    let knee = a + dir * upper * cos + bend * upper * sin;
+
+    let upper_rotation = Quat::from_rotation_arc((b - a) / upper, (knee - a) / upper);
+
+    let end = a + upper_rotation * (c - a);
+    let lower_rotation = Quat::from_rotation_arc(
+        (end - knee).normalize(),
+        (a + dir * distance - knee).normalize(),
+    );
+
+    (upper_rotation, lower_rotation)
+}
+
+type IkState<'w, 's> = (
+    Query<
+        'w,
+        's,
+        (
+            Entity,
+            &'static HumanoidRig,
+            &'static HumanoidIk,
+            Option<&'static HumanoidParams>,
+        ),
+    >,
+    Query<'w, 's, &'static GlobalTransform>,
+    Query<'w, 's, &'static mut Transform>,
+    Query<'w, 's, &'static Parent>,
+);
+
+/// World transform of an entity from the local transforms of its ancestors.
+/// Global transforms are not propagated yet when IK runs.
+fn world_transform(
+    entity: Entity,
+    transforms: &Query<&mut Transform>,
+    parents: &Query<&Parent>,
+) -> GlobalTransform {
+    let local = transforms
+        .get(entity)
+        .map(|t| GlobalTransform::from(*t))
+        .unwrap_or_default();
+
+    match parents.get(entity) {
+        Ok(parent) => world_transform(parent.get(), transforms, parents) * local,
+        Err(_) => local,
+    }
+}
+
+fn world_rotation(
+    entity: Entity,
+    transforms: &Query<&mut Transform>,
+    parents: &Query<&Parent>,
+) -> Quat {
+    world_transform(entity, transforms, parents)
+        .to_scale_rotation_translation()
+        .1
+}
+
+fn parent_rotation(
+    entity: Entity,
+    transforms: &Query<&mut Transform>,
+    parents: &Query<&Parent>,
+) -> Quat {
+    parents
+        .get(entity)
+        .map(|p| world_rotation(p.get(), transforms, parents))
+        .unwrap_or_default()
+}
+
+/// Applies a world space rotation to a bone.
+fn rotate_world(
+    entity: Entity,
+    rotation: Quat,
+    transforms: &mut Query<&mut Transform>,
+    parents: &Query<&Parent>,
+) {
+    let parent = parent_rotation(entity, transforms, parents);
+
+    if let Ok(mut transform) = transforms.get_mut(entity) {
+        transform.rotation =
+            (parent.inverse() * rotation * parent * transform.rotation).normalize();
+    }
+}
+
+/// Sets the world space rotation of a bone.
+fn set_world_rotation(
+    entity: Entity,
+    rotation: Quat,
+    weight: f32,
+    transforms: &mut Query<&mut Transform>,
+    parents: &Query<&Parent>,
+) {
+    let parent = parent_rotation(entity, transforms, parents);
+
+    if let Ok(mut transform) = transforms.get_mut(entity) {
+        transform.rotation = transform
+            .rotation
+            .slerp(parent.inverse() * rotation, weight);
+    }
+}
+
+const LIMBS: [(BoneName, BoneName, BoneName); 4] = [
+    (
+        BoneName::LeftUpperArm,
+        BoneName::LeftLowerArm,
+        BoneName::LeftHand,
+    ),
+    (
+        BoneName::RightUpperArm,
+        BoneName::RightLowerArm,
+        BoneName::RightHand,
+    ),
+    (
+        BoneName::LeftUpperLeg,
+        BoneName::LeftLowerLeg,
+        BoneName::LeftFoot,
+    ),
+    (
+        BoneName::RightUpperLeg,
+        BoneName::RightLowerLeg,
+        BoneName::RightFoot,
+    ),
+];
+
+const FEET: [BoneName; 2] = [BoneName::LeftFoot, BoneName::RightFoot];
+
+/// Bone transforms written by IK last frame, with the transforms they replaced.
+/// Inserted on the root entity of a VRM scene.
+#[derive(Component, Default)]
+pub(crate) struct IkWritten(HashMap<Entity, (Transform, Transform)>);
+
+/// Undoes the IK of last frame on bones that were not written since,
+/// so IK is solved from the animated pose instead of building on its own result.
+/// Runs for every avatar IK wrote to, so the pose is restored when the IK is removed.
+fn restore_ik(world: &mut World) {
+    let mut written = world.query::<&mut IkWritten>();
+    let written = written
+        .iter_mut(world)
+        .flat_map(|mut written| written.0.drain().collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    for (entity, (written, original)) in written {
+        if let Some(mut transform) = world.get_mut::<Transform>(entity) {
+            if *transform == written {
+                *transform = original;
+            }
+        }
+    }
+}
+
+/// Stretches a limb towards a target that is out of reach, up to a fraction of its length,
+/// by moving the lower bone and end bone away from their parents.
+fn stretch_limb(
+    rig: &HumanoidRig,
+    (upper, lower, end): (BoneName, BoneName, BoneName),
+    target: Vec3,
+    max_stretch: f32,
+    transforms: &mut Query<&mut Transform>,
+    parents: &Query<&Parent>,
+) {
+    if max_stretch <= 0.0 {
+        return;
+    }
+
+    let (Some(upper_entity), Some(lower_entity), Some(end_entity)) = (
+        rig.bones.get(&upper),
+        rig.bones.get(&lower),
+        rig.bones.get(&end),
+    ) else {
+        return;
+    };
+
+    let (Some(lower_rest), Some(end_rest)) = (rig.rest.bones.get(&lower), rig.rest.bones.get(&end))
+    else {
+        return;
+    };
+
+    let rests = [(lower_entity, lower_rest), (end_entity, end_rest)];
+
+    for (entity, rest) in rests {
+        if let Ok(mut transform) = transforms.get_mut(*entity) {
+            transform.translation = rest.local.translation;
+        }
+    }
+
+    let [a, b, c] = [*upper_entity, *lower_entity, *end_entity]
+        .map(|e| world_transform(e, transforms, parents).translation());
+
+    let length = a.distance(b) + b.distance(c);
+    if length <= f32::EPSILON {
+        return;
+    }
+
+    let scale = (a.distance(target) / length).clamp(1.0, 1.0 + max_stretch);
+    if scale <= 1.0 {
+        return;
+    }
+
+    for (entity, rest) in rests {
+        if let Ok(mut transform) = transforms.get_mut(*entity) {
+            transform.translation = rest.local.translation * scale;
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn solve_limb(
+    rig: &HumanoidRig,
+    params: Option<&HumanoidParams>,
+    (upper, lower, end): (BoneName, BoneName, BoneName),
+    limb: &LimbIk,
+    target: Transform,
+    default_pole: Vec3,
+    globals: &Query<&GlobalTransform>,
+    transforms: &mut Query<&mut Transform>,
+    parents: &Query<&Parent>,
+) {
+    if let Some(params) = params {
+        stretch_limb(
+            rig,
+            (upper, lower, end),
+            target.translation,
+            params.stretch(end) * limb.weight,
+            transforms,
+            parents,
+        );
+    }
+
+    let (Some(upper), Some(lower), Some(end_entity)) = (
+        rig.bones.get(&upper),
+        rig.bones.get(&lower),
+        rig.bones.get(&end),
+    ) else {
+        return;
+    };
+
+    let [a, b, c] = [*upper, *lower, *end_entity]
+        .map(|e| world_transform(e, transforms, parents).translation());
+
+    let pole = limb
+        .pole
+        .and_then(|pole| pole.resolve(globals))
+        .map(|pole| pole.translation)
+        .unwrap_or_else(|| {
+            // Keep the current bend, unless the limb is straight.
+            let dir = (target.translation - a).normalize_or_zero();
+            let bend = (b - a).reject_from(dir);
+            if bend.length_squared() > 1e-6 {
+                b
+            } else {
+                b + default_pole
+            }
+        });
+
+    let original = [*upper, *lower].map(|e| transforms.get(e).map(|t| t.rotation).ok());
+
+    let (upper_rotation, lower_rotation) = solve_two_bone(a, b, c, target.translation, pole);
+    rotate_world(*upper, upper_rotation, transforms, parents);
+    rotate_world(*lower, lower_rotation, transforms, parents);
+
+    for (entity, original) in [*upper, *lower].into_iter().zip(original) {
+        if let (Ok(mut transform), Some(original)) = (transforms.get_mut(entity), original) {
+            transform.rotation = original.slerp(transform.rotation, limb.weight);
+        }
+    }
+
+    if limb.rotation_weight > 0.0 {
+        if let Some(rest) = rig.rest.bones.get(&end) {
+            set_world_rotation(
+                *end_entity,
+                target.rotation * rest.global.rotation,
+                limb.rotation_weight * limb.weight,
+                transforms,
+                parents,
+            );
+        }
+    }
+}
+
+fn solve_head(
+    rig: &HumanoidRig,
+    head_ik: &HeadIk,
+    target: Vec3,
+    transforms: &mut Query<&mut Transform>,
+    parents: &Query<&Parent>,
+) {
+    let (Some(head), Some(rest)) = (
+        rig.bones.get(&BoneName::Head),
+        rig.rest.bones.get(&BoneName::Head),
+    ) else {
+        return;
+    };
+
+    let (_, rotation, origin) =
+        world_transform(*head, transforms, parents).to_scale_rotation_translation();
+
+    // VRM 0.0 avatars face -Z.
+    let forward = rotation * rest.global.rotation.inverse() * Vec3::NEG_Z;
+    let Some(dir) = (target - origin).try_normalize() else {
+        return;
+    };
+
+    let (axis, angle) = Quat::from_rotation_arc(forward, dir).to_axis_angle();
+    let angle = angle.min(head_ik.max_angle) * head_ik.weight;
+
+    let neck_angle = match rig.bones.get(&BoneName::Neck) {
+        Some(neck) => {
+            let neck_angle = angle * head_ik.neck_weight;
+            rotate_world(
+                *neck,
+                Quat::from_axis_angle(axis, neck_angle),
+                transforms,
+                parents,
+            );
+            neck_angle
+        }
+        None => 0.0,
+    };
+
+    rotate_world(
+        *head,
+        Quat::from_axis_angle(axis, angle - neck_angle),
+        transforms,
+        parents,
+    );
+}
+
+/// Collects the foot planting rays of every avatar.
+fn foot_rays(
+    world: &mut World,
+    state: &mut SystemState<IkState<'static, 'static>>,
+) -> Vec<(Entity, BoneName, Ray3d, f32)> {
+    let (avatars, _, transforms, parents) = state.get_mut(world);
+    let mut rays = Vec::new();
+
+    for (root, rig, ik, _) in avatars.iter() {
+        let Some(planting) = ik.foot_planting else {
+            continue;
+        };
+
+        let (_, root_rotation, _) =
+            world_transform(root, &transforms, &parents).to_scale_rotation_translation();
+        let up = root_rotation * Vec3::Y;
+
+        for foot in FEET {
+            let Some(entity) = rig.bones.get(&foot) else {
+                continue;
+            };
+
+            let position = world_transform(*entity, &transforms, &parents).translation();
+            let ray = Ray3d::new(position + up * planting.max_step, -up);
+            rays.push((root, foot, ray, planting.max_step * 2.0));
+        }
+    }
+
+    rays
+}
+
+pub(crate) fn solve_humanoid_ik(
+    world: &mut World,
+    state: &mut SystemState<IkState<'static, 'static>>,
+) {
+    restore_ik(world);
+
+    let mut hits = HashMap::<(Entity, BoneName), RaycastHit>::default();
+
+    if let Some(FootRaycast(raycast)) = world.get_resource::<FootRaycast>().copied() {
+        for (root, foot, ray, max_distance) in foot_rays(world, state) {
+            if let Ok(Some(hit)) = world.run_system_with_input(raycast, (ray, max_distance)) {
+                hits.insert((root, foot), hit);
+            }
+        }
+    }
+
+    let (avatars, globals, mut transforms, parents) = state.get_mut(world);
+    let mut written = Vec::new();
+
+    for (root, rig, ik, params) in avatars.iter() {
+        let original = rig
+            .bones
+            .values()
+            .filter_map(|entity| Some((*entity, *transforms.get(*entity).ok()?)))
+            .collect::<Vec<_>>();
+
+        let (_, root_rotation, root_translation) =
+            world_transform(root, &transforms, &parents).to_scale_rotation_translation();
+        let up = root_rotation * Vec3::Y;
+        let forward = root_rotation * Vec3::NEG_Z;
+
+        let feet = [ik.left_foot, ik.right_foot]
+            .map(|limb| limb.and_then(|limb| Some((limb, limb.target.resolve(&globals)?))));
+
+        if let Some(planting) = ik.foot_planting {
+            // Height of the ground below each foot, relative to the root.
+            let offsets = FEET.map(|foot| {
+                hits.get(&(root, foot)).map(|hit| {
+                    (
+                        (hit.point - root_translation).dot(up) * planting.weight,
+                        hit,
+                    )
+                })
+            });
+
+            // Lower the hips so both feet can reach the ground.
+            let drop = offsets
+                .iter()
+                .flatten()
+                .map(|(offset, _)| *offset)
+                .fold(0.0, f32::min);
+
+            if drop < 0.0 {
+                if let Some(hips) = rig.bones.get(&BoneName::Hips) {
+                    let parent = parents
+                        .get(*hips)
+                        .map(|p| world_transform(p.get(), &transforms, &parents))
+                        .unwrap_or_default();
+
+                    if let Ok(mut transform) = transforms.get_mut(*hips) {
+                        let world = parent.transform_point(transform.translation) + up * drop;
+                        transform.translation = parent.affine().inverse().transform_point3(world);
+                    }
+                }
+            }
+
+            let mut targets = FEET.map(|_| None);
+
+            for (i, foot) in FEET.into_iter().enumerate() {
+                if feet[i].is_some() {
+                    continue;
+                }
+
+                let (Some((offset, hit)), Some(entity)) = (offsets[i], rig.bones.get(&foot)) else {
+                    continue;
+                };
+
+                // Keep the lift of the animation above the ground.
+                let position = world_transform(*entity, &transforms, &parents).translation();
+                let rotation =
+                    Quat::from_rotation_arc(up, hit.normal.try_normalize().unwrap_or(up))
+                        * world_rotation(*entity, &transforms, &parents);
+
+                targets[i] = Some((
+                    *entity,
+                    Transform {
+                        translation: position + up * (offset - drop),
+                        rotation,
+                        ..default()
+                    },
+                ));
+            }
+
+            // Push the feet apart to the spacing of the avatar.
+            if let (Some(params), [Some((_, left)), Some((_, right))]) = (params, &mut targets) {
+                let side = (left.translation - right.translation).reject_from_normalized(up);
+                let distance = side.length();
+
+                if distance < params.feet_spacing {
+                    let dir = side
+                        .try_normalize()
+                        .unwrap_or_else(|| up.cross(forward).normalize());
+                    let push = dir * (params.feet_spacing - distance) * 0.5;
+                    left.translation += push;
+                    right.translation -= push;
+                }
+            }
+
+            for (i, target) in targets.into_iter().enumerate() {
+                let Some((entity, target)) = target else {
+                    continue;
+                };
+
+                solve_limb(
+                    rig,
+                    params,
+                    LIMBS[i + 2],
+                    &LimbIk::new(IkTarget::Transform(target)),
+                    target,
+                    forward,
+                    &globals,
+                    &mut transforms,
+                    &parents,
+                );
+
+                set_world_rotation(
+                    entity,
+                    target.rotation,
+                    planting.align_weight * planting.weight,
+                    &mut transforms,
+                    &parents,
+                );
+            }
+        }
+
+        for (i, limb) in [ik.left_foot, ik.right_foot].into_iter().enumerate() {
+            let (Some(limb), Some((_, target))) = (limb, feet[i]) else {
+                continue;
+            };
+
+            solve_limb(
+                rig,
+                params,
+                LIMBS[i + 2],
+                &limb,
+                target,
+                forward,
+                &globals,
+                &mut transforms,
+                &parents,
+            );
+        }
+
+        for (i, limb) in [ik.left_hand, ik.right_hand].into_iter().enumerate() {
+            let Some(limb) = limb else {
+                continue;
+            };
+
+            let Some(target) = limb.target.resolve(&globals) else {
+                continue;
+            };
+
+            // Elbows bend backwards.
+            solve_limb(
+                rig,
+                params,
+                LIMBS[i],
+                &limb,
+                target,
+                -forward,
+                &globals,
+                &mut transforms,
+                &parents,
+            );
+        }
+
+        if let Some(head) = ik.head {
+            if let Some(target) = head.target.resolve(&globals) {
+                solve_head(rig, &head, target.translation, &mut transforms, &parents);
+            }
+        }
+
+        let changed = original
+            .into_iter()
+            .filter_map(|(entity, original)| {
+                let transform = *transforms.get(entity).ok()?;
+                (transform != original).then_some((entity, (transform, original)))
+            })
+            .collect::<HashMap<_, _>>();
+
+        if !changed.is_empty() {
+            written.push((root, changed));
+        }
+    }
+
+    for (root, changed) in written {
+        world.entity_mut(root).insert(IkWritten(changed));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::animations::humanoid::test_rig::spawn_rig;
+
+    fn spawn_leg(world: &mut World) -> (Entity, [Entity; 4]) {
+        let (root, bones) = spawn_rig(
+            world,
+            &[
+                (BoneName::Hips, None, Vec3::new(0.0, 1.0, 0.0)),
+                (
+                    BoneName::LeftUpperLeg,
+                    Some(BoneName::Hips),
+                    Vec3::new(0.1, 0.0, 0.0),
+                ),
+                (
+                    BoneName::LeftLowerLeg,
+                    Some(BoneName::LeftUpperLeg),
+                    Vec3::new(0.0, -0.5, 0.0),
+                ),
+                (
+                    BoneName::LeftFoot,
+                    Some(BoneName::LeftLowerLeg),
+                    Vec3::new(0.0, -0.45, 0.0),
+                ),
+                (BoneName::Head, Some(BoneName::Hips), Vec3::ZERO),
+            ],
+        );
+
+        (
+            root,
+            [
+                BoneName::Hips,
+                BoneName::LeftUpperLeg,
+                BoneName::LeftLowerLeg,
+                BoneName::LeftFoot,
+            ]
+            .map(|bone| bones[&bone]),
+        )
+    }
+
+    fn position(world: &mut World, entity: Entity) -> Vec3 {
+        world.run_system_once_with(
+            entity,
+            |In(entity): In<Entity>, transforms: Query<&mut Transform>, parents: Query<&Parent>| {
+                world_transform(entity, &transforms, &parents).translation()
+            },
+        )
+    }
+
+    #[test]
+    fn two_bone_reaches_target() {
+        let a = Vec3::ZERO;
+        let b = Vec3::new(0.0, -1.0, 0.0);
+        let c = Vec3::new(0.0, -2.0, 0.0);
+        let target = Vec3::new(0.5, -1.2, 0.0);
+
+        let (upper, lower) = solve_two_bone(a, b, c, target, Vec3::new(0.0, 0.0, -1.0));
+
+        let knee = a + upper * (b - a);
+        let end = knee + lower * upper * (c - b);
+        assert!(end.abs_diff_eq(target, 1e-4));
+        // Bends towards the pole.
+        assert!(knee.z < -0.1);
+        assert!((knee.length() - 1.0).abs() < 1e-4);
+
+        // Out of reach targets straighten the limb.
+        let (upper, lower) = solve_two_bone(a, b, c, Vec3::new(0.0, 0.0, -5.0), Vec3::Y);
+        let end = a + upper * (b - a) + lower * upper * (c - b);
+        assert!(end.abs_diff_eq(Vec3::new(0.0, 0.0, -2.0), 1e-2));
+    }
+
+    #[test]
+    fn limb_and_head_targets() {
+        let mut world = World::new();
+        let (root, [hips, _, _, foot]) = spawn_leg(&mut world);
+
+        let target = Vec3::new(0.3, 0.3, -0.2);
+        let head_target = Vec3::new(5.0, 1.0, 0.0);
+        world.entity_mut(root).insert(HumanoidIk {
+            left_foot: Some(LimbIk::new(IkTarget::point(target))),
+            head: Some(HeadIk::new(IkTarget::point(head_target))),
+            ..default()
+        });
+
+        world.run_system_once(solve_humanoid_ik);
+
+        assert!(position(&mut world, foot).abs_diff_eq(target, 1e-3));
+
+        // The head turns towards the target, up to the maximum angle.
+        let head = world.get::<Children>(hips).unwrap()[1];
+        let forward = world.get::<Transform>(head).unwrap().rotation * Vec3::NEG_Z;
+        assert!((forward.angle_between(Vec3::X) - 10f32.to_radians()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn stretch_limit() {
+        let mut world = World::new();
+        let (root, [_, _, _, foot]) = spawn_leg(&mut world);
+
+        world.entity_mut(root).insert((
+            HumanoidIk {
+                left_foot: Some(LimbIk::new(IkTarget::point(Vec3::new(0.1, -1.0, 0.0)))),
+                ..default()
+            },
+            HumanoidParams {
+                leg_stretch: 0.1,
+                ..default()
+            },
+        ));
+
+        world.run_system_once(solve_humanoid_ik);
+
+        // The leg is 0.95 long, and stretches up to 10%.
+        let expected = Vec3::new(0.1, 1.0 - 0.95 * 1.1, 0.0);
+        assert!(position(&mut world, foot).abs_diff_eq(expected, 1e-3));
+
+        let rest = Vec3::new(0.0, -0.45, 0.0);
+        let limb = |world: &mut World, f: fn(&mut LimbIk)| {
+            f(world
+                .get_mut::<HumanoidIk>(root)
+                .unwrap()
+                .left_foot
+                .as_mut()
+                .unwrap());
+            world.run_system_once(solve_humanoid_ik);
+        };
+
+        // The rest length is restored when the IK is disabled.
+        limb(&mut world, |limb| limb.weight = 0.0);
+        assert_eq!(world.get::<Transform>(foot).unwrap().translation, rest);
+
+        // And once the target is in reach.
+        limb(&mut world, |limb| limb.weight = 1.0);
+        assert!(position(&mut world, foot).abs_diff_eq(expected, 1e-3));
+
+        limb(&mut world, |limb| {
+            limb.target = IkTarget::point(Vec3::new(0.1, 0.3, 0.0))
+        });
+        assert!(position(&mut world, foot).abs_diff_eq(Vec3::new(0.1, 0.3, 0.0), 1e-3));
+        assert_eq!(world.get::<Transform>(foot).unwrap().translation, rest);
+    }
+
+    #[test]
+    fn foot_planting() {
+        let mut world = World::new();
+        let (root, [hips, _, _, foot]) = spawn_leg(&mut world);
+
+        // Ground raised below the foot.
+        let raycast = world.register_system(
+            |In((ray, max_distance)): In<(Ray3d, f32)>| -> Option<RaycastHit> {
+                let height = 0.2;
+                let distance = ray.origin.y - height;
+                (distance <= max_distance).then_some(RaycastHit {
+                    point: Vec3::new(ray.origin.x, height, ray.origin.z),
+                    normal: Vec3::Y,
+                })
+            },
+        );
+        world.insert_resource(FootRaycast(raycast));
+        world.entity_mut(root).insert(HumanoidIk {
+            foot_planting: Some(FootPlanting::default()),
+            ..default()
+        });
+
+        let before = position(&mut world, foot);
+
+        // Nothing animates the legs, so the foot must not keep rising.
+        for _ in 0..3 {
+            world.run_system_once(solve_humanoid_ik);
+            let after = position(&mut world, foot);
+
+            assert!((after.y - before.y - 0.2).abs() < 1e-3);
+            assert!(after.xz().abs_diff_eq(before.xz(), 0.1));
+        }
+
+        // No right foot, so the hips are not lowered.
+        assert_eq!(world.get::<Transform>(hips).unwrap().translation.y, 1.0);
+    }
+
+    #[test]
+    fn solve_from_animated_pose() {
+        let mut world = World::new();
+        let (root, [hips, _, _, foot]) = spawn_leg(&mut world);
+
+        world.entity_mut(root).insert(HumanoidIk {
+            left_foot: Some(LimbIk {
+                weight: 0.5,
+                ..LimbIk::new(IkTarget::point(Vec3::new(0.3, 0.3, -0.2)))
+            }),
+            // Behind the avatar.
+            head: Some(HeadIk {
+                weight: 0.5,
+                max_angle: 30f32.to_radians(),
+                ..HeadIk::new(IkTarget::point(Vec3::new(1.0, 1.0, 5.0)))
+            }),
+            ..default()
+        });
+
+        world.run_system_once(solve_humanoid_ik);
+        let foot_position = position(&mut world, foot);
+        let head = world.get::<Children>(hips).unwrap()[1];
+
+        // Without animation, each frame gives the same pose instead of building on the last.
+        for _ in 0..5 {
+            world.run_system_once(solve_humanoid_ik);
+
+            assert!(position(&mut world, foot).abs_diff_eq(foot_position, 1e-5));
+
+            let forward = world.get::<Transform>(head).unwrap().rotation * Vec3::NEG_Z;
+            assert!((forward.angle_between(Vec3::NEG_Z) - 15f32.to_radians()).abs() < 1e-4);
+        }
+
+        // Removing the IK restores the pose.
+        world.entity_mut(root).remove::<HumanoidIk>();
+        world.run_system_once(solve_humanoid_ik);
+        assert_eq!(
+            world.get::<Transform>(head).unwrap().rotation,
+            Quat::IDENTITY
+        );
+        assert!(position(&mut world, foot).abs_diff_eq(Vec3::new(0.1, 0.05, 0.0), 1e-5));
+    }
+}
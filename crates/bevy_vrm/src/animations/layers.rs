@@ -0,0 +1,810 @@
+//! Masking, layering and crossfading of humanoid animations.
+//!
+//! Clips keyed by [VRM_ANIMATION_TARGETS] can be masked with a [BoneMask] and added to an
+//! [AnimationGraph] with [HumanoidGraphExt::add_masked_clip]. Clips with disjoint masks blend
+//! without affecting each other, such as upper body gestures over a locomotion cycle,
+//! and [AnimationTransitions] crossfades
+//! between graph nodes.
+//!
+//! [HumanoidLayers] are evaluated on top of the [AnimationPlayer], with per-layer masks,
+//! override or additive blending, and timed crossfades between clips.
+
+use bevy::{animation::AnimationTargetId, prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
+use serde_vrm::vrm0::BoneName;
+
+use crate::animations::{
+    humanoid::HumanoidRig,
+    sampling::sample_transform,
+    vrm::{VRM_ANIMATION_BONES, VRM_ANIMATION_TARGETS},
+};
+
+/// Groups of humanoid bones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Reflect, Serialize, Deserialize)]
+pub enum BoneGroup {
+    /// Hips, spine, chest and upper chest.
+    Spine,
+    /// Neck, head, jaw and eyes.
+    Head,
+    /// Shoulders, arms and hands.
+    Arms,
+    Fingers,
+    /// Legs, feet and toes.
+    Legs,
+}
+
+impl BoneGroup {
+    pub const ALL: [BoneGroup; 5] = [
+        BoneGroup::Spine,
+        BoneGroup::Head,
+        BoneGroup::Arms,
+        BoneGroup::Fingers,
+        BoneGroup::Legs,
+    ];
+
+    pub fn bones(&self) -> &'static [BoneName] {
+        match self {
+            BoneGroup::Spine => &[
+                BoneName::Hips,
+                BoneName::Spine,
+                BoneName::Chest,
+                BoneName::UpperChest,
+            ],
+            BoneGroup::Head => &[
+                BoneName::Neck,
+                BoneName::Head,
+                BoneName::Jaw,
+                BoneName::LeftEye,
+                BoneName::RightEye,
+            ],
+            BoneGroup::Arms => &[
+                BoneName::LeftShoulder,
+                BoneName::LeftUpperArm,
+                BoneName::LeftLowerArm,
+                BoneName::LeftHand,
+                BoneName::RightShoulder,
+                BoneName::RightUpperArm,
+                BoneName::RightLowerArm,
+                BoneName::RightHand,
+            ],
+            BoneGroup::Fingers => &[
+                BoneName::LeftThumbProximal,
+                BoneName::LeftThumbIntermediate,
+                BoneName::LeftThumbDistal,
+                BoneName::LeftIndexProximal,
+                BoneName::LeftIndexIntermediate,
+                BoneName::LeftIndexDistal,
+                BoneName::LeftMiddleProximal,
+                BoneName::LeftMiddleIntermediate,
+                BoneName::LeftMiddleDistal,
+                BoneName::LeftRingProximal,
+                BoneName::LeftRingIntermediate,
+                BoneName::LeftRingDistal,
+                BoneName::LeftLittleProximal,
+                BoneName::LeftLittleIntermediate,
+                BoneName::LeftLittleDistal,
+                BoneName::RightThumbProximal,
+                BoneName::RightThumbIntermediate,
+                BoneName::RightThumbDistal,
+                BoneName::RightIndexProximal,
+                BoneName::RightIndexIntermediate,
+                BoneName::RightIndexDistal,
+                BoneName::RightMiddleProximal,
+                BoneName::RightMiddleIntermediate,
+                BoneName::RightMiddleDistal,
+                BoneName::RightRingProximal,
+                BoneName::RightRingIntermediate,
+                BoneName::RightRingDistal,
+                BoneName::RightLittleProximal,
+                BoneName::RightLittleIntermediate,
+                BoneName::RightLittleDistal,
+            ],
+            BoneGroup::Legs => &[
+                BoneName::LeftUpperLeg,
+                BoneName::LeftLowerLeg,
+                BoneName::LeftFoot,
+                BoneName::LeftToes,
+                BoneName::RightUpperLeg,
+                BoneName::RightLowerLeg,
+                BoneName::RightFoot,
+                BoneName::RightToes,
+            ],
+        }
+    }
+
+    pub fn of(bone: BoneName) -> BoneGroup {
+        Self::ALL
+            .into_iter()
+            .find(|group| group.bones().contains(&bone))
+            .unwrap_or(BoneGroup::Spine)
+    }
+}
+
+/// A set of humanoid bones.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Reflect, Serialize, Deserialize)]
+pub struct BoneMask(u64);
+
+impl BoneMask {
+    pub const NONE: BoneMask = BoneMask(0);
+
+    fn bit(bone: BoneName) -> u64 {
+        1 << (bone as u64)
+    }
+
+    pub fn all() -> Self {
+        Self::groups(BoneGroup::ALL)
+    }
+
+    pub fn groups(groups: impl IntoIterator<Item = BoneGroup>) -> Self {
+        groups
+            .into_iter()
+            .flat_map(|group| group.bones().iter().copied())
+            .collect()
+    }
+
+    /// Spine, head, arms and fingers, without the hips.
+    pub fn upper_body() -> Self {
+        Self::groups([
+            BoneGroup::Spine,
+            BoneGroup::Head,
+            BoneGroup::Arms,
+            BoneGroup::Fingers,
+        ])
+        .without(BoneName::Hips)
+    }
+
+    /// Hips and legs.
+    pub fn lower_body() -> Self {
+        Self::groups([BoneGroup::Legs]).with(BoneName::Hips)
+    }
+
+    pub fn with(mut self, bone: BoneName) -> Self {
+        self.0 |= Self::bit(bone);
+        self
+    }
+
+    pub fn without(mut self, bone: BoneName) -> Self {
+        self.0 &= !Self::bit(bone);
+        self
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    /// Every bone not in this mask.
+    pub fn inverse(self) -> Self {
+        Self(Self::all().0 & !self.0)
+    }
+
+    pub fn contains(&self, bone: BoneName) -> bool {
+        self.0 & Self::bit(bone) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn bones(&self) -> impl Iterator<Item = BoneName> + '_ {
+        BoneGroup::ALL
+            .into_iter()
+            .flat_map(|group| group.bones().iter().copied())
+            .filter(|bone| self.contains(*bone))
+    }
+
+    /// Whether a target is a bone within the mask.
+    pub fn contains_target(&self, target: &AnimationTargetId) -> bool {
+        VRM_ANIMATION_BONES
+            .get(target)
+            .is_some_and(|bone| self.contains(*bone))
+    }
+
+    /// Copies the curves of the bones in the mask.
+    pub fn mask_clip(&self, clip: &AnimationClip) -> AnimationClip {
+        let mut out = AnimationClip::default();
+
+        for (target, curves) in clip.curves() {
+            if self.contains_target(target) {
+                for curve in curves {
+                    out.add_curve_to_target(*target, curve.clone());
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Splits a clip into the curves of bones in the mask, and every other curve,
+    /// including targets that are not bones.
+    pub fn split_clip(&self, clip: &AnimationClip) -> (AnimationClip, AnimationClip) {
+        let mut inside = AnimationClip::default();
+        let mut outside = AnimationClip::default();
+
+        for (target, curves) in clip.curves() {
+            let out = if self.contains_target(target) {
+                &mut inside
+            } else {
+                &mut outside
+            };
+
+            for curve in curves {
+                out.add_curve_to_target(*target, curve.clone());
+            }
+        }
+
+        (inside, outside)
+    }
+}
+
+impl FromIterator<BoneName> for BoneMask {
+    fn from_iter<T: IntoIterator<Item = BoneName>>(iter: T) -> Self {
+        iter.into_iter().fold(Self::NONE, Self::with)
+    }
+}
+
+impl From<BoneGroup> for BoneMask {
+    fn from(group: BoneGroup) -> Self {
+        Self::groups([group])
+    }
+}
+
+/// Adds masked humanoid clips to an [AnimationGraph].
+pub trait HumanoidGraphExt {
+    /// Adds a copy of `clip` with only the bones in `mask`.
+    fn add_masked_clip(
+        &mut self,
+        clips: &mut Assets<AnimationClip>,
+        clip: &AnimationClip,
+        mask: BoneMask,
+        weight: f32,
+        parent: AnimationNodeIndex,
+    ) -> AnimationNodeIndex;
+}
+
+impl HumanoidGraphExt for AnimationGraph {
+    fn add_masked_clip(
+        &mut self,
+        clips: &mut Assets<AnimationClip>,
+        clip: &AnimationClip,
+        mask: BoneMask,
+        weight: f32,
+        parent: AnimationNodeIndex,
+    ) -> AnimationNodeIndex {
+        let handle = clips.add(mask.mask_clip(clip));
+        self.add_clip(handle, weight, parent)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect, Serialize, Deserialize)]
+pub enum LayerBlend {
+    /// Blends from the pose below towards the layer by its weight.
+    #[default]
+    Override,
+    /// Adds the difference between the layer and its first frame to the pose below.
+    Additive,
+}
+
+/// A clip playing within a [HumanoidLayer].
+#[derive(Clone, Debug, Reflect)]
+pub struct LayerClip {
+    pub clip: Handle<AnimationClip>,
+    pub time: f32,
+    pub speed: f32,
+    pub repeat: bool,
+    pub weight: f32,
+    /// Change in weight per second, used for crossfades.
+    pub fade: f32,
+}
+
+impl LayerClip {
+    pub fn new(clip: Handle<AnimationClip>) -> Self {
+        Self {
+            clip,
+            time: 0.0,
+            speed: 1.0,
+            repeat: true,
+            weight: 1.0,
+            fade: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Reflect)]
+pub struct HumanoidLayer {
+    pub mask: BoneMask,
+    pub blend: LayerBlend,
+    pub weight: f32,
+    /// Change in layer weight per second, until reaching [Self::target_weight].
+    pub fade: f32,
+    pub target_weight: f32,
+    pub clips: Vec<LayerClip>,
+}
+
+impl HumanoidLayer {
+    pub fn new(mask: BoneMask, blend: LayerBlend) -> Self {
+        Self {
+            mask,
+            blend,
+            weight: 1.0,
+            fade: 0.0,
+            target_weight: 1.0,
+            clips: Vec::new(),
+        }
+    }
+
+    /// Plays a clip, fading out the other clips of the layer over `duration` seconds.
+    pub fn play(&mut self, clip: Handle<AnimationClip>, duration: f32) -> &mut LayerClip {
+        if duration <= 0.0 {
+            self.clips.clear();
+        } else {
+            for other in self.clips.iter_mut() {
+                other.fade = -1.0 / duration;
+            }
+        }
+
+        let mut layer_clip = LayerClip::new(clip);
+
+        if duration > 0.0 {
+            layer_clip.weight = 0.0;
+            layer_clip.fade = 1.0 / duration;
+        }
+
+        self.clips.push(layer_clip);
+        self.clips.last_mut().unwrap()
+    }
+
+    /// Fades the weight of the layer to `weight` over `duration` seconds.
+    pub fn fade_to(&mut self, weight: f32, duration: f32) {
+        self.target_weight = weight;
+
+        if duration <= 0.0 {
+            self.weight = weight;
+            self.fade = 0.0;
+        } else {
+            self.fade = (weight - self.weight).abs() / duration;
+        }
+    }
+
+    fn advance(&mut self, delta: f32, clips: &Assets<AnimationClip>) {
+        let step = self.fade * delta;
+        self.weight = if self.weight < self.target_weight {
+            (self.weight + step).min(self.target_weight)
+        } else {
+            (self.weight - step).max(self.target_weight)
+        };
+
+        for layer_clip in self.clips.iter_mut() {
+            layer_clip.weight = (layer_clip.weight + layer_clip.fade * delta).clamp(0.0, 1.0);
+            layer_clip.time += layer_clip.speed * delta;
+
+            let duration = clips
+                .get(&layer_clip.clip)
+                .map(|c| c.duration())
+                .unwrap_or_default();
+
+            if layer_clip.repeat && duration > 0.0 {
+                layer_clip.time = layer_clip.time.rem_euclid(duration);
+            }
+        }
+
+        self.clips.retain(|c| !(c.fade < 0.0 && c.weight <= 0.0));
+    }
+
+    /// Samples the blended local transform of a bone, and the reference used by
+    /// additive blending.
+    fn sample(
+        &self,
+        bone: BoneName,
+        base: Transform,
+        clips: &Assets<AnimationClip>,
+    ) -> Option<(Transform, Transform)> {
+        let target = VRM_ANIMATION_TARGETS.get(&bone)?;
+
+        let mut total = 0.0;
+        let mut sampled = base;
+        let mut reference = base;
+
+        for layer_clip in self.clips.iter() {
+            if layer_clip.weight <= 0.0 {
+                continue;
+            }
+
+            let Some(curves) = clips
+                .get(&layer_clip.clip)
+                .and_then(|clip| clip.curves_for_target(*target))
+            else {
+                continue;
+            };
+
+            let value = sample_transform(curves, layer_clip.time, base);
+            let first = sample_transform(curves, 0.0, base);
+
+            total += layer_clip.weight;
+            let t = layer_clip.weight / total;
+
+            sampled.translation = sampled.translation.lerp(value.translation, t);
+            sampled.rotation = sampled.rotation.slerp(value.rotation, t);
+            reference.translation = reference.translation.lerp(first.translation, t);
+            reference.rotation = reference.rotation.slerp(first.rotation, t);
+        }
+
+        if total == 0.0 {
+            return None;
+        }
+
+        // Weights below 1 in total, such as a lone clip fading in or out, blend with the base.
+        if total < 1.0 {
+            sampled.translation = base.translation.lerp(sampled.translation, total);
+            sampled.rotation = base.rotation.slerp(sampled.rotation, total);
+            reference.translation = base.translation.lerp(reference.translation, total);
+            reference.rotation = base.rotation.slerp(reference.rotation, total);
+        }
+
+        Some((sampled, reference))
+    }
+}
+
+/// Animation layers of a VRM avatar, applied in order after the [AnimationPlayer].
+/// Insert on the root entity of a VRM scene with a [HumanoidRig].
+///
+/// Layers blend with the animated pose each frame, so bones that nothing else animates
+/// keep their rest pose as the base.
+#[derive(Component, Clone, Debug, Default, Reflect)]
+#[reflect(Component)]
+pub struct HumanoidLayers(pub Vec<HumanoidLayer>);
+
+/// Bone transforms written by [HumanoidLayers] last frame, with the transforms they replaced.
+/// Inserted on the root entity of a VRM scene.
+#[derive(Component, Default)]
+pub(crate) struct LayersWritten(HashMap<Entity, (Transform, Transform)>);
+
+pub(crate) fn apply_humanoid_layers(
+    mut commands: Commands,
+    time: Res<Time>,
+    clips: Res<Assets<AnimationClip>>,
+    mut written: Query<&mut LayersWritten>,
+    mut avatars: Query<(Entity, &HumanoidRig, &mut HumanoidLayers)>,
+    mut transforms: Query<&mut Transform>,
+) {
+    let delta = time.delta_seconds();
+
+    // Undo last frame's layers on bones that were not written since, so layers blend
+    // with the animated pose instead of their own result.
+    for mut written in written.iter_mut() {
+        for (entity, (written, original)) in written.0.drain() {
+            if let Ok(mut transform) = transforms.get_mut(entity) {
+                if *transform == written {
+                    *transform = original;
+                }
+            }
+        }
+    }
+
+    for (root, rig, mut layers) in avatars.iter_mut() {
+        let original = layers
+            .0
+            .iter()
+            .fold(BoneMask::NONE, |mask, layer| mask.union(layer.mask))
+            .bones()
+            .filter_map(|bone| {
+                let entity = *rig.bones.get(&bone)?;
+                Some((entity, *transforms.get(entity).ok()?))
+            })
+            .collect::<Vec<_>>();
+
+        for layer in layers.0.iter_mut() {
+            layer.advance(delta, &clips);
+
+            if layer.weight <= 0.0 {
+                continue;
+            }
+
+            for bone in layer.mask.bones() {
+                let Some(entity) = rig.bones.get(&bone) else {
+                    continue;
+                };
+
+                let Ok(mut transform) = transforms.get_mut(*entity) else {
+                    continue;
+                };
+
+                let Some((sampled, reference)) = layer.sample(bone, *transform, &clips) else {
+                    continue;
+                };
+
+                match layer.blend {
+                    LayerBlend::Override => {
+                        transform.translation = transform
+                            .translation
+                            .lerp(sampled.translation, layer.weight);
+                        transform.rotation =
+                            transform.rotation.slerp(sampled.rotation, layer.weight);
+                    }
+                    LayerBlend::Additive => {
+                        let rotation = reference.rotation.inverse() * sampled.rotation;
+                        transform.rotation *= Quat::IDENTITY.slerp(rotation, layer.weight);
+                        transform.translation +=
+                            (sampled.translation - reference.translation) * layer.weight;
+                    }
+                }
+            }
+        }
+
+        let changed = original
+            .into_iter()
+            .filter_map(|(entity, original)| {
+                let transform = *transforms.get(entity).ok()?;
+                (transform != original).then_some((entity, (transform, original)))
+            })
+            .collect::<HashMap<_, _>>();
+
+        match written.get_mut(root) {
+            Ok(mut written) => written.0 = changed,
+            Err(_) if !changed.is_empty() => {
+                commands.entity(root).insert(LayersWritten(changed));
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::{
+        animation::{Interpolation, Keyframes, VariableCurve},
+        ecs::system::RunSystemOnce,
+    };
+
+    use super::*;
+    use crate::animations::retarget::HumanoidRestPose;
+
+    fn rotation_clip(bone: BoneName, rotations: Vec<Quat>) -> AnimationClip {
+        let mut clip = AnimationClip::default();
+        clip.add_curve_to_target(
+            VRM_ANIMATION_TARGETS[&bone],
+            VariableCurve {
+                keyframe_timestamps: (0..rotations.len()).map(|i| i as f32).collect(),
+                keyframes: Keyframes::Rotation(rotations),
+                interpolation: Interpolation::Linear,
+            },
+        );
+        clip
+    }
+
+    fn setup() -> (World, Entity, Entity, Entity) {
+        let mut world = World::new();
+        world.insert_resource(Time::<()>::default());
+        world.insert_resource(Assets::<AnimationClip>::default());
+
+        let spine = world.spawn(TransformBundle::default()).id();
+        let arm = world.spawn(TransformBundle::default()).id();
+
+        let mut bones = HashMap::default();
+        bones.insert(BoneName::Spine, spine);
+        bones.insert(BoneName::LeftUpperArm, arm);
+
+        let root = world
+            .spawn(HumanoidRig {
+                bones,
+                rest: HumanoidRestPose::default(),
+            })
+            .id();
+
+        (world, root, spine, arm)
+    }
+
+    fn advance(world: &mut World, seconds: f32) {
+        world
+            .resource_mut::<Time>()
+            .advance_by(Duration::from_secs_f32(seconds));
+        world.run_system_once(apply_humanoid_layers);
+    }
+
+    #[test]
+    fn masks() {
+        let mask = BoneMask::from(BoneGroup::Arms);
+        assert!(mask.contains(BoneName::LeftHand));
+        assert!(!mask.contains(BoneName::LeftThumbProximal));
+        assert_eq!(mask.bones().count(), 8);
+
+        assert_eq!(BoneMask::all().bones().count(), 55);
+        assert_eq!(
+            BoneMask::upper_body().union(BoneMask::lower_body()),
+            BoneMask::all()
+        );
+        assert_eq!(BoneMask::upper_body().inverse(), BoneMask::lower_body());
+        assert_eq!(BoneGroup::of(BoneName::RightToes), BoneGroup::Legs);
+
+        let clip = rotation_clip(BoneName::LeftUpperArm, vec![Quat::IDENTITY]);
+        let (inside, outside) = mask.split_clip(&clip);
+        assert_eq!(inside.curves().len(), 1);
+        assert!(outside.curves().is_empty());
+        assert!(BoneMask::lower_body().mask_clip(&clip).curves().is_empty());
+    }
+
+    #[test]
+    fn masked_graph_nodes() {
+        let mut clips = Assets::<AnimationClip>::default();
+        let clip = rotation_clip(BoneName::LeftUpperArm, vec![Quat::IDENTITY]);
+
+        let mut graph = AnimationGraph::new();
+        let root = graph.root;
+        let node = graph.add_masked_clip(&mut clips, &clip, BoneGroup::Legs.into(), 1.0, root);
+
+        let handle = graph.get(node).unwrap().clip.clone().unwrap();
+        assert!(clips.get(&handle).unwrap().curves().is_empty());
+    }
+
+    #[test]
+    fn override_layer_respects_mask() {
+        let (mut world, root, spine, arm) = setup();
+
+        let raise = Quat::from_rotation_z(1.0);
+        let clip = world
+            .resource_mut::<Assets<AnimationClip>>()
+            .add(rotation_clip(BoneName::LeftUpperArm, vec![raise, raise]));
+        let spine_clip = world
+            .resource_mut::<Assets<AnimationClip>>()
+            .add(rotation_clip(BoneName::Spine, vec![raise, raise]));
+
+        let mut layer = HumanoidLayer::new(BoneGroup::Arms.into(), LayerBlend::Override);
+        layer.play(clip, 0.0);
+        layer.play(spine_clip, 0.0);
+
+        world.entity_mut(root).insert(HumanoidLayers(vec![layer]));
+
+        advance(&mut world, 0.1);
+
+        // Only the spine clip is playing, and the spine is not in the mask.
+        assert_eq!(
+            world.get::<Transform>(spine).unwrap().rotation,
+            Quat::IDENTITY
+        );
+        assert_eq!(
+            world.get::<Transform>(arm).unwrap().rotation,
+            Quat::IDENTITY
+        );
+
+        let clip = world
+            .resource_mut::<Assets<AnimationClip>>()
+            .add(rotation_clip(BoneName::LeftUpperArm, vec![raise, raise]));
+        world.get_mut::<HumanoidLayers>(root).unwrap().0[0].play(clip, 0.0);
+
+        advance(&mut world, 0.1);
+        assert!(world
+            .get::<Transform>(arm)
+            .unwrap()
+            .rotation
+            .abs_diff_eq(raise, 1e-5));
+    }
+
+    #[test]
+    fn lone_clip_fades_in_from_base() {
+        let (mut world, root, _, arm) = setup();
+
+        let raise = Quat::from_rotation_z(1.0);
+        let clip = world
+            .resource_mut::<Assets<AnimationClip>>()
+            .add(rotation_clip(BoneName::LeftUpperArm, vec![raise, raise]));
+
+        let mut layer = HumanoidLayer::new(BoneGroup::Arms.into(), LayerBlend::Override);
+        layer.play(clip, 1.0);
+
+        world.entity_mut(root).insert(HumanoidLayers(vec![layer]));
+
+        // Half way through the fade, half way from the base pose.
+        advance(&mut world, 0.5);
+        assert!(world
+            .get::<Transform>(arm)
+            .unwrap()
+            .rotation
+            .abs_diff_eq(Quat::from_rotation_z(0.5), 1e-5));
+    }
+
+    #[test]
+    fn crossfade_and_additive() {
+        let (mut world, root, _, arm) = setup();
+
+        let a = Quat::from_rotation_z(1.0);
+        let b = Quat::from_rotation_z(-1.0);
+
+        let mut assets = world.resource_mut::<Assets<AnimationClip>>();
+        let clip_a = assets.add(rotation_clip(BoneName::LeftUpperArm, vec![a, a]));
+        let clip_b = assets.add(rotation_clip(BoneName::LeftUpperArm, vec![b, b]));
+        let additive = assets.add(rotation_clip(
+            BoneName::LeftUpperArm,
+            vec![Quat::IDENTITY, a, a],
+        ));
+
+        let mut layer = HumanoidLayer::new(BoneGroup::Arms.into(), LayerBlend::Override);
+        layer.play(clip_a, 0.0);
+        layer.play(clip_b, 1.0);
+
+        world.entity_mut(root).insert(HumanoidLayers(vec![layer]));
+
+        // Halfway through the crossfade, both clips have the same weight.
+        advance(&mut world, 0.5);
+        assert!(world
+            .get::<Transform>(arm)
+            .unwrap()
+            .rotation
+            .abs_diff_eq(Quat::IDENTITY, 1e-5));
+
+        // The faded out clip is removed.
+        advance(&mut world, 0.6);
+        assert_eq!(
+            world.get::<HumanoidLayers>(root).unwrap().0[0].clips.len(),
+            1
+        );
+
+        // Additive layers add rotation relative to their first frame.
+        world.get_mut::<Transform>(arm).unwrap().rotation = Quat::IDENTITY;
+        let mut layers = world.get_mut::<HumanoidLayers>(root).unwrap();
+        layers.0.clear();
+        let mut layer = HumanoidLayer::new(BoneGroup::Arms.into(), LayerBlend::Additive);
+        layer.play(additive, 0.0).time = 1.0;
+        layer.fade_to(0.5, 0.0);
+        layers.0.push(layer);
+
+        advance(&mut world, 0.0);
+        assert!(world
+            .get::<Transform>(arm)
+            .unwrap()
+            .rotation
+            .abs_diff_eq(Quat::from_rotation_z(0.5), 1e-5));
+    }
+
+    #[test]
+    fn blend_with_unanimated_pose() {
+        let (mut world, root, spine, arm) = setup();
+
+        let raise = Quat::from_rotation_z(1.0);
+        let mut assets = world.resource_mut::<Assets<AnimationClip>>();
+        let spine_clip = assets.add(rotation_clip(BoneName::Spine, vec![raise, raise]));
+        let additive = assets.add(rotation_clip(
+            BoneName::LeftUpperArm,
+            vec![Quat::IDENTITY, raise, raise],
+        ));
+
+        let mut over = HumanoidLayer::new(BoneGroup::Spine.into(), LayerBlend::Override);
+        over.play(spine_clip, 0.0);
+        over.fade_to(0.5, 0.0);
+
+        let mut add = HumanoidLayer::new(BoneGroup::Arms.into(), LayerBlend::Additive);
+        add.play(additive, 0.0).time = 1.0;
+        add.fade_to(0.5, 0.0);
+
+        world
+            .entity_mut(root)
+            .insert(HumanoidLayers(vec![over, add]));
+
+        // Nothing animates the bones, so the layers must not build on last frame's result.
+        for _ in 0..3 {
+            advance(&mut world, 0.0);
+            let half = Quat::from_rotation_z(0.5);
+            assert!(world
+                .get::<Transform>(spine)
+                .unwrap()
+                .rotation
+                .abs_diff_eq(half, 1e-5));
+            assert!(world
+                .get::<Transform>(arm)
+                .unwrap()
+                .rotation
+                .abs_diff_eq(half, 1e-5));
+        }
+
+        world.entity_mut(root).remove::<HumanoidLayers>();
+        advance(&mut world, 0.0);
+        assert_eq!(
+            world.get::<Transform>(spine).unwrap().rotation,
+            Quat::IDENTITY
+        );
+        assert_eq!(
+            world.get::<Transform>(arm).unwrap().rotation,
+            Quat::IDENTITY
+        );
+    }
+}
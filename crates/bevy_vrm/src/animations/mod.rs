@@ -1,2 +1,20 @@
+pub mod bone_mapping;
+pub mod bvh;
+pub mod expressions;
+pub mod hand;
+pub mod humanoid;
+pub mod idle;
+pub mod ik;
+pub mod layers;
+pub mod params;
+#[cfg(feature = "vrm1")]
+pub mod recorder;
+pub mod replication;
+pub mod rest_pose;
+pub mod retarget;
+pub mod root_motion;
+pub mod sampling;
 pub mod target_chain;
 pub mod vrm;
+#[cfg(feature = "vrm1")]
+pub mod vrma;
@@ -0,0 +1,297 @@
+//! Humanoid avatar parameters from VRM 0.0 files.
+//!
+//! VRM 0.0 stores the avatar settings of Unity's Mecanim: how far limbs may stretch when
+//! solving IK, how the roll of the hands and feet is spread along the limbs, and the
+//! spacing of the feet.
+
+use bevy::{prelude::*, utils::HashMap};
+use gltf_kun_vrm::vrm0::weight::Humanoid;
+use serde_vrm::vrm0::BoneName;
+
+use crate::animations::humanoid::HumanoidRig;
+
+/// Avatar parameters of a VRM humanoid, with the defaults of Mecanim.
+/// Inserted on the root entity of the VRM scene.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Reflect)]
+#[reflect(Component)]
+pub struct HumanoidParams {
+    /// Fraction the arms may stretch beyond their length to reach an IK target.
+    pub arm_stretch: f32,
+    /// Fraction the legs may stretch beyond their length to reach an IK target.
+    pub leg_stretch: f32,
+    /// Fraction of the lower arm's roll that is moved to the upper arm.
+    pub upper_arm_twist: f32,
+    /// Fraction of the hand's roll that is moved to the lower arm.
+    pub lower_arm_twist: f32,
+    /// Fraction of the lower leg's roll that is moved to the upper leg.
+    pub upper_leg_twist: f32,
+    /// Fraction of the foot's roll that is moved to the lower leg.
+    pub lower_leg_twist: f32,
+    /// Minimum sideways distance between planted feet, in meters.
+    pub feet_spacing: f32,
+}
+
+impl Default for HumanoidParams {
+    fn default() -> Self {
+        Self {
+            arm_stretch: 0.05,
+            leg_stretch: 0.05,
+            upper_arm_twist: 0.5,
+            lower_arm_twist: 0.5,
+            upper_leg_twist: 0.5,
+            lower_leg_twist: 0.5,
+            feet_spacing: 0.0,
+        }
+    }
+}
+
+impl From<&Humanoid> for HumanoidParams {
+    fn from(value: &Humanoid) -> Self {
+        let default = Self::default();
+
+        Self {
+            arm_stretch: value.arm_stretch.unwrap_or(default.arm_stretch),
+            leg_stretch: value.leg_stretch.unwrap_or(default.leg_stretch),
+            upper_arm_twist: value.upper_arm_twist.unwrap_or(default.upper_arm_twist),
+            lower_arm_twist: value.lower_arm_twist.unwrap_or(default.lower_arm_twist),
+            upper_leg_twist: value.upper_leg_twist.unwrap_or(default.upper_leg_twist),
+            lower_leg_twist: value.lower_leg_twist.unwrap_or(default.lower_leg_twist),
+            feet_spacing: value.feet_spacing.unwrap_or(default.feet_spacing),
+        }
+    }
+}
+
+impl HumanoidParams {
+    /// Stretch limit of the limb ending in a bone.
+    pub fn stretch(&self, end: BoneName) -> f32 {
+        match end {
+            BoneName::LeftHand | BoneName::RightHand => self.arm_stretch,
+            _ => self.leg_stretch,
+        }
+    }
+}
+
+/// Twist component of a rotation around an axis.
+pub fn twist(rotation: Quat, axis: Vec3) -> Quat {
+    let projected = Vec3::new(rotation.x, rotation.y, rotation.z).project_onto_normalized(axis);
+    let twist = Quat::from_xyzw(projected.x, projected.y, projected.z, rotation.w);
+
+    if twist.length_squared() <= f32::EPSILON {
+        return Quat::IDENTITY;
+    }
+
+    let twist = twist.normalize();
+    if twist.w < 0.0 {
+        -twist
+    } else {
+        twist
+    }
+}
+
+/// Moves part of the twist of `child` onto `parent`, around the axis from `parent` to `child`.
+/// The world rotation of `child` is kept.
+fn move_twist(
+    rig: &HumanoidRig,
+    (parent, child): (BoneName, BoneName),
+    fraction: f32,
+    transforms: &mut Query<&mut Transform>,
+) {
+    if fraction <= 0.0 {
+        return;
+    }
+
+    let (Some(parent_entity), Some(child_entity)) = (rig.bones.get(&parent), rig.bones.get(&child))
+    else {
+        return;
+    };
+
+    let Some(axis) = rig
+        .rest
+        .bones
+        .get(&parent)
+        .zip(rig.rest.bones.get(&child))
+        .and_then(|(a, b)| (b.global.translation - a.global.translation).try_normalize())
+    else {
+        return;
+    };
+
+    let (Ok(parent_transform), Ok(child_transform)) = (
+        transforms.get(*parent_entity),
+        transforms.get(*child_entity),
+    ) else {
+        return;
+    };
+
+    let (Some(parent_normalized), Some(child_normalized)) = (
+        rig.rest
+            .normalize_rotation(parent, parent_transform.rotation),
+        rig.rest.normalize_rotation(child, child_transform.rotation),
+    ) else {
+        return;
+    };
+
+    let moved = Quat::IDENTITY.slerp(twist(child_normalized, axis), fraction);
+
+    let (Some(parent_rotation), Some(child_rotation)) = (
+        rig.rest
+            .denormalize_rotation(parent, parent_normalized * moved),
+        rig.rest
+            .denormalize_rotation(child, moved.inverse() * child_normalized),
+    ) else {
+        return;
+    };
+
+    if let Ok(mut transform) = transforms.get_mut(*parent_entity) {
+        transform.rotation = parent_rotation;
+    }
+    if let Ok(mut transform) = transforms.get_mut(*child_entity) {
+        transform.rotation = child_rotation;
+    }
+}
+
+/// Rotations written by [distribute_twist] last frame, with the rotations they replaced.
+/// Inserted on the root entity of a VRM scene.
+#[derive(Component, Default)]
+pub(crate) struct DistributedTwist(HashMap<Entity, (Quat, Quat)>);
+
+/// Removes the twist moved last frame from bones that were not written since,
+/// so the roll is only distributed once when nothing animates the limbs.
+/// Runs before anything else writes the pose of the frame.
+pub(crate) fn restore_twist(
+    mut avatars: Query<&mut DistributedTwist>,
+    mut transforms: Query<&mut Transform>,
+) {
+    for mut distributed in avatars.iter_mut() {
+        for (entity, (written, original)) in distributed.0.drain() {
+            if let Ok(mut transform) = transforms.get_mut(entity) {
+                if transform.rotation == written {
+                    transform.rotation = original;
+                }
+            }
+        }
+    }
+}
+
+/// Spreads the roll of the hands and feet along the limbs, following [HumanoidParams].
+/// VRM humanoids have no twist bones, so the roll is moved onto the limb bones themselves,
+/// which spreads the twisting of the skin across the whole limb.
+pub(crate) fn distribute_twist(
+    mut commands: Commands,
+    mut avatars: Query<(
+        Entity,
+        &HumanoidRig,
+        &HumanoidParams,
+        Option<&mut DistributedTwist>,
+    )>,
+    mut transforms: Query<&mut Transform>,
+) {
+    for (root, rig, params, distributed) in avatars.iter_mut() {
+        let mut written = HashMap::default();
+
+        for (upper, lower, end, upper_twist, lower_twist) in [
+            (
+                BoneName::LeftUpperArm,
+                BoneName::LeftLowerArm,
+                BoneName::LeftHand,
+                params.upper_arm_twist,
+                params.lower_arm_twist,
+            ),
+            (
+                BoneName::RightUpperArm,
+                BoneName::RightLowerArm,
+                BoneName::RightHand,
+                params.upper_arm_twist,
+                params.lower_arm_twist,
+            ),
+            (
+                BoneName::LeftUpperLeg,
+                BoneName::LeftLowerLeg,
+                BoneName::LeftFoot,
+                params.upper_leg_twist,
+                params.lower_leg_twist,
+            ),
+            (
+                BoneName::RightUpperLeg,
+                BoneName::RightLowerLeg,
+                BoneName::RightFoot,
+                params.upper_leg_twist,
+                params.lower_leg_twist,
+            ),
+        ] {
+            let original = [upper, lower, end].map(|bone| {
+                let entity = *rig.bones.get(&bone)?;
+                Some((entity, transforms.get(entity).ok()?.rotation))
+            });
+
+            move_twist(rig, (lower, end), lower_twist, &mut transforms);
+            move_twist(rig, (upper, lower), upper_twist, &mut transforms);
+
+            for (entity, original) in original.into_iter().flatten() {
+                if let Ok(transform) = transforms.get(entity) {
+                    if transform.rotation != original {
+                        written.insert(entity, (transform.rotation, original));
+                    }
+                }
+            }
+        }
+
+        match distributed {
+            Some(mut distributed) => distributed.0 = written,
+            None if !written.is_empty() => {
+                commands.entity(root).insert(DistributedTwist(written));
+            }
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animations::{humanoid::test_rig::spawn_flat_rig, retarget::HumanoidRestPose};
+
+    #[test]
+    fn spread_hand_roll() {
+        let mut world = World::new();
+        let rest = HumanoidRestPose::normalized([
+            (BoneName::LeftUpperArm, Vec3::new(0.2, 1.5, 0.0)),
+            (BoneName::LeftLowerArm, Vec3::new(0.5, 1.5, 0.0)),
+            (BoneName::LeftHand, Vec3::new(0.8, 1.5, 0.0)),
+        ]);
+
+        let (root, bones) = spawn_flat_rig(&mut world, rest);
+        world.entity_mut(root).insert(HumanoidParams::default());
+
+        // Rolled around the arm and bent up.
+        let hand_rotation = Quat::from_rotation_z(0.4) * Quat::from_rotation_x(1.0);
+        world
+            .get_mut::<Transform>(bones[&BoneName::LeftHand])
+            .unwrap()
+            .rotation = hand_rotation;
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems((restore_twist, distribute_twist).chain());
+        schedule.run(&mut world);
+
+        // Nothing writes the pose again, so the roll must not accumulate.
+        schedule.run(&mut world);
+
+        let rotation = |bone| world.get::<Transform>(bones[&bone]).unwrap().rotation;
+        let (upper, lower, hand) = (
+            rotation(BoneName::LeftUpperArm),
+            rotation(BoneName::LeftLowerArm),
+            rotation(BoneName::LeftHand),
+        );
+
+        // The world rotation of the hand is kept.
+        assert!((upper * lower * hand).abs_diff_eq(hand_rotation, 1e-5));
+
+        let roll = twist(hand_rotation, Vec3::X).to_axis_angle().1;
+        assert!((roll - 1.0).abs() < 1e-4);
+
+        // Half the roll moves to the lower arm, and half of that to the upper arm.
+        assert!(upper.abs_diff_eq(Quat::from_rotation_x(0.25), 1e-4));
+        assert!(lower.abs_diff_eq(Quat::from_rotation_x(0.25), 1e-4));
+        assert!(twist(hand, Vec3::X).abs_diff_eq(Quat::from_rotation_x(0.5), 1e-4));
+    }
+}
@@ -0,0 +1,574 @@
+//! Records the humanoid motion of a VRM avatar and exports it as a
+//! [VRM Animation](https://vrm.dev/en/vrma/) (`.vrma`) file.
+//!
+//! Poses are recorded as normalized rotations, and exported with a rest pose skeleton
+//! where every bone has an identity rotation, facing +Z as in VRM 1.0.
+
+use bevy::{prelude::*, utils::HashMap};
+use gltf_kun::{
+    graph::{
+        gltf::{
+            accessor::{ComponentType, Type},
+            animation::{AnimationSampler, Interpolation, TargetPath},
+            Accessor, Animation, Buffer, GltfDocument, Node,
+        },
+        ByteNode, Extensions, Graph, GraphNodeWeight,
+    },
+    io::format::glb::{GlbExport, GlbExportError},
+};
+use gltf_kun_vrm::vrm1::vrmc_vrm_animation::{
+    expression::{Expression, ExpressionWeight},
+    human_bone::{HumanBone, HumanBoneWeight},
+    VrmcVrmAnimation, VrmcVrmAnimationWeight,
+};
+use serde_vrm::vrm0::BoneName;
+use thiserror::Error;
+
+use crate::{
+    animations::{
+        humanoid::{humanoid_ancestor, HumanoidPose, HumanoidRig},
+        retarget::{to_vrm0_rotation, to_vrm0_vec, HumanoidRestPose},
+        vrma::{bone_name_to_vrm1, expression_name_to_vrm1},
+    },
+    expressions::{ExpressionName, VrmExpressions},
+};
+
+#[derive(Clone, Debug, Default, Reflect)]
+pub struct RecordedFrame {
+    pub time: f32,
+    pub pose: HumanoidPose,
+    pub expressions: HashMap<ExpressionName, f32>,
+}
+
+/// Records the pose and expressions of a VRM avatar at a fixed rate.
+/// Insert on the root entity of a VRM scene with a [HumanoidRig].
+#[derive(Component, Clone, Debug, Reflect)]
+#[reflect(Component)]
+pub struct VrmaRecorder {
+    pub recording: bool,
+    /// Samples per second.
+    pub frame_rate: f32,
+    /// Keys that can be linearly interpolated from their neighbours within this tolerance
+    /// are removed when exporting, in radians for rotations and meters for translations.
+    pub tolerance: f32,
+    elapsed: f32,
+    rest: HumanoidRestPose,
+    frames: Vec<RecordedFrame>,
+}
+
+impl Default for VrmaRecorder {
+    fn default() -> Self {
+        Self {
+            recording: false,
+            frame_rate: 30.0,
+            tolerance: 1e-3,
+            elapsed: 0.0,
+            rest: HumanoidRestPose::default(),
+            frames: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum VrmaExportError {
+    #[error("No frames recorded")]
+    NoFrames,
+    #[error("Failed to export glb: {0}")]
+    Export(#[from] GlbExportError),
+}
+
+impl VrmaRecorder {
+    /// Clears previous frames and starts recording.
+    pub fn start(&mut self) {
+        self.clear();
+        self.recording = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.recording = false;
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.elapsed = 0.0;
+    }
+
+    pub fn frames(&self) -> &[RecordedFrame] {
+        &self.frames
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.frames.last().map(|f| f.time).unwrap_or_default()
+    }
+
+    /// Advances the recording by `delta` seconds, adding a frame for each sample time passed.
+    pub fn record(
+        &mut self,
+        delta: f32,
+        rest: &HumanoidRestPose,
+        pose: &HumanoidPose,
+        expressions: &HashMap<ExpressionName, f32>,
+    ) {
+        if self.frames.is_empty() {
+            self.rest = rest.clone();
+        } else {
+            self.elapsed += delta;
+        }
+
+        while self.frames.len() as f32 / self.frame_rate <= self.elapsed {
+            self.frames.push(RecordedFrame {
+                time: self.frames.len() as f32 / self.frame_rate,
+                pose: pose.clone(),
+                expressions: expressions.clone(),
+            });
+        }
+    }
+
+    /// Exports the recording as a `.vrma` file.
+    pub fn export(&self) -> Result<Vec<u8>, VrmaExportError> {
+        if self.frames.is_empty() {
+            return Err(VrmaExportError::NoFrames);
+        }
+
+        let mut graph = Graph::new();
+        let doc = GltfDocument::new(&mut graph);
+        let buffer = doc.create_buffer(&mut graph);
+
+        let mut scene = doc.create_scene(&mut graph);
+        scene.get_mut(&mut graph).name = Some("Scene".to_string());
+        doc.set_default_scene(&mut graph, Some(scene));
+
+        let vrma = VrmcVrmAnimation::new(&mut graph);
+        vrma.write(
+            &mut graph,
+            &VrmcVrmAnimationWeight {
+                spec_version: Some("1.0".to_string()),
+            },
+        );
+        doc.add_extension(&mut graph, vrma);
+
+        // Rest pose skeleton.
+        let mut bones = self.rest.bones.keys().copied().collect::<Vec<_>>();
+        bones.sort_by_key(|b| *b as u8);
+
+        let mut nodes = HashMap::<BoneName, Node>::default();
+
+        for bone in bones.iter() {
+            let mut node = doc.create_node(&mut graph);
+            let weight = node.get_mut(&mut graph);
+            weight.name = Some(bone_name_to_vrm1(*bone));
+            nodes.insert(*bone, node);
+
+            let human_bone = HumanBone::new(&mut graph);
+            human_bone.write(
+                &mut graph,
+                &HumanBoneWeight {
+                    name: bone_name_to_vrm1(*bone),
+                },
+            );
+            human_bone.set_node(&mut graph, Some(node));
+            vrma.add_human_bone(&mut graph, human_bone);
+        }
+
+        for bone in bones.iter() {
+            let mut node = nodes[bone];
+            let global = self.rest.bones[bone].global.translation;
+
+            let parent = humanoid_ancestor(*bone, |b| nodes.contains_key(&b));
+            let translation = match parent {
+                Some(parent) => {
+                    nodes[&parent].add_child(&mut graph, &node);
+                    global - self.rest.bones[&parent].global.translation
+                }
+                None => {
+                    scene.add_node(&mut graph, node);
+                    global
+                }
+            };
+
+            node.get_mut(&mut graph).translation = to_vrm0_vec(translation);
+        }
+
+        let animation = doc.create_animation(&mut graph);
+        let times = self.frames.iter().map(|f| f.time).collect::<Vec<_>>();
+
+        for bone in bones.iter() {
+            let rotations = self
+                .frames
+                .iter()
+                .map(|f| f.pose.rotation(*bone).unwrap_or_default())
+                .collect::<Vec<_>>();
+
+            let (times, rotations) = reduce_keys(
+                &times,
+                &rotations,
+                self.tolerance,
+                |a, b, t| a.slerp(*b, t),
+                |a, b| a.angle_between(*b),
+            );
+
+            let rotations = rotations
+                .into_iter()
+                .map(|r| to_vrm0_rotation(r).to_array())
+                .collect::<Vec<_>>();
+
+            add_channel(
+                &mut graph,
+                doc,
+                buffer,
+                animation,
+                nodes[bone],
+                TargetPath::Rotation,
+                &times,
+                (Type::Vec4, &rotations),
+            );
+        }
+
+        if let Some(hips) = nodes.get(&BoneName::Hips) {
+            let rest = self.rest.bones[&BoneName::Hips].global.translation;
+            let translations = self
+                .frames
+                .iter()
+                .map(|f| f.pose.hips_translation.unwrap_or(rest))
+                .collect::<Vec<_>>();
+
+            let (times, translations) = reduce_keys(
+                &times,
+                &translations,
+                self.tolerance,
+                |a, b, t| a.lerp(*b, t),
+                |a, b| a.distance(*b),
+            );
+
+            let translations = translations
+                .into_iter()
+                .map(|t| to_vrm0_vec(t).to_array())
+                .collect::<Vec<_>>();
+
+            add_channel(
+                &mut graph,
+                doc,
+                buffer,
+                animation,
+                *hips,
+                TargetPath::Translation,
+                &times,
+                (Type::Vec3, &translations),
+            );
+        }
+
+        let mut expressions = self
+            .frames
+            .iter()
+            .flat_map(|f| f.expressions.keys())
+            .cloned()
+            .collect::<Vec<_>>();
+        expressions.sort_by_key(|e| e.to_string());
+        expressions.dedup();
+
+        for name in expressions {
+            let (vrm1_name, is_preset) = expression_name_to_vrm1(&name);
+
+            let mut node = doc.create_node(&mut graph);
+            node.get_mut(&mut graph).name = Some(vrm1_name.clone());
+            scene.add_node(&mut graph, node);
+
+            let expression = Expression::new(&mut graph);
+            expression.write(
+                &mut graph,
+                &ExpressionWeight {
+                    name: vrm1_name,
+                    is_preset,
+                },
+            );
+            expression.set_node(&mut graph, Some(node));
+            vrma.add_expression(&mut graph, expression);
+
+            // Expression weights are stored in the X translation of their node.
+            let weights = self
+                .frames
+                .iter()
+                .map(|f| f.expressions.get(&name).copied().unwrap_or_default())
+                .collect::<Vec<_>>();
+
+            let (times, weights) = reduce_keys(
+                &times,
+                &weights,
+                self.tolerance,
+                |a, b, t| a + (b - a) * t,
+                |a, b| (a - b).abs(),
+            );
+
+            let translations = weights
+                .into_iter()
+                .map(|w| [w, 0.0, 0.0])
+                .collect::<Vec<_>>();
+
+            add_channel(
+                &mut graph,
+                doc,
+                buffer,
+                animation,
+                node,
+                TargetPath::Translation,
+                &times,
+                (Type::Vec3, &translations),
+            );
+        }
+
+        let glb = GlbExport::<VrmcVrmAnimation>::export(&mut graph, &doc)?;
+
+        Ok(glb.0)
+    }
+}
+
+/// Removes keys that can be interpolated from the surrounding keys within `tolerance`.
+/// A channel that does not change is reduced to a single key.
+pub fn reduce_keys<T: Clone>(
+    times: &[f32],
+    values: &[T],
+    tolerance: f32,
+    interpolate: impl Fn(&T, &T, f32) -> T,
+    distance: impl Fn(&T, &T) -> f32,
+) -> (Vec<f32>, Vec<T>) {
+    let Some(first) = values.first() else {
+        return (Vec::new(), Vec::new());
+    };
+
+    if values.iter().all(|v| distance(first, v) <= tolerance) {
+        return (vec![times[0]], vec![first.clone()]);
+    }
+
+    let mut kept = vec![0];
+
+    for i in 1..values.len() - 1 {
+        let start = *kept.last().unwrap();
+        let end = i + 1;
+
+        // Whether every key since the last kept key lies on the segment to the next key.
+        let removable = (start + 1..=i).all(|j| {
+            let t = (times[j] - times[start]) / (times[end] - times[start]);
+            distance(&interpolate(&values[start], &values[end], t), &values[j]) <= tolerance
+        });
+
+        if !removable {
+            kept.push(i);
+        }
+    }
+
+    kept.push(values.len() - 1);
+
+    (
+        kept.iter().map(|i| times[*i]).collect(),
+        kept.iter().map(|i| values[*i].clone()).collect(),
+    )
+}
+
+fn create_accessor(
+    graph: &mut Graph,
+    doc: GltfDocument,
+    buffer: Buffer,
+    element_type: Type,
+    data: Vec<u8>,
+) -> Accessor {
+    let mut accessor = doc.create_accessor(graph);
+    accessor.set_buffer(graph, Some(buffer));
+
+    let weight = accessor.get_mut(graph);
+    weight.component_type = ComponentType::F32;
+    weight.element_type = element_type;
+    weight.data = data;
+
+    accessor
+}
+
+#[allow(clippy::too_many_arguments)]
+fn add_channel<const N: usize>(
+    graph: &mut Graph,
+    doc: GltfDocument,
+    buffer: Buffer,
+    animation: Animation,
+    node: Node,
+    path: TargetPath,
+    times: &[f32],
+    (element_type, values): (Type, &[[f32; N]]),
+) {
+    let input = create_accessor(
+        graph,
+        doc,
+        buffer,
+        Type::Scalar,
+        times.iter().flat_map(|t| t.to_le_bytes()).collect(),
+    );
+    let output = create_accessor(
+        graph,
+        doc,
+        buffer,
+        element_type,
+        values
+            .iter()
+            .flatten()
+            .flat_map(|v| v.to_le_bytes())
+            .collect(),
+    );
+
+    let mut sampler = AnimationSampler::new(graph);
+    sampler.get_mut(graph).interpolation = Interpolation::Linear;
+    sampler.set_input(graph, Some(input));
+    sampler.set_output(graph, Some(output));
+
+    let mut channel = animation.create_channel(graph);
+    channel.get_mut(graph).path = path;
+    channel.set_sampler(graph, Some(sampler));
+    channel.set_target(graph, Some(node));
+}
+
+/// Records the pose of avatars with a [VrmaRecorder].
+pub(crate) fn record_vrma(
+    time: Res<Time>,
+    mut avatars: Query<(&HumanoidRig, &mut VrmaRecorder, Option<&VrmExpressions>)>,
+    transforms: Query<&Transform>,
+) {
+    for (rig, mut recorder, expressions) in avatars.iter_mut() {
+        if !recorder.recording {
+            continue;
+        }
+
+        let pose = rig.read_pose(&transforms);
+        let expressions = expressions
+            .map(|e| {
+                e.0.iter()
+                    .map(|e| (e.name.clone(), e.applied_weight()))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        recorder.record(time.delta_seconds(), &rig.rest, &pose, &expressions);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::animation::{AnimationTargetId, Keyframes};
+    use gltf_kun::io::format::glb::GlbImport;
+
+    use super::*;
+    use crate::{
+        animations::{
+            expressions::expression_target_id, vrm::VRM_ANIMATION_TARGETS, vrma::import_vrma,
+        },
+        expressions::PresetName,
+    };
+
+    #[test]
+    fn reduce_static_and_linear_keys() {
+        let times = [0.0, 1.0, 2.0, 3.0, 4.0];
+
+        let (t, v) = reduce_keys(
+            &times,
+            &[1.0; 5],
+            1e-3,
+            |a, b, t| a + (b - a) * t,
+            |a, b| (a - b).abs(),
+        );
+        assert_eq!((t, v), (vec![0.0], vec![1.0]));
+
+        let values = [0.0, 1.0, 2.0, 3.0, 0.0];
+        let (t, v) = reduce_keys(
+            &times,
+            &values,
+            1e-3,
+            |a, b, t| a + (b - a) * t,
+            |a, b| (a - b).abs(),
+        );
+        assert_eq!((t, v), (vec![0.0, 3.0, 4.0], vec![0.0, 3.0, 0.0]));
+    }
+
+    #[test]
+    fn export_round_trip() {
+        let rest = HumanoidRestPose::normalized([
+            (BoneName::Hips, Vec3::new(0.0, 1.0, 0.0)),
+            (BoneName::Spine, Vec3::new(0.0, 1.2, 0.0)),
+            (BoneName::Chest, Vec3::new(0.0, 1.4, 0.0)),
+            (BoneName::LeftUpperArm, Vec3::new(0.2, 1.5, 0.0)),
+        ]);
+
+        let mut recorder = VrmaRecorder {
+            frame_rate: 10.0,
+            ..default()
+        };
+        recorder.start();
+
+        for i in 0..10 {
+            let mut pose = HumanoidPose {
+                hips_translation: Some(Vec3::new(0.0, 1.0, -0.1 * i as f32)),
+                ..default()
+            };
+            pose.set_rotation(
+                BoneName::LeftUpperArm,
+                Quat::from_rotation_z(0.1 * i as f32),
+            );
+            pose.set_rotation(BoneName::Spine, Quat::IDENTITY);
+
+            let mut expressions = HashMap::default();
+            expressions.insert(PresetName::Joy.into(), 0.5);
+
+            recorder.record(0.1, &rest, &pose, &expressions);
+        }
+
+        assert_eq!(recorder.frames().len(), 10);
+        assert!((recorder.duration() - 0.9).abs() < 1e-5);
+
+        let bytes = recorder.export().unwrap();
+
+        // Optional fields are left out, the schema has no null values.
+        let length = u32::from_le_bytes(bytes[12..16].try_into().unwrap()) as usize;
+        let json = std::str::from_utf8(&bytes[20..20 + length]).unwrap();
+        assert!(json.contains("VRMC_vrm_animation"));
+        assert!(!json.contains("null"), "{}", json);
+
+        let mut graph = Graph::new();
+        let doc = bevy::tasks::block_on(GlbImport::<VrmcVrmAnimation>::import_slice(
+            &mut graph, &bytes,
+        ))
+        .unwrap();
+        let (clip, imported_rest) = import_vrma(&graph, doc).unwrap();
+
+        for bone in [BoneName::Hips, BoneName::LeftUpperArm] {
+            assert!(imported_rest.bones[&bone]
+                .global
+                .translation
+                .abs_diff_eq(rest.bones[&bone].global.translation, 1e-5));
+        }
+
+        let curves = |target: AnimationTargetId| clip.curves_for_target(target).unwrap();
+
+        // Linear motion is reduced to the first and last keys.
+        let arm = &curves(VRM_ANIMATION_TARGETS[&BoneName::LeftUpperArm])[0];
+        assert_eq!(arm.keyframe_timestamps, vec![0.0, 0.9]);
+        let Keyframes::Rotation(rotations) = &arm.keyframes else {
+            panic!("Expected rotations");
+        };
+        assert!(rotations[1].abs_diff_eq(Quat::from_rotation_z(0.9), 1e-5));
+
+        // Static channels are reduced to one key.
+        let spine = &curves(VRM_ANIMATION_TARGETS[&BoneName::Spine])[0];
+        assert_eq!(spine.keyframe_timestamps.len(), 1);
+
+        let hips = curves(VRM_ANIMATION_TARGETS[&BoneName::Hips]);
+        let Some(Keyframes::Translation(translations)) = hips
+            .iter()
+            .map(|c| &c.keyframes)
+            .find(|k| matches!(k, Keyframes::Translation(_)))
+        else {
+            panic!("Expected hips translation");
+        };
+        assert!(translations
+            .last()
+            .unwrap()
+            .abs_diff_eq(Vec3::new(0.0, 1.0, -0.9), 1e-5));
+
+        let joy = &curves(expression_target_id(&PresetName::Joy.into()))[0];
+        assert!(matches!(&joy.keyframes, Keyframes::Weights(w) if w == &[0.5]));
+    }
+}
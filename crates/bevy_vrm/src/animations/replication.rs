@@ -0,0 +1,929 @@
+//! Pose replication for networked VRM avatars.
+//!
+//! The sender captures a [HumanoidPoseFrame] of its avatar each network tick and encodes it,
+//! either as a full frame or as a delta against a frame the receiver already has.
+//! The receiver decodes frames into a [PoseReplica], which plays them back with a small
+//! delay, interpolating between frames and extrapolating when frames arrive late.
+
+use std::collections::VecDeque;
+
+use bevy::{ecs::system::RunSystemOnce, prelude::*, utils::HashMap};
+use serde_vrm::vrm0::BoneName;
+use thiserror::Error;
+
+use crate::{
+    animations::{
+        humanoid::{HumanoidPose, HumanoidRig},
+        layers::BoneMask,
+    },
+    expressions::{ExpressionName, VrmExpressions},
+    look_at::{LookAtTarget, VrmLookAt},
+};
+
+/// Pose of an avatar at a point in time, for sending over the network.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HumanoidPoseFrame {
+    /// Increases with each captured frame, used to reference the base of delta frames.
+    pub sequence: u32,
+    /// Time of the sender when the frame was captured, in seconds.
+    pub time: f32,
+    /// Normalized rotation of each bone.
+    pub rotations: HashMap<BoneName, Quat>,
+    /// Position of the hips in model space.
+    pub hips_translation: Option<Vec3>,
+    pub expressions: HashMap<ExpressionName, f32>,
+    /// World space point the avatar is looking at.
+    pub look_at: Option<Vec3>,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum PoseFrameError {
+    #[error("Frame data ended unexpectedly")]
+    UnexpectedEnd,
+    #[error("Unknown bone index {0}")]
+    InvalidBone(u8),
+    #[error("Invalid expression name")]
+    InvalidName,
+    #[error("Delta frame needs base frame {0}")]
+    MissingBase(u32),
+}
+
+const DELTA: u8 = 1;
+const HIPS: u8 = 1 << 1;
+const HIPS_CLEARED: u8 = 1 << 2;
+const LOOK_AT: u8 = 1 << 3;
+const LOOK_AT_CLEARED: u8 = 1 << 4;
+
+/// Hips positions are sent in millimeters.
+const HIPS_SCALE: f32 = 1000.0;
+
+fn bone_index(bone: BoneName) -> Option<u8> {
+    BoneMask::all()
+        .bones()
+        .position(|b| b == bone)
+        .map(|i| i as u8)
+}
+
+fn bone_from_index(index: u8) -> Option<BoneName> {
+    BoneMask::all().bones().nth(index as usize)
+}
+
+/// Packs a unit quaternion into 32 bits, storing the three smallest components
+/// in 10 bits each and the index of the largest in 2 bits.
+pub fn quantize_rotation(rotation: Quat) -> u32 {
+    let components = rotation.normalize().to_array();
+
+    let (largest, _) = components
+        .iter()
+        .enumerate()
+        .fold((0, -1.0), |(index, max), (i, c)| {
+            if c.abs() > max {
+                (i, c.abs())
+            } else {
+                (index, max)
+            }
+        });
+
+    let sign = components[largest].signum();
+    let mut packed = (largest as u32) << 30;
+    let mut shift = 20;
+
+    for (i, c) in components.iter().enumerate() {
+        if i == largest {
+            continue;
+        }
+
+        let normalized = (c * sign * std::f32::consts::SQRT_2 + 1.0) * 0.5;
+        let value = (normalized.clamp(0.0, 1.0) * 1023.0).round() as u32;
+        packed |= value << shift;
+        shift -= 10;
+    }
+
+    packed
+}
+
+pub fn dequantize_rotation(packed: u32) -> Quat {
+    let largest = (packed >> 30) as usize;
+    let mut components = [0.0; 4];
+    let mut shift = 20;
+    let mut sum = 0.0;
+
+    for (i, c) in components.iter_mut().enumerate() {
+        if i == largest {
+            continue;
+        }
+
+        let value = ((packed >> shift) & 1023) as f32 / 1023.0;
+        *c = (value * 2.0 - 1.0) / std::f32::consts::SQRT_2;
+        sum += *c * *c;
+        shift -= 10;
+    }
+
+    components[largest] = (1.0 - sum).max(0.0).sqrt();
+
+    Quat::from_array(components).normalize()
+}
+
+fn quantize_translation(translation: Vec3) -> [i16; 3] {
+    translation.to_array().map(|v| {
+        (v * HIPS_SCALE)
+            .round()
+            .clamp(i16::MIN as f32, i16::MAX as f32) as i16
+    })
+}
+
+fn dequantize_translation(values: [i16; 3]) -> Vec3 {
+    Vec3::from_array(values.map(|v| v as f32 / HIPS_SCALE))
+}
+
+/// Writes an expression name, prefixed with its length.
+/// Names longer than 255 bytes are not sent.
+fn write_name(bytes: &mut Vec<u8>, name: &str) {
+    bytes.push(name.len() as u8);
+    bytes.extend(name.as_bytes());
+}
+
+fn quantize_weight(weight: f32) -> u8 {
+    (weight.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], PoseFrameError> {
+        if self.0.len() < N {
+            return Err(PoseFrameError::UnexpectedEnd);
+        }
+
+        let (bytes, rest) = self.0.split_at(N);
+        self.0 = rest;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, PoseFrameError> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, PoseFrameError> {
+        Ok(u32::from_le_bytes(self.bytes()?))
+    }
+
+    fn f32(&mut self) -> Result<f32, PoseFrameError> {
+        Ok(f32::from_le_bytes(self.bytes()?))
+    }
+
+    fn u16(&mut self) -> Result<u16, PoseFrameError> {
+        Ok(u16::from_le_bytes(self.bytes()?))
+    }
+
+    fn i16(&mut self) -> Result<i16, PoseFrameError> {
+        Ok(i16::from_le_bytes(self.bytes()?))
+    }
+
+    fn str(&mut self) -> Result<&'a str, PoseFrameError> {
+        let len = self.u8()? as usize;
+
+        if self.0.len() < len {
+            return Err(PoseFrameError::UnexpectedEnd);
+        }
+
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        std::str::from_utf8(bytes).map_err(|_| PoseFrameError::InvalidName)
+    }
+}
+
+impl HumanoidPoseFrame {
+    /// Captures the current pose of an avatar.
+    pub fn capture(
+        sequence: u32,
+        time: f32,
+        rig: &HumanoidRig,
+        transforms: &Query<&Transform>,
+        expressions: Option<&VrmExpressions>,
+        look_at: Option<Vec3>,
+    ) -> Self {
+        let pose = rig.read_pose(transforms);
+
+        Self {
+            sequence,
+            time,
+            rotations: pose.rotations,
+            hips_translation: pose.hips_translation,
+            expressions: expressions
+                .map(|e| e.0.iter().map(|e| (e.name.clone(), e.weight)).collect())
+                .unwrap_or_default(),
+            look_at,
+        }
+    }
+
+    /// Encodes the frame with every value.
+    pub fn encode(&self) -> Vec<u8> {
+        self.encode_inner(None)
+    }
+
+    /// Encodes only the values that changed since `base`, and the bones and expressions
+    /// that were removed.
+    /// The receiver must have `base` to decode the frame, such as the last frame it acknowledged.
+    pub fn encode_delta(&self, base: &Self) -> Vec<u8> {
+        self.encode_inner(Some(base))
+    }
+
+    fn encode_inner(&self, base: Option<&Self>) -> Vec<u8> {
+        let hips = self.hips_translation.map(quantize_translation);
+        let base_hips = base.and_then(|b| b.hips_translation.map(quantize_translation));
+
+        let mut flags = 0;
+
+        if base.is_some() {
+            flags |= DELTA;
+        }
+
+        if hips.is_some() && (base.is_none() || hips != base_hips) {
+            flags |= HIPS;
+        } else if hips.is_none() && base_hips.is_some() {
+            flags |= HIPS_CLEARED;
+        }
+
+        let base_look_at = base.and_then(|b| b.look_at);
+        if self.look_at.is_some() && (base.is_none() || self.look_at != base_look_at) {
+            flags |= LOOK_AT;
+        } else if self.look_at.is_none() && base_look_at.is_some() {
+            flags |= LOOK_AT_CLEARED;
+        }
+
+        let mut bytes = vec![flags];
+        bytes.extend(self.sequence.to_le_bytes());
+
+        if let Some(base) = base {
+            bytes.extend(base.sequence.to_le_bytes());
+        }
+
+        bytes.extend(self.time.to_le_bytes());
+
+        let mut rotations = self
+            .rotations
+            .iter()
+            .filter_map(|(bone, rotation)| {
+                let index = bone_index(*bone)?;
+                let packed = quantize_rotation(*rotation);
+                let unchanged = base
+                    .and_then(|b| b.rotations.get(bone))
+                    .is_some_and(|r| quantize_rotation(*r) == packed);
+
+                (!unchanged).then_some((index, packed))
+            })
+            .collect::<Vec<_>>();
+        rotations.sort_by_key(|(index, _)| *index);
+
+        let mut removed_bones = base
+            .iter()
+            .flat_map(|b| b.rotations.keys())
+            .filter(|bone| !self.rotations.contains_key(*bone))
+            .filter_map(|bone| bone_index(*bone))
+            .collect::<Vec<_>>();
+        removed_bones.sort();
+
+        // Bone indices fit in a byte, so there are never more than 255 bones to count.
+        bytes.push(rotations.len() as u8);
+        for (index, packed) in rotations {
+            bytes.push(index);
+            bytes.extend(packed.to_le_bytes());
+        }
+
+        bytes.push(removed_bones.len() as u8);
+        bytes.extend(removed_bones);
+
+        if flags & HIPS != 0 {
+            for v in hips.unwrap_or_default() {
+                bytes.extend(v.to_le_bytes());
+            }
+        }
+
+        let mut expressions = self
+            .expressions
+            .iter()
+            .map(|(name, weight)| (name.to_string(), quantize_weight(*weight)))
+            .filter(|(name, weight)| {
+                let base = base.map(|b| {
+                    b.expressions
+                        .get(&ExpressionName::from_name(name))
+                        .copied()
+                        .map(quantize_weight)
+                });
+
+                base.is_none() || base.flatten() != Some(*weight)
+            })
+            .filter(|(name, _)| name.len() <= u8::MAX as usize)
+            .take(u16::MAX as usize)
+            .collect::<Vec<_>>();
+        expressions.sort();
+
+        let mut removed_expressions = base
+            .iter()
+            .flat_map(|b| b.expressions.keys())
+            .filter(|name| !self.expressions.contains_key(*name))
+            .map(|name| name.to_string())
+            .filter(|name| name.len() <= u8::MAX as usize)
+            .take(u16::MAX as usize)
+            .collect::<Vec<_>>();
+        removed_expressions.sort();
+
+        bytes.extend((expressions.len() as u16).to_le_bytes());
+        for (name, weight) in expressions {
+            write_name(&mut bytes, &name);
+            bytes.push(weight);
+        }
+
+        bytes.extend((removed_expressions.len() as u16).to_le_bytes());
+        for name in removed_expressions {
+            write_name(&mut bytes, &name);
+        }
+
+        if flags & LOOK_AT != 0 {
+            for v in self.look_at.unwrap_or_default().to_array() {
+                bytes.extend(v.to_le_bytes());
+            }
+        }
+
+        bytes
+    }
+
+    /// Sequence of the base frame needed to decode a delta frame.
+    pub fn base_sequence(bytes: &[u8]) -> Result<Option<u32>, PoseFrameError> {
+        let mut reader = Reader(bytes);
+        let flags = reader.u8()?;
+        reader.u32()?;
+
+        if flags & DELTA != 0 {
+            Ok(Some(reader.u32()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Decodes a frame. Delta frames are applied on top of `base`.
+    pub fn decode(bytes: &[u8], base: Option<&Self>) -> Result<Self, PoseFrameError> {
+        let mut reader = Reader(bytes);
+
+        let flags = reader.u8()?;
+        let sequence = reader.u32()?;
+
+        let mut frame = if flags & DELTA != 0 {
+            let base_sequence = reader.u32()?;
+
+            match base {
+                Some(base) if base.sequence == base_sequence => base.clone(),
+                _ => return Err(PoseFrameError::MissingBase(base_sequence)),
+            }
+        } else {
+            Self::default()
+        };
+
+        frame.sequence = sequence;
+        frame.time = reader.f32()?;
+
+        for _ in 0..reader.u8()? {
+            let index = reader.u8()?;
+            let bone = bone_from_index(index).ok_or(PoseFrameError::InvalidBone(index))?;
+            frame
+                .rotations
+                .insert(bone, dequantize_rotation(reader.u32()?));
+        }
+
+        for _ in 0..reader.u8()? {
+            let index = reader.u8()?;
+            let bone = bone_from_index(index).ok_or(PoseFrameError::InvalidBone(index))?;
+            frame.rotations.remove(&bone);
+        }
+
+        if flags & HIPS != 0 {
+            frame.hips_translation = Some(dequantize_translation([
+                reader.i16()?,
+                reader.i16()?,
+                reader.i16()?,
+            ]));
+        } else if flags & HIPS_CLEARED != 0 {
+            frame.hips_translation = None;
+        }
+
+        for _ in 0..reader.u16()? {
+            let name = ExpressionName::from_name(reader.str()?);
+            let weight = reader.u8()? as f32 / 255.0;
+            frame.expressions.insert(name, weight);
+        }
+
+        for _ in 0..reader.u16()? {
+            let name = ExpressionName::from_name(reader.str()?);
+            frame.expressions.remove(&name);
+        }
+
+        if flags & LOOK_AT != 0 {
+            frame.look_at = Some(Vec3::new(reader.f32()?, reader.f32()?, reader.f32()?));
+        } else if flags & LOOK_AT_CLEARED != 0 {
+            frame.look_at = None;
+        }
+
+        Ok(frame)
+    }
+
+    /// The frame as it is received after encoding.
+    pub fn quantized(&self) -> Self {
+        Self {
+            sequence: self.sequence,
+            time: self.time,
+            rotations: self
+                .rotations
+                .iter()
+                .map(|(bone, r)| (*bone, dequantize_rotation(quantize_rotation(*r))))
+                .collect(),
+            hips_translation: self
+                .hips_translation
+                .map(|t| dequantize_translation(quantize_translation(t))),
+            expressions: self
+                .expressions
+                .iter()
+                .map(|(name, w)| (name.clone(), quantize_weight(*w) as f32 / 255.0))
+                .collect(),
+            look_at: self.look_at,
+        }
+    }
+
+    /// Interpolates between two frames, or extrapolates past `other` when `t` is above 1.
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        let rotations = other
+            .rotations
+            .iter()
+            .map(|(bone, b)| {
+                let rotation = match self.rotations.get(bone) {
+                    Some(a) if t <= 1.0 => a.slerp(*b, t),
+                    Some(a) => {
+                        // Decoded rotations may be in opposite hemispheres.
+                        let a = if a.dot(*b) < 0.0 { -*a } else { *a };
+                        let (axis, angle) = (a.inverse() * *b).to_axis_angle();
+                        *b * Quat::from_axis_angle(axis, angle * (t - 1.0))
+                    }
+                    None => *b,
+                };
+
+                (*bone, rotation)
+            })
+            .collect();
+
+        let hips_translation = match (self.hips_translation, other.hips_translation) {
+            (Some(a), Some(b)) => Some(a.lerp(b, t)),
+            (_, b) => b,
+        };
+
+        // Expressions are held once past the last frame.
+        let expressions = other
+            .expressions
+            .iter()
+            .map(|(name, b)| {
+                let a = self.expressions.get(name).copied().unwrap_or_default();
+                (name.clone(), a + (b - a) * t.min(1.0))
+            })
+            .collect();
+
+        let look_at = match (self.look_at, other.look_at) {
+            (Some(a), Some(b)) => Some(a.lerp(b, t.min(1.0))),
+            (_, b) => b,
+        };
+
+        Self {
+            sequence: other.sequence,
+            time: self.time + (other.time - self.time) * t,
+            rotations,
+            hips_translation,
+            expressions,
+            look_at,
+        }
+    }
+}
+
+/// Plays back the pose of a remote avatar from received frames.
+/// Insert on the root entity of a VRM scene with a [HumanoidRig].
+#[derive(Component, Clone, Debug)]
+pub struct PoseReplica {
+    /// How far behind the newest frame playback runs, in seconds.
+    /// A larger delay hides more network jitter.
+    pub delay: f32,
+    /// How far past the newest frame the pose is extrapolated, in seconds.
+    pub max_extrapolation: f32,
+    /// Number of received frames kept, for interpolation and as bases of delta frames.
+    pub max_frames: usize,
+    frames: VecDeque<HumanoidPoseFrame>,
+    /// Time since the newest frame was received.
+    since_newest: f32,
+}
+
+impl Default for PoseReplica {
+    fn default() -> Self {
+        Self {
+            delay: 0.1,
+            max_extrapolation: 0.25,
+            max_frames: 32,
+            frames: VecDeque::new(),
+            since_newest: 0.0,
+        }
+    }
+}
+
+impl PoseReplica {
+    pub fn frames(&self) -> &VecDeque<HumanoidPoseFrame> {
+        &self.frames
+    }
+
+    pub fn newest(&self) -> Option<&HumanoidPoseFrame> {
+        self.frames.back()
+    }
+
+    /// Adds a received frame. Frames older than the newest frame are dropped.
+    pub fn push(&mut self, frame: HumanoidPoseFrame) {
+        if self
+            .newest()
+            .is_some_and(|newest| newest.time >= frame.time)
+        {
+            return;
+        }
+
+        self.frames.push_back(frame);
+        self.since_newest = 0.0;
+
+        while self.frames.len() > self.max_frames.max(2) {
+            self.frames.pop_front();
+        }
+    }
+
+    /// Decodes and adds a received frame, finding the base of delta frames in the kept frames.
+    pub fn receive(&mut self, bytes: &[u8]) -> Result<(), PoseFrameError> {
+        let base = match HumanoidPoseFrame::base_sequence(bytes)? {
+            Some(sequence) => Some(
+                self.frames
+                    .iter()
+                    .find(|f| f.sequence == sequence)
+                    .ok_or(PoseFrameError::MissingBase(sequence))?,
+            ),
+            None => None,
+        };
+
+        let frame = HumanoidPoseFrame::decode(bytes, base)?;
+        self.push(frame);
+
+        Ok(())
+    }
+
+    /// Sender time currently played back.
+    pub fn playback_time(&self) -> Option<f32> {
+        self.newest()
+            .map(|newest| newest.time + self.since_newest - self.delay)
+    }
+
+    /// Samples the received frames at a sender time.
+    pub fn sample(&self, time: f32) -> Option<HumanoidPoseFrame> {
+        let newest = self.newest()?;
+
+        if let Some(i) = self.frames.iter().position(|f| f.time >= time) {
+            if i == 0 {
+                return Some(self.frames[0].clone());
+            }
+
+            let (a, b) = (&self.frames[i - 1], &self.frames[i]);
+            return Some(a.lerp(b, (time - a.time) / (b.time - a.time)));
+        }
+
+        let Some(previous) = self.frames.iter().rev().nth(1) else {
+            return Some(newest.clone());
+        };
+
+        let time = time.min(newest.time + self.max_extrapolation);
+        let t = (time - previous.time) / (newest.time - previous.time);
+
+        Some(previous.lerp(newest, t))
+    }
+}
+
+/// Captures a [HumanoidPoseFrame] of an avatar, for use outside of systems.
+pub fn capture_pose_frame(
+    world: &mut World,
+    root: Entity,
+    sequence: u32,
+    time: f32,
+) -> Option<HumanoidPoseFrame> {
+    world.run_system_once_with(
+        (root, sequence, time),
+        |In((root, sequence, time)): In<(Entity, u32, f32)>,
+         avatars: Query<(&HumanoidRig, Option<&VrmExpressions>, Option<&VrmLookAt>)>,
+         transforms: Query<&Transform>,
+         globals: Query<&GlobalTransform>| {
+            let (rig, expressions, look_at) = avatars.get(root).ok()?;
+
+            let look_at = look_at
+                .and_then(|l| l.target)
+                .and_then(|target| match target {
+                    LookAtTarget::Point(point) => Some(point),
+                    LookAtTarget::Entity(entity) => {
+                        globals.get(entity).ok().map(|g| g.translation())
+                    }
+                });
+
+            Some(HumanoidPoseFrame::capture(
+                sequence,
+                time,
+                rig,
+                &transforms,
+                expressions,
+                look_at,
+            ))
+        },
+    )
+}
+
+/// Writes the sampled pose of each [PoseReplica] to its avatar.
+pub(crate) fn apply_pose_replicas(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut avatars: Query<(
+        Entity,
+        &mut PoseReplica,
+        Option<&mut HumanoidPose>,
+        Option<&mut VrmExpressions>,
+        Option<&mut VrmLookAt>,
+    )>,
+) {
+    for (entity, mut replica, pose, expressions, look_at) in avatars.iter_mut() {
+        replica.since_newest += time.delta_seconds();
+
+        let Some(frame) = replica
+            .playback_time()
+            .and_then(|time| replica.sample(time))
+        else {
+            continue;
+        };
+
+        let sampled = HumanoidPose {
+            rotations: frame.rotations,
+            hips_translation: frame.hips_translation,
+        };
+
+        match pose {
+            Some(mut pose) => *pose = sampled,
+            None => {
+                commands.entity(entity).insert(sampled);
+            }
+        }
+
+        if let Some(mut expressions) = expressions {
+            for (name, weight) in frame.expressions {
+                expressions.set_weight(name, weight);
+            }
+        }
+
+        if let Some(mut look_at) = look_at {
+            look_at.target = frame.look_at.map(LookAtTarget::Point);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::time::TimeUpdateStrategy;
+
+    use super::*;
+    use crate::{
+        animations::humanoid::{apply_humanoid_pose, test_rig::spawn_rig},
+        expressions::{Expression, PresetName},
+    };
+
+    #[test]
+    fn quantized_rotations() {
+        for rotation in [
+            Quat::IDENTITY,
+            Quat::from_rotation_x(0.3),
+            Quat::from_euler(EulerRot::YXZ, 2.0, -0.7, 1.2),
+            -Quat::from_rotation_z(3.0),
+        ] {
+            let decoded = dequantize_rotation(quantize_rotation(rotation));
+            assert!(decoded.angle_between(rotation) < 0.3f32.to_radians());
+        }
+    }
+
+    #[test]
+    fn delta_frames() {
+        let mut frame = HumanoidPoseFrame {
+            sequence: 1,
+            time: 0.5,
+            hips_translation: Some(Vec3::new(0.0, 1.0, 0.2)),
+            look_at: Some(Vec3::new(1.0, 1.5, -2.0)),
+            ..default()
+        };
+        for (i, bone) in BoneMask::all().bones().enumerate() {
+            frame
+                .rotations
+                .insert(bone, Quat::from_rotation_y(i as f32 * 0.01));
+        }
+        frame.expressions.insert(PresetName::Joy.into(), 0.5);
+        frame
+            .expressions
+            .insert(ExpressionName::Custom("wink".into()), 1.0);
+        frame.expressions.insert("blink".into(), 0.25);
+
+        let full = frame.encode();
+        let decoded = HumanoidPoseFrame::decode(&full, None).unwrap();
+        assert_eq!(decoded, frame.quantized());
+        assert!(decoded
+            .expressions
+            .contains_key(&ExpressionName::Preset(PresetName::Blink)));
+
+        let mut next = frame.clone();
+        next.sequence = 2;
+        next.time = 0.6;
+        next.rotations
+            .insert(BoneName::Head, Quat::from_rotation_x(0.2));
+        next.expressions.insert(PresetName::Joy.into(), 0.8);
+        next.look_at = None;
+
+        let delta = next.encode_delta(&frame);
+        assert!(delta.len() < full.len() / 10);
+
+        assert_eq!(
+            HumanoidPoseFrame::decode(&delta, None),
+            Err(PoseFrameError::MissingBase(1))
+        );
+        let decoded = HumanoidPoseFrame::decode(&delta, Some(&decoded)).unwrap();
+        assert_eq!(decoded, next.quantized());
+
+        // Removed bones and expressions are removed from the base.
+        let mut removed = next.clone();
+        removed.sequence = 3;
+        removed.rotations.remove(&BoneName::Head);
+        removed
+            .expressions
+            .remove(&ExpressionName::Custom("wink".into()));
+
+        let delta = removed.encode_delta(&next);
+        let decoded = HumanoidPoseFrame::decode(&delta, Some(&decoded)).unwrap();
+        assert_eq!(decoded, removed.quantized());
+    }
+
+    #[test]
+    fn many_expressions() {
+        let mut frame = HumanoidPoseFrame::default();
+        for i in 0..300 {
+            frame
+                .expressions
+                .insert(ExpressionName::Custom(format!("expression_{}", i)), 0.5);
+        }
+
+        let decoded = HumanoidPoseFrame::decode(&frame.encode(), None).unwrap();
+        assert_eq!(decoded, frame.quantized());
+    }
+
+    #[test]
+    fn interpolate_and_extrapolate() {
+        let frame = |sequence, time: f32| HumanoidPoseFrame {
+            sequence,
+            time,
+            rotations: [(BoneName::Head, Quat::from_rotation_y(time))]
+                .into_iter()
+                .collect(),
+            hips_translation: Some(Vec3::new(time, 1.0, 0.0)),
+            ..default()
+        };
+
+        let mut replica = PoseReplica::default();
+        replica.push(frame(1, 0.0));
+        replica.push(frame(2, 0.1));
+        // Late frames are dropped.
+        replica.push(frame(0, 0.05));
+        assert_eq!(replica.frames().len(), 2);
+
+        let sample = replica.sample(0.05).unwrap();
+        assert!(sample.rotations[&BoneName::Head].abs_diff_eq(Quat::from_rotation_y(0.05), 1e-5));
+
+        let sample = replica.sample(0.2).unwrap();
+        assert!(sample.rotations[&BoneName::Head].abs_diff_eq(Quat::from_rotation_y(0.2), 1e-5));
+        assert!(sample
+            .hips_translation
+            .unwrap()
+            .abs_diff_eq(Vec3::new(0.2, 1.0, 0.0), 1e-5));
+
+        // Extrapolation is limited.
+        let sample = replica.sample(5.0).unwrap();
+        assert!(sample.rotations[&BoneName::Head].abs_diff_eq(Quat::from_rotation_y(0.35), 1e-5));
+    }
+
+    #[test]
+    fn extrapolate_across_hemispheres() {
+        let frame = |sequence, time: f32, degrees: f32| {
+            HumanoidPoseFrame {
+                sequence,
+                time,
+                rotations: [(BoneName::Head, Quat::from_rotation_x(degrees.to_radians()))]
+                    .into_iter()
+                    .collect(),
+                ..default()
+            }
+            .quantized()
+        };
+
+        let (a, b) = (frame(1, 0.0, -88.0), frame(2, 0.1, -92.0));
+        assert!(a.rotations[&BoneName::Head].dot(b.rotations[&BoneName::Head]) < 0.0);
+
+        let mut replica = PoseReplica::default();
+        replica.push(a);
+        replica.push(b);
+
+        let sample = replica.sample(0.125).unwrap();
+        let expected = Quat::from_rotation_x(-93f32.to_radians());
+        assert!(sample.rotations[&BoneName::Head].angle_between(expected) < 0.5f32.to_radians());
+    }
+
+    fn avatar_app() -> (App, Entity, Entity) {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_millis(
+                50,
+            )))
+            .add_systems(
+                PostUpdate,
+                (apply_pose_replicas, apply_humanoid_pose).chain(),
+            );
+
+        let world = app.world_mut();
+        let (root, bones) = spawn_rig(
+            world,
+            &[
+                (BoneName::Hips, None, Vec3::new(0.0, 1.0, 0.0)),
+                (
+                    BoneName::Head,
+                    Some(BoneName::Hips),
+                    Vec3::new(0.0, 0.6, 0.0),
+                ),
+            ],
+        );
+        world
+            .entity_mut(root)
+            .insert(VrmExpressions(vec![Expression {
+                name: PresetName::Joy.into(),
+                is_binary: false,
+                binds: Vec::new(),
+                weight: 0.0,
+            }]));
+
+        (app, root, bones[&BoneName::Head])
+    }
+
+    #[test]
+    fn replicate_between_apps() {
+        let (mut sender, sender_root, sender_head) = avatar_app();
+        let (mut receiver, receiver_root, receiver_head) = avatar_app();
+
+        receiver
+            .world_mut()
+            .entity_mut(receiver_root)
+            .insert(PoseReplica::default());
+
+        let mut acked: Option<HumanoidPoseFrame> = None;
+
+        for i in 0..10 {
+            let time = i as f32 * 0.05;
+
+            let world = sender.world_mut();
+            world.get_mut::<Transform>(sender_head).unwrap().rotation = Quat::from_rotation_y(time);
+            world
+                .get_mut::<VrmExpressions>(sender_root)
+                .unwrap()
+                .set_weight(PresetName::Joy, time);
+            sender.update();
+
+            let frame = capture_pose_frame(sender.world_mut(), sender_root, i, time).unwrap();
+            let bytes = match &acked {
+                Some(base) => frame.encode_delta(base),
+                None => frame.encode(),
+            };
+            acked = Some(frame);
+
+            receiver
+                .world_mut()
+                .get_mut::<PoseReplica>(receiver_root)
+                .unwrap()
+                .receive(&bytes)
+                .unwrap();
+            receiver.update();
+        }
+
+        // Playback runs one frame behind the newest frame.
+        let world = receiver.world_mut();
+        let rotation = world.get::<Transform>(receiver_head).unwrap().rotation;
+        assert!(rotation.angle_between(Quat::from_rotation_y(0.4)) < 1e-2);
+
+        let joy = world
+            .get::<VrmExpressions>(receiver_root)
+            .unwrap()
+            .weight(PresetName::Joy);
+        assert!((joy - 0.4).abs() < 1e-2);
+    }
+}
@@ -0,0 +1,456 @@
+//! Reference poses and rest pose baking for VRM humanoids.
+//!
+//! VRM 0.0 avatars are modeled in a T-pose, though the angle of the arms varies between files.
+//! [ReferencePose] creates a T-pose or A-pose for any avatar, which can be inserted as a
+//! [HumanoidPose], and [rebake_rest_pose] makes the current pose the new rest pose of the avatar.
+
+use bevy::{
+    ecs::system::SystemState,
+    math::Affine3A,
+    prelude::*,
+    render::{
+        mesh::{
+            skinning::{SkinnedMesh, SkinnedMeshInverseBindposes},
+            VertexAttributeValues,
+        },
+        primitives::Aabb,
+    },
+};
+use serde_vrm::vrm0::BoneName;
+use thiserror::Error;
+
+use crate::animations::{
+    humanoid::{HumanoidPose, HumanoidRig},
+    retarget::HumanoidRestPose,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Reflect)]
+pub enum ReferencePose {
+    /// Arms pointing straight out to the sides.
+    TPose,
+    /// Arms lowered from the T-pose by an angle, in radians.
+    APose { arm_angle: f32 },
+}
+
+impl ReferencePose {
+    /// A-pose with the arms lowered by 45 degrees.
+    pub fn a_pose() -> Self {
+        Self::APose {
+            arm_angle: 45f32.to_radians(),
+        }
+    }
+
+    /// Normalized pose of an avatar in the reference pose.
+    /// Every bone is set to its rest rotation, with the arms straightened and rotated into place.
+    pub fn pose(&self, rest: &HumanoidRestPose) -> HumanoidPose {
+        let mut pose = HumanoidPose {
+            rotations: rest.bones.keys().map(|b| (*b, Quat::IDENTITY)).collect(),
+            hips_translation: rest
+                .bones
+                .get(&BoneName::Hips)
+                .map(|b| b.global.translation),
+        };
+
+        let arm_angle = match self {
+            Self::TPose => 0.0,
+            Self::APose { arm_angle } => *arm_angle,
+        };
+
+        let position = |bone| rest.bones.get(&bone).map(|b| b.global.translation);
+        let center = position(BoneName::Hips).unwrap_or_default();
+
+        for (upper, lower, hand) in [
+            (
+                BoneName::LeftUpperArm,
+                BoneName::LeftLowerArm,
+                BoneName::LeftHand,
+            ),
+            (
+                BoneName::RightUpperArm,
+                BoneName::RightLowerArm,
+                BoneName::RightHand,
+            ),
+        ] {
+            let (Some(a), Some(b), Some(c)) = (position(upper), position(lower), position(hand))
+            else {
+                continue;
+            };
+
+            let outward = Vec3::X * (a.x - center.x).signum();
+            let target = outward * arm_angle.cos() - Vec3::Y * arm_angle.sin();
+
+            let (Some(upper_dir), Some(lower_dir)) =
+                ((b - a).try_normalize(), (c - b).try_normalize())
+            else {
+                continue;
+            };
+
+            let upper_rotation = Quat::from_rotation_arc(upper_dir, target);
+            let lower_rotation = Quat::from_rotation_arc(lower_dir, target);
+
+            pose.set_rotation(upper, upper_rotation);
+            pose.set_rotation(lower, upper_rotation.inverse() * lower_rotation);
+        }
+
+        pose
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum RestPoseError {
+    #[error("Avatar has no HumanoidRig")]
+    MissingRig,
+    #[error("Mesh asset not found, it may have been unloaded from the main world")]
+    MissingMesh,
+    #[error("Inverse bindposes asset not found")]
+    MissingInverseBindposes,
+    #[error("Joint {0} has no inverse bindpose")]
+    InvalidJoint(usize),
+}
+
+/// Captures the current pose of an avatar's bones as a rest pose.
+pub fn capture_rest_pose(world: &mut World, root: Entity) -> HumanoidRestPose {
+    let mut state = SystemState::<(
+        Query<(Entity, &BoneName)>,
+        Query<(&Transform, Option<&Parent>)>,
+    )>::new(world);
+    let (bones, transforms) = state.get(world);
+
+    HumanoidRestPose::from_hierarchy(root, bones.iter().map(|(e, n)| (e, *n)), &transforms)
+}
+
+/// World space transform of an entity, computed from the [Transform] hierarchy,
+/// so it does not wait for transform propagation.
+fn global_transform(entity: Entity, transforms: &Query<(&Transform, Option<&Parent>)>) -> Affine3A {
+    let mut global = Affine3A::IDENTITY;
+    let mut current = Some(entity);
+
+    while let Some(e) = current {
+        let Ok((transform, parent)) = transforms.get(e) else {
+            break;
+        };
+
+        global = transform.compute_affine() * global;
+        current = parent.map(|p| p.get());
+    }
+
+    global
+}
+
+/// Makes the current pose of an avatar its rest pose.
+///
+/// Skinned meshes under `root` are deformed into the current pose and given new inverse
+/// bindposes, then the [HumanoidRig] rest pose is captured again. New mesh and bindpose assets
+/// are created, so meshes shared with other avatars are not changed. Their [Aabb] is updated
+/// to the baked vertices. Meshes without joint indices and weights are left as they are.
+/// Any [HumanoidPose] on the avatar is removed, as its rotations are relative to the old rest pose.
+///
+/// Morph targets are not deformed, so expressions may be slightly off on posed vertices.
+pub fn rebake_rest_pose(world: &mut World, root: Entity) -> Result<(), RestPoseError> {
+    if world.get::<HumanoidRig>(root).is_none() {
+        return Err(RestPoseError::MissingRig);
+    }
+
+    let mut state = SystemState::<(
+        Query<(Entity, &SkinnedMesh, &Handle<Mesh>)>,
+        Query<(&Transform, Option<&Parent>)>,
+        Query<&Parent>,
+    )>::new(world);
+    let (skins, transforms, parents) = state.get(world);
+
+    let skins = skins
+        .iter()
+        .filter(|(entity, ..)| parents.iter_ancestors(*entity).any(|e| e == root))
+        .map(|(entity, skin, mesh)| {
+            let joints = skin
+                .joints
+                .iter()
+                .map(|joint| Mat4::from(global_transform(*joint, &transforms)))
+                .collect::<Vec<_>>();
+
+            (entity, skin.inverse_bindposes.clone(), mesh.clone(), joints)
+        })
+        .collect::<Vec<_>>();
+
+    for (entity, bindposes, mesh, joints) in skins {
+        let inverse_bindposes = world
+            .resource::<Assets<SkinnedMeshInverseBindposes>>()
+            .get(&bindposes)
+            .ok_or(RestPoseError::MissingInverseBindposes)?;
+
+        let skinning = joints
+            .iter()
+            .enumerate()
+            .map(|(i, joint)| {
+                inverse_bindposes
+                    .get(i)
+                    .map(|inverse| *joint * *inverse)
+                    .ok_or(RestPoseError::InvalidJoint(i))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut mesh = world
+            .resource::<Assets<Mesh>>()
+            .get(&mesh)
+            .ok_or(RestPoseError::MissingMesh)?
+            .clone();
+
+        // Meshes that can't be baked keep their bindposes, which still deform them into the pose.
+        if !bake_skinning(&mut mesh, &skinning) {
+            continue;
+        }
+
+        let aabb = mesh.compute_aabb();
+        let inverse_bindposes = joints.iter().map(|j| j.inverse()).collect::<Vec<_>>();
+
+        let mesh = world.resource_mut::<Assets<Mesh>>().add(mesh);
+        let bindposes = world
+            .resource_mut::<Assets<SkinnedMeshInverseBindposes>>()
+            .add(SkinnedMeshInverseBindposes::from(inverse_bindposes));
+
+        let mut entity = world.entity_mut(entity);
+        entity.insert(mesh);
+        match aabb {
+            Some(aabb) => entity.insert(aabb),
+            None => entity.remove::<Aabb>(),
+        };
+        if let Some(mut skin) = entity.get_mut::<SkinnedMesh>() {
+            skin.inverse_bindposes = bindposes;
+        }
+    }
+
+    let rest = capture_rest_pose(world, root);
+
+    let mut entity = world.entity_mut(root);
+    entity.remove::<HumanoidPose>();
+    if let Some(mut rig) = entity.get_mut::<HumanoidRig>() {
+        rig.rest = rest;
+    }
+
+    Ok(())
+}
+
+/// Deforms the vertices of a mesh by its joint matrices.
+/// Returns false if the mesh has no joint indices and weights to bake.
+fn bake_skinning(mesh: &mut Mesh, skinning: &[Mat4]) -> bool {
+    let (
+        Some(VertexAttributeValues::Uint16x4(joints)),
+        Some(VertexAttributeValues::Float32x4(weights)),
+    ) = (
+        mesh.attribute(Mesh::ATTRIBUTE_JOINT_INDEX),
+        mesh.attribute(Mesh::ATTRIBUTE_JOINT_WEIGHT),
+    )
+    else {
+        return false;
+    };
+
+    let matrices = joints
+        .iter()
+        .zip(weights)
+        .map(|(joints, weights)| {
+            joints
+                .iter()
+                .zip(weights)
+                .filter_map(|(joint, weight)| Some(*skinning.get(*joint as usize)? * *weight))
+                .fold(Mat4::ZERO, |acc, m| acc + m)
+        })
+        .collect::<Vec<_>>();
+
+    if let Some(VertexAttributeValues::Float32x3(positions)) =
+        mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION)
+    {
+        for (position, matrix) in positions.iter_mut().zip(&matrices) {
+            *position = matrix.transform_point3(Vec3::from(*position)).into();
+        }
+    }
+
+    if let Some(VertexAttributeValues::Float32x3(normals)) =
+        mesh.attribute_mut(Mesh::ATTRIBUTE_NORMAL)
+    {
+        for (normal, matrix) in normals.iter_mut().zip(&matrices) {
+            let normal_matrix = Mat3::from_mat4(*matrix).inverse().transpose();
+            *normal = (normal_matrix * Vec3::from(*normal))
+                .normalize_or_zero()
+                .into();
+        }
+    }
+
+    if let Some(VertexAttributeValues::Float32x4(tangents)) =
+        mesh.attribute_mut(Mesh::ATTRIBUTE_TANGENT)
+    {
+        for (tangent, matrix) in tangents.iter_mut().zip(&matrices) {
+            let [x, y, z, w] = *tangent;
+            let t = matrix
+                .transform_vector3(Vec3::new(x, y, z))
+                .normalize_or_zero();
+            *tangent = [t.x, t.y, t.z, w];
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::{mesh::PrimitiveTopology, render_asset::RenderAssetUsages};
+
+    use super::*;
+    use crate::animations::humanoid::{test_rig::spawn_rig, write_humanoid_pose};
+
+    /// Avatar with the left arm lowered 30 degrees, and a mesh skinned to the lower arm.
+    fn setup(world: &mut World) -> (Entity, Entity, Entity) {
+        world.init_resource::<Assets<Mesh>>();
+        world.init_resource::<Assets<SkinnedMeshInverseBindposes>>();
+
+        let angle = -30f32.to_radians();
+        let dir = Vec3::new(angle.cos(), angle.sin(), 0.0);
+
+        let (root, bones) = spawn_rig(
+            world,
+            &[
+                (BoneName::Hips, None, Vec3::new(0.0, 1.0, 0.0)),
+                (
+                    BoneName::LeftUpperArm,
+                    Some(BoneName::Hips),
+                    Vec3::new(0.2, 0.5, 0.0),
+                ),
+                (
+                    BoneName::LeftLowerArm,
+                    Some(BoneName::LeftUpperArm),
+                    dir * 0.3,
+                ),
+                (BoneName::LeftHand, Some(BoneName::LeftLowerArm), dir * 0.3),
+            ],
+        );
+        let lower = bones[&BoneName::LeftLowerArm];
+
+        // Vertex at the end of the lower arm.
+        let lower_global = Vec3::new(0.2, 1.5, 0.0) + dir * 0.3;
+        let mesh = Mesh::new(PrimitiveTopology::PointList, RenderAssetUsages::default())
+            .with_inserted_attribute(
+                Mesh::ATTRIBUTE_POSITION,
+                vec![(lower_global + dir * 0.3).to_array()],
+            )
+            .with_inserted_attribute(
+                Mesh::ATTRIBUTE_JOINT_INDEX,
+                VertexAttributeValues::Uint16x4(vec![[0, 0, 0, 0]]),
+            )
+            .with_inserted_attribute(Mesh::ATTRIBUTE_JOINT_WEIGHT, vec![[1.0, 0.0, 0.0, 0.0]]);
+        let mesh = world.resource_mut::<Assets<Mesh>>().add(mesh);
+        let bindposes = world
+            .resource_mut::<Assets<SkinnedMeshInverseBindposes>>()
+            .add(SkinnedMeshInverseBindposes::from(vec![
+                Mat4::from_translation(-lower_global),
+            ]));
+
+        let skinned = world
+            .spawn((
+                mesh,
+                SkinnedMesh {
+                    inverse_bindposes: bindposes,
+                    joints: vec![lower],
+                },
+            ))
+            .set_parent(root)
+            .id();
+
+        (root, lower, skinned)
+    }
+
+    fn arm_direction(rest: &HumanoidRestPose, pose: &HumanoidPose) -> (Vec3, Vec3) {
+        let position = |bone| rest.bones[&bone].global.translation;
+        let upper = pose.rotations[&BoneName::LeftUpperArm];
+        let lower = upper * pose.rotations[&BoneName::LeftLowerArm];
+
+        (
+            upper * (position(BoneName::LeftLowerArm) - position(BoneName::LeftUpperArm)),
+            lower * (position(BoneName::LeftHand) - position(BoneName::LeftLowerArm)),
+        )
+    }
+
+    #[test]
+    fn reference_poses() {
+        let mut world = World::new();
+        let (root, ..) = setup(&mut world);
+        let rest = world.get::<HumanoidRig>(root).unwrap().rest.clone();
+
+        let (upper, lower) = arm_direction(&rest, &ReferencePose::TPose.pose(&rest));
+        assert!(upper.normalize().abs_diff_eq(Vec3::X, 1e-5));
+        assert!(lower.normalize().abs_diff_eq(Vec3::X, 1e-5));
+
+        let (upper, _) = arm_direction(&rest, &ReferencePose::a_pose().pose(&rest));
+        let expected = Vec3::new(1.0, -1.0, 0.0).normalize();
+        assert!(upper.normalize().abs_diff_eq(expected, 1e-5));
+    }
+
+    #[test]
+    fn rebake_t_pose() {
+        let mut world = World::new();
+        let (root, _, skinned) = setup(&mut world);
+
+        let rest = world.get::<HumanoidRig>(root).unwrap().rest.clone();
+        write_humanoid_pose(&mut world, root, ReferencePose::TPose.pose(&rest));
+        world.entity_mut(root).insert(HumanoidPose::default());
+
+        rebake_rest_pose(&mut world, root).unwrap();
+        assert!(world.get::<HumanoidPose>(root).is_none());
+
+        // The vertex follows the arm into the T-pose.
+        let mesh = world.get::<Handle<Mesh>>(skinned).unwrap();
+        let mesh = world.resource::<Assets<Mesh>>().get(mesh).unwrap();
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            panic!("Expected positions");
+        };
+        assert!(Vec3::from(positions[0]).abs_diff_eq(Vec3::new(0.8, 1.5, 0.0), 1e-5));
+
+        let aabb = world.get::<Aabb>(skinned).unwrap();
+        assert!(Vec3::from(aabb.center).abs_diff_eq(Vec3::new(0.8, 1.5, 0.0), 1e-5));
+
+        // The current pose is the new rest pose.
+        let rig = world.get::<HumanoidRig>(root).unwrap();
+        assert!(rig.rest.bones[&BoneName::LeftLowerArm]
+            .global
+            .translation
+            .abs_diff_eq(Vec3::new(0.5, 1.5, 0.0), 1e-5));
+
+        let skin = world.get::<SkinnedMesh>(skinned).unwrap();
+        let bindposes = world
+            .resource::<Assets<SkinnedMeshInverseBindposes>>()
+            .get(&skin.inverse_bindposes)
+            .unwrap();
+        let global = Mat4::from_translation(Vec3::new(0.5, 1.5, 0.0))
+            * Mat4::from_quat(rig.rest.bones[&BoneName::LeftLowerArm].global.rotation);
+        assert!((global * bindposes[0]).abs_diff_eq(Mat4::IDENTITY, 1e-5));
+    }
+
+    #[test]
+    fn rebake_skips_unweighted_meshes() {
+        let mut world = World::new();
+        let (root, _, skinned) = setup(&mut world);
+
+        let mesh = world.get::<Handle<Mesh>>(skinned).unwrap().clone();
+        world
+            .resource_mut::<Assets<Mesh>>()
+            .get_mut(&mesh)
+            .unwrap()
+            .remove_attribute(Mesh::ATTRIBUTE_JOINT_WEIGHT);
+        let bindposes = world
+            .get::<SkinnedMesh>(skinned)
+            .unwrap()
+            .inverse_bindposes
+            .clone();
+
+        let rest = world.get::<HumanoidRig>(root).unwrap().rest.clone();
+        write_humanoid_pose(&mut world, root, ReferencePose::TPose.pose(&rest));
+        rebake_rest_pose(&mut world, root).unwrap();
+
+        // The unbaked vertices keep the bindposes they were modeled with.
+        assert_eq!(world.get::<Handle<Mesh>>(skinned), Some(&mesh));
+        assert_eq!(
+            world.get::<SkinnedMesh>(skinned).unwrap().inverse_bindposes,
+            bindposes
+        );
+    }
+}
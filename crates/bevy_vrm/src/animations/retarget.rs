@@ -0,0 +1,427 @@
+//! Retargeting of humanoid animations between avatars with different rest poses.
+//!
+//! Rotations are converted through normalized bone space, as in the VRM 1.0 normalized
+//! humanoid: a normalized rotation is applied in model space, so the rest pose is
+//! the identity for every bone regardless of bone roll.
+//! Both rest poses must face the same direction.
+
+use bevy::{
+    animation::{AnimationTargetId, Interpolation, Keyframes, VariableCurve},
+    math::Affine3A,
+    prelude::*,
+    utils::HashMap,
+};
+use serde_vrm::vrm0::BoneName;
+
+use crate::animations::vrm::{VRM_ANIMATION_BONES, VRM_ANIMATION_TARGETS};
+
+/// Rotates 180 degrees around Y, from facing +Z to facing -Z.
+pub(crate) fn to_vrm0_rotation(q: Quat) -> Quat {
+    Quat::from_xyzw(-q.x, q.y, -q.z, q.w)
+}
+
+pub(crate) fn to_vrm0_vec(v: Vec3) -> Vec3 {
+    Vec3::new(-v.x, v.y, -v.z)
+}
+
+/// Rest transform of a humanoid bone.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Reflect)]
+pub struct RestBone {
+    /// Transform relative to the parent node.
+    pub local: Transform,
+    /// Transform in model space.
+    pub global: Transform,
+}
+
+impl RestBone {
+    /// Model space transform of the parent node.
+    pub fn parent_global(&self) -> Affine3A {
+        self.global.compute_affine() * self.local.compute_affine().inverse()
+    }
+}
+
+/// Rest pose of a humanoid, keyed by [BoneName].
+#[derive(Clone, Debug, Default, Reflect)]
+pub struct HumanoidRestPose {
+    pub bones: HashMap<BoneName, RestBone>,
+}
+
+impl HumanoidRestPose {
+    /// Creates a normalized rest pose from the model space position of each bone.
+    /// Every bone has an identity rotation, and the parent of the hips is the model root.
+    pub fn normalized(positions: impl IntoIterator<Item = (BoneName, Vec3)>) -> Self {
+        let bones = positions
+            .into_iter()
+            .map(|(name, position)| {
+                let transform = Transform::from_translation(position);
+
+                (
+                    name,
+                    RestBone {
+                        local: transform,
+                        global: transform,
+                    },
+                )
+            })
+            .collect();
+
+        Self { bones }
+    }
+
+    /// Captures the current pose of the bones under `root` as a rest pose.
+    /// The transform of `root` itself is not included.
+    pub fn from_hierarchy(
+        root: Entity,
+        bones: impl IntoIterator<Item = (Entity, BoneName)>,
+        transforms: &Query<(&Transform, Option<&Parent>)>,
+    ) -> Self {
+        let mut rest = Self::default();
+
+        for (entity, name) in bones {
+            let Ok((local, mut parent)) = transforms.get(entity) else {
+                continue;
+            };
+
+            let mut global = *local;
+            let mut in_root = false;
+
+            while let Some(p) = parent {
+                if p.get() == root {
+                    in_root = true;
+                    break;
+                }
+
+                let Ok((transform, next)) = transforms.get(p.get()) else {
+                    break;
+                };
+
+                global = *transform * global;
+                parent = next;
+            }
+
+            if in_root {
+                rest.bones.insert(
+                    name,
+                    RestBone {
+                        local: *local,
+                        global,
+                    },
+                );
+            }
+        }
+
+        rest
+    }
+
+    /// Rotates the model space of the rest pose, such as to turn a +Z facing model to face -Z.
+    pub fn rotated(mut self, rotation: Quat) -> Self {
+        for bone in self.bones.values_mut() {
+            bone.global = Transform::from_rotation(rotation) * bone.global;
+        }
+
+        self
+    }
+
+    /// Length of the leg, from the upper leg to the foot.
+    /// Falls back to the height of the hips if the legs are missing.
+    pub fn leg_length(&self) -> Option<f32> {
+        let position = |name| self.bones.get(&name).map(|b| b.global.translation);
+
+        let leg = |upper, lower, foot| {
+            let upper = position(upper)?;
+            let lower = position(lower)?;
+            let foot = position(foot)?;
+            Some(upper.distance(lower) + lower.distance(foot))
+        };
+
+        leg(
+            BoneName::LeftUpperLeg,
+            BoneName::LeftLowerLeg,
+            BoneName::LeftFoot,
+        )
+        .or_else(|| {
+            leg(
+                BoneName::RightUpperLeg,
+                BoneName::RightLowerLeg,
+                BoneName::RightFoot,
+            )
+        })
+        .or_else(|| position(BoneName::Hips).map(|p| p.y))
+        .filter(|length| *length > f32::EPSILON)
+    }
+
+    /// Converts a local rotation of a bone to normalized bone space.
+    pub fn normalize_rotation(&self, bone: BoneName, rotation: Quat) -> Option<Quat> {
+        let rest = self.bones.get(&bone)?;
+        let parent = rest.global.rotation * rest.local.rotation.inverse();
+        Some(parent * rotation * rest.global.rotation.inverse())
+    }
+
+    /// Converts a normalized rotation of a bone to a local rotation.
+    pub fn denormalize_rotation(&self, bone: BoneName, normalized: Quat) -> Option<Quat> {
+        let rest = self.bones.get(&bone)?;
+        let parent = rest.global.rotation * rest.local.rotation.inverse();
+        Some(parent.inverse() * normalized * rest.global.rotation)
+    }
+}
+
+/// Converts humanoid animation from a source rest pose to a target rest pose.
+#[derive(Clone, Debug)]
+pub struct Retargeter {
+    pub source: HumanoidRestPose,
+    pub target: HumanoidRestPose,
+}
+
+impl Retargeter {
+    pub fn new(source: HumanoidRestPose, target: HumanoidRestPose) -> Self {
+        Self { source, target }
+    }
+
+    /// Ratio of target to source leg length, used to scale hips translation.
+    pub fn hips_scale(&self) -> f32 {
+        match (self.source.leg_length(), self.target.leg_length()) {
+            (Some(source), Some(target)) => target / source,
+            _ => 1.0,
+        }
+    }
+
+    /// Converts a local rotation of a source bone to a local rotation of the target bone.
+    pub fn rotation(&self, bone: BoneName, rotation: Quat) -> Option<Quat> {
+        let normalized = self.source.normalize_rotation(bone, rotation)?;
+        self.target.denormalize_rotation(bone, normalized)
+    }
+
+    /// Converts a local translation of the source hips to a local translation of the target hips.
+    /// The offset from the rest position is scaled by [Self::hips_scale].
+    pub fn hips_translation(&self, translation: Vec3) -> Option<Vec3> {
+        self.hips_translation_with(translation, false)
+    }
+
+    fn hips_translation_with(&self, translation: Vec3, is_tangent: bool) -> Option<Vec3> {
+        let source = self.source.bones.get(&BoneName::Hips)?;
+        let target = self.target.bones.get(&BoneName::Hips)?;
+
+        let source_parent = source.parent_global();
+        let target_parent = target.parent_global().inverse();
+        let scale = self.hips_scale();
+
+        if is_tangent {
+            let offset = source_parent.transform_vector3(translation) * scale;
+            return Some(target_parent.transform_vector3(offset));
+        }
+
+        let offset = source_parent.transform_point3(translation) - source.global.translation;
+        let position = target.global.translation + offset * scale;
+
+        Some(target_parent.transform_point3(position))
+    }
+
+    /// Retargets a clip onto [VRM_ANIMATION_TARGETS].
+    ///
+    /// `bones` maps the targets of the source clip to humanoid bones,
+    /// [VRM_ANIMATION_BONES] can be used for clips already keyed by [VRM_ANIMATION_TARGETS].
+    /// Bones keep their rotation curves, and the hips their translation curves.
+    /// Curves of targets that are not bones, such as expressions, are copied unchanged.
+    pub fn clip(
+        &self,
+        clip: &AnimationClip,
+        bones: &HashMap<AnimationTargetId, BoneName>,
+    ) -> AnimationClip {
+        let mut out = AnimationClip::default();
+
+        for (id, curves) in clip.curves() {
+            let Some(bone) = bones.get(id) else {
+                for curve in curves {
+                    out.add_curve_to_target(*id, curve.clone());
+                }
+                continue;
+            };
+
+            let Some(target) = VRM_ANIMATION_TARGETS.get(bone) else {
+                continue;
+            };
+
+            for curve in curves {
+                if let Some(curve) = self.curve(*bone, curve) {
+                    out.add_curve_to_target(*target, curve);
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Retargets a clip keyed by [VRM_ANIMATION_TARGETS], such as a VRMA clip.
+    pub fn vrm_clip(&self, clip: &AnimationClip) -> AnimationClip {
+        self.clip(clip, &VRM_ANIMATION_BONES)
+    }
+
+    /// Retargets a single curve of a bone.
+    pub fn curve(&self, bone: BoneName, curve: &VariableCurve) -> Option<VariableCurve> {
+        let is_cubic = matches!(curve.interpolation, Interpolation::CubicSpline);
+        // Cubic spline keyframes are stored as [in_tangent, value, out_tangent].
+        // Rotation retargeting is linear in the quaternion, so tangents are converted the same way.
+        let is_tangent = |i: usize| is_cubic && i % 3 != 1;
+
+        let keyframes = match &curve.keyframes {
+            Keyframes::Rotation(rotations) => Keyframes::Rotation(
+                rotations
+                    .iter()
+                    .map(|r| self.rotation(bone, *r))
+                    .collect::<Option<_>>()?,
+            ),
+            Keyframes::Translation(translations) if bone == BoneName::Hips => {
+                Keyframes::Translation(
+                    translations
+                        .iter()
+                        .enumerate()
+                        .map(|(i, t)| self.hips_translation_with(*t, is_tangent(i)))
+                        .collect::<Option<_>>()?,
+                )
+            }
+            _ => return None,
+        };
+
+        Some(VariableCurve {
+            keyframe_timestamps: curve.keyframe_timestamps.clone(),
+            keyframes,
+            interpolation: curve.interpolation.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rest(hips_height: f32, roll: Quat) -> HumanoidRestPose {
+        let hips = Transform::from_xyz(0.0, hips_height, 0.0);
+        let upper_leg_local = Transform {
+            translation: Vec3::new(0.1, 0.0, 0.0),
+            rotation: roll,
+            ..default()
+        };
+        let upper_leg = hips * upper_leg_local;
+        let lower_leg_local = Transform::from_xyz(0.0, -hips_height / 2.0, 0.0);
+        let lower_leg = upper_leg * lower_leg_local;
+        let foot_local = Transform::from_xyz(0.0, -hips_height / 2.0, 0.0);
+        let foot = lower_leg * foot_local;
+
+        let mut pose = HumanoidRestPose::default();
+        pose.bones.insert(
+            BoneName::Hips,
+            RestBone {
+                local: hips,
+                global: hips,
+            },
+        );
+        pose.bones.insert(
+            BoneName::LeftUpperLeg,
+            RestBone {
+                local: upper_leg_local,
+                global: upper_leg,
+            },
+        );
+        pose.bones.insert(
+            BoneName::LeftLowerLeg,
+            RestBone {
+                local: lower_leg_local,
+                global: lower_leg,
+            },
+        );
+        pose.bones.insert(
+            BoneName::LeftFoot,
+            RestBone {
+                local: foot_local,
+                global: foot,
+            },
+        );
+        pose
+    }
+
+    #[test]
+    fn rest_rotation_maps_to_rest_rotation() {
+        let roll = Quat::from_rotation_y(1.2);
+        let retargeter = Retargeter::new(rest(1.0, roll), rest(1.0, Quat::IDENTITY));
+
+        let rotation = retargeter.rotation(BoneName::LeftUpperLeg, roll).unwrap();
+        assert!(rotation.abs_diff_eq(Quat::IDENTITY, 1e-5));
+    }
+
+    #[test]
+    fn rotation_is_preserved_in_model_space() {
+        let roll = Quat::from_rotation_y(1.2);
+        let source = rest(1.0, roll);
+        let target = rest(1.0, Quat::from_rotation_x(-0.4));
+        let retargeter = Retargeter::new(source.clone(), target.clone());
+
+        let kick = Quat::from_rotation_x(0.8);
+        let source_local = roll * kick;
+        let target_local = retargeter
+            .rotation(BoneName::LeftUpperLeg, source_local)
+            .unwrap();
+
+        // Both bones rotate the same way from their rest pose in model space.
+        let world_delta = |pose: &HumanoidRestPose, local: Quat| {
+            let bone = pose.bones[&BoneName::LeftUpperLeg];
+            let parent = bone.global.rotation * bone.local.rotation.inverse();
+            parent * local * bone.global.rotation.inverse()
+        };
+
+        assert!(world_delta(&source, source_local)
+            .abs_diff_eq(world_delta(&target, target_local), 1e-5));
+    }
+
+    #[test]
+    fn hips_translation_scaled_by_leg_length() {
+        let retargeter = Retargeter::new(rest(1.0, Quat::IDENTITY), rest(0.5, Quat::IDENTITY));
+        assert!((retargeter.hips_scale() - 0.5).abs() < 1e-5);
+
+        let translation = retargeter
+            .hips_translation(Vec3::new(0.2, 1.0, 0.4))
+            .unwrap();
+        assert!(translation.abs_diff_eq(Vec3::new(0.1, 0.5, 0.2), 1e-5));
+
+        let crouch = retargeter
+            .hips_translation(Vec3::new(0.0, 0.8, 0.0))
+            .unwrap();
+        assert!(crouch.abs_diff_eq(Vec3::new(0.0, 0.4, 0.0), 1e-5));
+    }
+
+    #[test]
+    fn clip_keeps_other_targets() {
+        let source = rest(1.0, Quat::from_rotation_y(0.5));
+        let retargeter = Retargeter::new(source, rest(1.0, Quat::IDENTITY));
+
+        let other = AnimationTargetId::from_name(&Name::new("Other"));
+        let bone = AnimationTargetId::from_name(&Name::new("mixamorig:LeftUpLeg"));
+
+        let mut clip = AnimationClip::default();
+        clip.add_curve_to_target(
+            bone,
+            VariableCurve {
+                keyframe_timestamps: vec![0.0, 1.0],
+                keyframes: Keyframes::Rotation(vec![Quat::IDENTITY; 2]),
+                interpolation: Interpolation::Linear,
+            },
+        );
+        clip.add_curve_to_target(
+            other,
+            VariableCurve {
+                keyframe_timestamps: vec![0.0],
+                keyframes: Keyframes::Weights(vec![1.0]),
+                interpolation: Interpolation::Step,
+            },
+        );
+
+        let mut bones = HashMap::default();
+        bones.insert(bone, BoneName::LeftUpperLeg);
+
+        let out = retargeter.clip(&clip, &bones);
+        assert!(out.curves_for_target(other).is_some());
+        assert!(out.curves_for_target(bone).is_none());
+        assert!(out
+            .curves_for_target(VRM_ANIMATION_TARGETS[&BoneName::LeftUpperLeg])
+            .is_some());
+    }
+}
@@ -0,0 +1,223 @@
+//! Root motion for VRM humanoids.
+//!
+//! Locomotion clips move the avatar by animating the `Hips` translation and rotation.
+//! [RootMotion] extracts the horizontal movement and yaw of the hips each frame,
+//! keeps the hips in place above the root, and moves the root instead.
+
+use bevy::prelude::*;
+use serde_vrm::vrm0::BoneName;
+
+use crate::animations::humanoid::HumanoidRig;
+
+/// Extracts root motion from the hips of a VRM avatar.
+/// Insert on the root entity of a VRM scene with a [HumanoidRig].
+///
+/// The movement of the last frame is stored in [RootMotion::delta_translation] and
+/// [RootMotion::delta_yaw], in the space of the root's parent, for use by character controllers.
+#[derive(Component, Clone, Debug, Reflect)]
+#[reflect(Component)]
+pub struct RootMotion {
+    /// Applies the movement to the root [Transform].
+    pub apply: bool,
+    /// Extracts the yaw of the hips, turning the root instead.
+    pub extract_yaw: bool,
+    /// Horizontal movement larger than this in one frame, such as a clip looping,
+    /// is ignored.
+    pub max_step: f32,
+    pub delta_translation: Vec3,
+    /// Rotation around the Y axis, in radians.
+    pub delta_yaw: f32,
+    /// Model space position and yaw of the hips in the previous frame.
+    previous: Option<(Vec3, f32)>,
+}
+
+impl Default for RootMotion {
+    fn default() -> Self {
+        Self {
+            apply: true,
+            extract_yaw: true,
+            max_step: 0.5,
+            delta_translation: Vec3::ZERO,
+            delta_yaw: 0.0,
+            previous: None,
+        }
+    }
+}
+
+impl RootMotion {
+    /// Only stores the movement, without moving the root.
+    pub fn extract_only() -> Self {
+        Self {
+            apply: false,
+            ..default()
+        }
+    }
+
+    /// Forgets the previous hips position, such as when switching clips.
+    pub fn reset(&mut self) {
+        self.previous = None;
+    }
+}
+
+/// Yaw of a model space rotation, for an avatar facing -Z.
+fn yaw(rotation: Quat) -> f32 {
+    let forward = rotation * Vec3::NEG_Z;
+    (-forward.x).atan2(-forward.z)
+}
+
+pub(crate) fn apply_root_motion(
+    mut avatars: Query<(Entity, &HumanoidRig, &mut RootMotion)>,
+    mut transforms: Query<&mut Transform>,
+) {
+    for (root, rig, mut motion) in avatars.iter_mut() {
+        motion.delta_translation = Vec3::ZERO;
+        motion.delta_yaw = 0.0;
+
+        let (Some(hips), Some(rest)) = (
+            rig.bones.get(&BoneName::Hips),
+            rig.rest.bones.get(&BoneName::Hips),
+        ) else {
+            continue;
+        };
+
+        let Ok(mut transform) = transforms.get_mut(*hips) else {
+            continue;
+        };
+
+        let parent = rest.parent_global();
+        let position = parent.transform_point3(transform.translation);
+
+        let normalized = rig
+            .rest
+            .normalize_rotation(BoneName::Hips, transform.rotation)
+            .unwrap_or_default();
+        let hips_yaw = if motion.extract_yaw {
+            yaw(normalized)
+        } else {
+            0.0
+        };
+
+        // Keep the hips above the root.
+        let rest_position = rest.global.translation;
+        transform.translation = parent.inverse().transform_point3(Vec3::new(
+            rest_position.x,
+            position.y,
+            rest_position.z,
+        ));
+
+        if motion.extract_yaw {
+            if let Some(rotation) = rig.rest.denormalize_rotation(
+                BoneName::Hips,
+                Quat::from_rotation_y(-hips_yaw) * normalized,
+            ) {
+                transform.rotation = rotation;
+            }
+        }
+
+        let previous = motion.previous.replace((position, hips_yaw));
+        let Some((previous_position, previous_yaw)) = previous else {
+            continue;
+        };
+
+        let step = (position - previous_position) * Vec3::new(1.0, 0.0, 1.0);
+        if step.length() > motion.max_step {
+            continue;
+        }
+
+        // Movement relative to the heading of the hips, which is now the heading of the root.
+        let step = Quat::from_rotation_y(-previous_yaw) * step;
+
+        let mut delta_yaw = hips_yaw - previous_yaw;
+        if delta_yaw > std::f32::consts::PI {
+            delta_yaw -= std::f32::consts::TAU;
+        } else if delta_yaw < -std::f32::consts::PI {
+            delta_yaw += std::f32::consts::TAU;
+        }
+
+        let Ok(mut root_transform) = transforms.get_mut(root) else {
+            continue;
+        };
+
+        motion.delta_translation = root_transform.rotation * (root_transform.scale * step);
+        motion.delta_yaw = delta_yaw;
+
+        if motion.apply {
+            root_transform.translation += motion.delta_translation;
+            root_transform.rotate_y(delta_yaw);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::animations::humanoid::test_rig::spawn_rig;
+
+    fn animate(world: &mut World, hips: Entity, translation: Vec3, yaw: f32) {
+        *world.get_mut::<Transform>(hips).unwrap() = Transform {
+            translation,
+            rotation: Quat::from_rotation_y(yaw),
+            ..default()
+        };
+        world.run_system_once(apply_root_motion);
+    }
+
+    #[test]
+    fn extract_translation_and_yaw() {
+        let mut world = World::new();
+        let (root, bones) = spawn_rig(
+            &mut world,
+            &[(BoneName::Hips, None, Vec3::new(0.0, 1.0, 0.0))],
+        );
+        let hips = bones[&BoneName::Hips];
+        world.entity_mut(root).insert(RootMotion::default());
+
+        animate(&mut world, hips, Vec3::new(0.0, 0.9, -0.1), 0.0);
+        assert_eq!(
+            world.get::<Transform>(root).unwrap().translation,
+            Vec3::ZERO
+        );
+        assert_eq!(
+            world.get::<Transform>(hips).unwrap().translation,
+            Vec3::new(0.0, 0.9, 0.0)
+        );
+
+        animate(&mut world, hips, Vec3::new(0.0, 0.9, -0.3), 0.2);
+        let motion = world.get::<RootMotion>(root).unwrap();
+        assert!(motion
+            .delta_translation
+            .abs_diff_eq(Vec3::new(0.0, 0.0, -0.2), 1e-5));
+        assert!((motion.delta_yaw - 0.2).abs() < 1e-5);
+
+        let transform = *world.get::<Transform>(root).unwrap();
+        assert!(transform
+            .translation
+            .abs_diff_eq(Vec3::new(0.0, 0.0, -0.2), 1e-5));
+        assert!(transform
+            .rotation
+            .abs_diff_eq(Quat::from_rotation_y(0.2), 1e-5));
+
+        // The hips stay in place, facing forward.
+        let hips_transform = world.get::<Transform>(hips).unwrap();
+        assert!(hips_transform.rotation.abs_diff_eq(Quat::IDENTITY, 1e-5));
+        assert!(hips_transform
+            .translation
+            .abs_diff_eq(Vec3::new(0.0, 0.9, 0.0), 1e-5));
+
+        // Movement follows the heading of the root.
+        animate(&mut world, hips, Vec3::new(-0.1, 0.9, -0.3), 0.2);
+        let motion = world.get::<RootMotion>(root).unwrap();
+        assert!(motion
+            .delta_translation
+            .abs_diff_eq(Vec3::new(-0.1, 0.0, 0.0), 1e-5));
+
+        // Looping back to the start of the clip is ignored.
+        animate(&mut world, hips, Vec3::new(0.0, 0.9, 1.0), 0.0);
+        assert_eq!(
+            world.get::<RootMotion>(root).unwrap().delta_translation,
+            Vec3::ZERO
+        );
+    }
+}
@@ -0,0 +1,448 @@
+//! Sampling of [VariableCurve]s outside of the [AnimationPlayer],
+//! matching the glTF interpolation modes used for playback.
+//!
+//! VRMA clips keep their glTF interpolation, and sample the same way
+//! whether played by the [AnimationPlayer] or by [HumanoidLayers](super::layers::HumanoidLayers).
+//!
+//! Cubic spline keyframes are stored as `[in_tangent, value, out_tangent]`,
+//! see the [glTF spec](https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html#interpolation-cubic).
+
+use std::ops::{Add, Mul};
+
+use bevy::{
+    animation::{Interpolation, Keyframes, VariableCurve},
+    prelude::*,
+};
+
+/// A value sampled from a [VariableCurve].
+#[derive(Clone, Debug, PartialEq)]
+pub enum CurveSample {
+    Translation(Vec3),
+    Rotation(Quat),
+    Scale(Vec3),
+    Weights(Vec<f32>),
+}
+
+impl CurveSample {
+    /// Writes the sample to a transform. Weights are ignored.
+    pub fn apply(&self, transform: &mut Transform) {
+        match self {
+            Self::Translation(t) => transform.translation = *t,
+            Self::Rotation(r) => transform.rotation = *r,
+            Self::Scale(s) => transform.scale = *s,
+            Self::Weights(_) => {}
+        }
+    }
+}
+
+/// Cubic Hermite spline between two keyframes.
+///
+/// `t` is the normalized time between the keyframes, and `step_duration` the time between them
+/// in seconds, which scales the tangents.
+pub fn cubic_hermite<T>(
+    value_start: T,
+    tangent_out_start: T,
+    tangent_in_end: T,
+    value_end: T,
+    t: f32,
+    step_duration: f32,
+) -> T
+where
+    T: Mul<f32, Output = T> + Add<Output = T>,
+{
+    let t2 = t * t;
+    let t3 = t2 * t;
+
+    value_start * (2.0 * t3 - 3.0 * t2 + 1.0)
+        + tangent_out_start * (step_duration * (t3 - 2.0 * t2 + t))
+        + value_end * (-2.0 * t3 + 3.0 * t2)
+        + tangent_in_end * (step_duration * (t3 - t2))
+}
+
+/// Number of values stored per keyframe.
+fn stride(curve: &VariableCurve) -> usize {
+    match curve.interpolation {
+        Interpolation::CubicSpline => 3,
+        _ => 1,
+    }
+}
+
+/// Number of values stored per keyframe, or `None` if the keyframes do not match the
+/// timestamps, such as cubic spline curves without tangents.
+fn values_per_keyframe(curve: &VariableCurve) -> Option<usize> {
+    let expected = curve.keyframe_timestamps.len() * stride(curve);
+
+    let len = match &curve.keyframes {
+        Keyframes::Translation(k) => k.len(),
+        Keyframes::Scale(k) => k.len(),
+        Keyframes::Rotation(k) => k.len(),
+        // One value per morph target.
+        Keyframes::Weights(k) => {
+            return match k.len().checked_div(expected) {
+                Some(count) if count > 0 && k.len() % expected == 0 => Some(count),
+                _ => None,
+            };
+        }
+    };
+
+    (len == expected).then_some(1)
+}
+
+/// Samples a curve at the given time. Times outside the curve are clamped to the first
+/// or last keyframe.
+///
+/// Returns `None` for empty or malformed curves.
+pub fn sample_curve(curve: &VariableCurve, time: f32) -> Option<CurveSample> {
+    let timestamps = &curve.keyframe_timestamps;
+    let last = timestamps.len().checked_sub(1)?;
+    let count = values_per_keyframe(curve)?;
+
+    let (start, end, t) = if time <= timestamps[0] {
+        (0, 0, 0.0)
+    } else if time >= timestamps[last] {
+        (last, last, 0.0)
+    } else {
+        let end = timestamps.partition_point(|k| *k <= time);
+        let start = end - 1;
+        let duration = timestamps[end] - timestamps[start];
+        (start, end, (time - timestamps[start]) / duration)
+    };
+
+    let step_duration = timestamps[end] - timestamps[start];
+    let stride = stride(curve);
+
+    // Index of the value, in tangents, and out tangents of each keyframe.
+    let value = |i: usize| i * stride + stride / 2;
+    let out_tangent = |i: usize| i * stride + 2;
+    let in_tangent = |i: usize| i * stride;
+
+    macro_rules! interpolate {
+        ($keyframes:expr, $lerp:expr, $post:expr) => {{
+            let keyframes = $keyframes;
+            match curve.interpolation {
+                _ if start == end => keyframes[value(start)],
+                Interpolation::Step => keyframes[value(start)],
+                Interpolation::Linear => $lerp(keyframes[value(start)], keyframes[value(end)], t),
+                Interpolation::CubicSpline => $post(cubic_hermite(
+                    keyframes[value(start)],
+                    keyframes[out_tangent(start)],
+                    keyframes[in_tangent(end)],
+                    keyframes[value(end)],
+                    t,
+                    step_duration,
+                )),
+            }
+        }};
+    }
+
+    let sample = match &curve.keyframes {
+        Keyframes::Translation(keyframes) => {
+            CurveSample::Translation(interpolate!(keyframes, Vec3::lerp, std::convert::identity))
+        }
+        Keyframes::Scale(keyframes) => {
+            CurveSample::Scale(interpolate!(keyframes, Vec3::lerp, std::convert::identity))
+        }
+        Keyframes::Rotation(keyframes) => {
+            CurveSample::Rotation(interpolate!(keyframes, Quat::slerp, Quat::normalize))
+        }
+        Keyframes::Weights(keyframes) => {
+            let weights = (0..count)
+                .map(|m| {
+                    let at = |i: usize| keyframes[i * count + m];
+
+                    match curve.interpolation {
+                        _ if start == end => at(value(start)),
+                        Interpolation::Step => at(value(start)),
+                        Interpolation::Linear => {
+                            at(value(start)) + (at(value(end)) - at(value(start))) * t
+                        }
+                        Interpolation::CubicSpline => cubic_hermite(
+                            at(value(start)),
+                            at(out_tangent(start)),
+                            at(in_tangent(end)),
+                            at(value(end)),
+                            t,
+                            step_duration,
+                        ),
+                    }
+                })
+                .collect();
+
+            CurveSample::Weights(weights)
+        }
+    };
+
+    Some(sample)
+}
+
+/// Samples every curve of a target and applies them to a transform.
+pub fn sample_transform(
+    curves: &[VariableCurve],
+    time: f32,
+    mut transform: Transform,
+) -> Transform {
+    for curve in curves {
+        if let Some(sample) = sample_curve(curve, time) {
+            sample.apply(&mut transform);
+        }
+    }
+
+    transform
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::animation::AnimationTargetId;
+
+    use super::*;
+
+    fn cubic(keyframes: Keyframes) -> VariableCurve {
+        VariableCurve {
+            keyframe_timestamps: vec![0.0, 2.0],
+            keyframes,
+            interpolation: Interpolation::CubicSpline,
+        }
+    }
+
+    #[test]
+    fn hermite_basis() {
+        // Hermite basis from the glTF 2.0 spec, with a step of 2 seconds.
+        // At t = 0.5 the basis is [0.5, 0.125, 0.5, -0.125].
+        let value = cubic_hermite(0.0, 1.0, 0.0, 1.0, 0.5, 2.0);
+        assert!((value - 0.75).abs() < 1e-6);
+
+        let value = cubic_hermite(0.0, 0.0, 1.0, 1.0, 0.5, 2.0);
+        assert!((value - 0.25).abs() < 1e-6);
+
+        // Endpoints match keyframe values.
+        assert_eq!(cubic_hermite(3.0, 5.0, 7.0, 4.0, 0.0, 1.0), 3.0);
+        assert_eq!(cubic_hermite(3.0, 5.0, 7.0, 4.0, 1.0, 1.0), 4.0);
+    }
+
+    #[test]
+    fn cubic_translation() {
+        // [in, value, out] per keyframe.
+        let curve = cubic(Keyframes::Translation(vec![
+            Vec3::ZERO,
+            Vec3::ZERO,
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(1.0, 1.0, 1.0),
+            Vec3::ZERO,
+        ]));
+
+        let Some(CurveSample::Translation(t)) = sample_curve(&curve, 1.0) else {
+            panic!("Expected translation");
+        };
+        assert!(t.abs_diff_eq(Vec3::new(0.75, 0.25, 0.5), 1e-6));
+
+        // Clamped outside of the curve.
+        assert_eq!(
+            sample_curve(&curve, 5.0),
+            Some(CurveSample::Translation(Vec3::ONE))
+        );
+        assert_eq!(
+            sample_curve(&curve, -1.0),
+            Some(CurveSample::Translation(Vec3::ZERO))
+        );
+    }
+
+    #[test]
+    fn cubic_rotation_is_normalized() {
+        let end = Quat::from_rotation_y(1.0);
+        let curve = cubic(Keyframes::Rotation(vec![
+            Quat::from_xyzw(0.0, 0.0, 0.0, 0.0),
+            Quat::IDENTITY,
+            Quat::from_xyzw(0.0, 0.2, 0.0, 0.0),
+            Quat::from_xyzw(0.0, 0.2, 0.0, 0.0),
+            end,
+            Quat::from_xyzw(0.0, 0.0, 0.0, 0.0),
+        ]));
+
+        let Some(CurveSample::Rotation(r)) = sample_curve(&curve, 1.0) else {
+            panic!("Expected rotation");
+        };
+        assert!(r.is_normalized());
+
+        // Hermite blend of the components, then normalized.
+        let raw = Quat::IDENTITY * 0.5
+            + Quat::from_xyzw(0.0, 0.2, 0.0, 0.0) * 0.25
+            + end * 0.5
+            + Quat::from_xyzw(0.0, 0.2, 0.0, 0.0) * -0.25;
+        assert!(r.abs_diff_eq(raw.normalize(), 1e-6));
+
+        let Some(CurveSample::Rotation(r)) = sample_curve(&curve, 2.0) else {
+            panic!("Expected rotation");
+        };
+        assert!(r.abs_diff_eq(end, 1e-6));
+    }
+
+    #[test]
+    fn cubic_scale_and_weights() {
+        let curve = cubic(Keyframes::Scale(vec![
+            Vec3::ZERO,
+            Vec3::ONE,
+            Vec3::ZERO,
+            Vec3::ZERO,
+            Vec3::splat(2.0),
+            Vec3::ZERO,
+        ]));
+        assert_eq!(
+            sample_curve(&curve, 1.0),
+            Some(CurveSample::Scale(Vec3::splat(1.5)))
+        );
+
+        // Two morph targets, keyframes are [in; 2], [value; 2], [out; 2].
+        let curve = cubic(Keyframes::Weights(vec![
+            0.0, 0.0, 0.0, 1.0, 1.0, 0.0, //
+            0.0, 0.0, 1.0, 0.0, 0.0, 0.0,
+        ]));
+        let Some(CurveSample::Weights(weights)) = sample_curve(&curve, 1.0) else {
+            panic!("Expected weights");
+        };
+        assert!((weights[0] - 0.75).abs() < 1e-6);
+        assert!((weights[1] - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn linear_and_step() {
+        let mut curve = VariableCurve {
+            keyframe_timestamps: vec![0.0, 1.0, 3.0],
+            keyframes: Keyframes::Translation(vec![Vec3::ZERO, Vec3::X, Vec3::Y]),
+            interpolation: Interpolation::Linear,
+        };
+
+        assert_eq!(
+            sample_curve(&curve, 2.0),
+            Some(CurveSample::Translation(Vec3::new(0.5, 0.5, 0.0)))
+        );
+
+        curve.interpolation = Interpolation::Step;
+        assert_eq!(
+            sample_curve(&curve, 2.0),
+            Some(CurveSample::Translation(Vec3::X))
+        );
+    }
+
+    #[test]
+    fn malformed_curves() {
+        // Cubic spline without tangents.
+        let curve = cubic(Keyframes::Translation(vec![Vec3::ZERO, Vec3::ONE]));
+        assert_eq!(sample_curve(&curve, 1.0), None);
+
+        let curve = cubic(Keyframes::Weights(vec![0.0, 1.0, 0.0, 1.0]));
+        assert_eq!(sample_curve(&curve, 1.0), None);
+
+        let curve = VariableCurve {
+            keyframe_timestamps: vec![0.0, 1.0],
+            keyframes: Keyframes::Rotation(vec![Quat::IDENTITY]),
+            interpolation: Interpolation::Linear,
+        };
+        assert_eq!(sample_curve(&curve, 0.5), None);
+
+        let transform = Transform::from_xyz(1.0, 2.0, 3.0);
+        assert_eq!(sample_transform(&[curve], 0.5, transform), transform);
+    }
+
+    /// Plays a clip through the [AnimationPlayer], which VRMA clips play through,
+    /// and returns the target's transform at each time.
+    fn play(clip: AnimationClip, times: &[f32]) -> Vec<Transform> {
+        use std::time::Duration;
+
+        use bevy::{animation::AnimationTarget, time::TimeUpdateStrategy};
+
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default(), AnimationPlugin))
+            .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::ZERO));
+
+        let clip = app
+            .world_mut()
+            .resource_mut::<Assets<AnimationClip>>()
+            .add(clip);
+        let (graph, node) = AnimationGraph::from_clip(clip);
+        let graph = app
+            .world_mut()
+            .resource_mut::<Assets<AnimationGraph>>()
+            .add(graph);
+
+        let player = app
+            .world_mut()
+            .spawn((AnimationPlayer::default(), graph))
+            .id();
+        let target = app
+            .world_mut()
+            .spawn((
+                Transform::default(),
+                AnimationTarget {
+                    id: AnimationTargetId::from_name(&Name::new("target")),
+                    player,
+                },
+            ))
+            .id();
+
+        times
+            .iter()
+            .map(|time| {
+                app.world_mut()
+                    .get_mut::<AnimationPlayer>(player)
+                    .unwrap()
+                    .play(node)
+                    .seek_to(*time);
+                app.update();
+                *app.world().get::<Transform>(target).unwrap()
+            })
+            .collect()
+    }
+
+    /// The [AnimationPlayer] is the reference for sampled values, since it plays the same
+    /// clips at runtime. No glTF sample asset is vendored to test against.
+    #[test]
+    fn matches_animation_player() {
+        let translation = cubic(Keyframes::Translation(vec![
+            Vec3::ZERO,
+            Vec3::ZERO,
+            Vec3::new(1.0, -2.0, 0.5),
+            Vec3::new(0.0, 1.0, 3.0),
+            Vec3::new(1.0, 1.0, 1.0),
+            Vec3::ZERO,
+        ]));
+        let rotation = cubic(Keyframes::Rotation(vec![
+            Quat::from_xyzw(0.0, 0.0, 0.0, 0.0),
+            Quat::from_rotation_x(0.3),
+            Quat::from_xyzw(0.1, 0.4, 0.0, 0.0),
+            Quat::from_xyzw(0.0, -0.2, 0.3, 0.0),
+            Quat::from_rotation_y(1.2),
+            Quat::from_xyzw(0.0, 0.0, 0.0, 0.0),
+        ]));
+
+        let mut clip = AnimationClip::default();
+        let id = AnimationTargetId::from_name(&Name::new("target"));
+        clip.add_curve_to_target(id, translation.clone());
+        clip.add_curve_to_target(id, rotation.clone());
+
+        let times = [0.25, 0.7, 1.0, 1.6];
+
+        for (time, played) in times.iter().zip(play(clip, &times)) {
+            let sampled = sample_transform(
+                &[translation.clone(), rotation.clone()],
+                *time,
+                Transform::default(),
+            );
+
+            assert!(
+                sampled.translation.abs_diff_eq(played.translation, 1e-5),
+                "{}: {} != {}",
+                time,
+                sampled.translation,
+                played.translation
+            );
+            assert!(
+                sampled.rotation.abs_diff_eq(played.rotation, 1e-5),
+                "{}: {} != {}",
+                time,
+                sampled.rotation,
+                played.rotation
+            );
+        }
+    }
+}
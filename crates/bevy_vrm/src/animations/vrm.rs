@@ -1,103 +1,192 @@
 use std::sync::LazyLock;
 
-use bevy::{animation::AnimationTargetId, utils::HashMap};
+use bevy::{
+    animation::{AnimationTarget, AnimationTargetId},
+    ecs::system::RunSystemOnce,
+    prelude::*,
+    utils::HashMap,
+};
 use serde_vrm::vrm0::BoneName;
 
-use crate::animations::target_chain::TargetChain;
-
-macro_rules! push_bone {
-    ($map:ident, $chain:ident, $name:ident) => {
-        $map.insert(
-            BoneName::$name,
-            $chain.push_target(BoneName::$name.to_string()),
-        );
-    };
-}
-
-macro_rules! finger {
-    ($map:ident, $chain:ident, $side:ident, $finger:ident) => {
-        paste::paste! {
-            {
-                let mut chain = $chain.clone();
-
-                push_bone!($map, chain, [<$side $finger Proximal>]);
-                push_bone!($map, chain, [<$side $finger Intermediate>]);
-                push_bone!($map, chain, [<$side $finger Distal>]);
-            }
-        }
-    };
-}
-
-macro_rules! arm {
-    ($map:ident, $chain:ident, $side:ident) => {
-        paste::paste! {
-            {
-                let mut chain = $chain.clone();
-
-                push_bone!($map, chain, [<$side Shoulder>]);
-                push_bone!($map, chain, [<$side UpperArm>]);
-                push_bone!($map, chain, [<$side LowerArm>]);
-                push_bone!($map, chain, [<$side Hand>]);
-
-                finger!($map, chain, $side, Thumb);
-                finger!($map, chain, $side, Index);
-                finger!($map, chain, $side, Middle);
-                finger!($map, chain, $side, Ring);
-                finger!($map, chain, $side, Little);
-            }
-        }
-    };
-}
-
-macro_rules! leg {
-    ($map:ident, $chain:ident, $side:ident) => {
-        paste::paste! {
-            {
-                let mut chain = $chain.clone();
-
-                push_bone!($map, chain, [<$side UpperLeg>]);
-                push_bone!($map, chain, [<$side LowerLeg>]);
-                push_bone!($map, chain, [<$side Foot>]);
-                push_bone!($map, chain, [<$side Toes>]);
-            }
-        }
-    };
+use crate::animations::{
+    humanoid::{humanoid_parent, is_required_bone},
+    layers::BoneMask,
+    target_chain::TargetChain,
+};
+
+/// Creates the [AnimationTargetId] of a humanoid bone from its ancestors, ordered from the hips.
+///
+/// Only required bones are part of the path, so the ID of a bone is the same whether or not
+/// an avatar has optional bones like `Chest`, `UpperChest`, `Neck` or `Toes`.
+pub fn humanoid_target_id(
+    bone: BoneName,
+    ancestors: impl IntoIterator<Item = BoneName>,
+) -> AnimationTargetId {
+    let mut chain = TargetChain::default();
+
+    for ancestor in ancestors.into_iter().filter(|b| is_required_bone(*b)) {
+        chain.push_target(ancestor.to_string());
+    }
+
+    chain.push_target(bone.to_string())
 }
 
+/// [AnimationTargetId] of each humanoid bone, built from its path in the VRM humanoid hierarchy.
+///
+/// These IDs are canonical: every avatar uses them, whatever the hierarchy of its scene,
+/// so clips keyed by them play on any avatar.
 pub static VRM_ANIMATION_TARGETS: LazyLock<HashMap<BoneName, AnimationTargetId>> =
     LazyLock::new(|| {
-        let mut map = HashMap::default();
-        let mut chain = TargetChain::default();
-
-        push_bone!(map, chain, Hips);
-
-        leg!(map, chain, Left);
-        leg!(map, chain, Right);
-
-        push_bone!(map, chain, Spine);
-        push_bone!(map, chain, Chest);
-        push_bone!(map, chain, UpperChest);
-
-        arm!(map, chain, Left);
-        arm!(map, chain, Right);
-
-        push_bone!(map, chain, Neck);
-        push_bone!(map, chain, Head);
+        BoneMask::all()
+            .bones()
+            .map(|bone| {
+                let mut ancestors = Vec::new();
+                let mut parent = humanoid_parent(bone);
+
+                while let Some(p) = parent {
+                    ancestors.push(p);
+                    parent = humanoid_parent(p);
+                }
+
+                ancestors.reverse();
+
+                (bone, humanoid_target_id(bone, ancestors))
+            })
+            .collect()
+    });
 
-        {
-            let mut chain = chain.clone();
-            push_bone!(map, chain, Jaw);
-        }
+/// Inverse of [VRM_ANIMATION_TARGETS].
+pub static VRM_ANIMATION_BONES: LazyLock<HashMap<AnimationTargetId, BoneName>> =
+    LazyLock::new(|| {
+        VRM_ANIMATION_TARGETS
+            .iter()
+            .map(|(bone, id)| (*id, *bone))
+            .collect()
+    });
 
-        {
-            let mut chain = chain.clone();
-            push_bone!(map, chain, LeftEye);
-        }
+/// Inserts an [AnimationTarget] on each humanoid bone, with its ID from [VRM_ANIMATION_TARGETS].
+///
+/// IDs do not depend on the scene hierarchy, so clips still play on avatars whose bones
+/// are parented differently. Such avatars are reported by
+/// [validate_humanoid](crate::animations::humanoid::validate_humanoid).
+/// The player is the root entity of the scene.
+#[cfg_attr(not(feature = "vrm0"), allow(dead_code))]
+pub(crate) fn insert_humanoid_targets(world: &mut World) {
+    world.run_system_once(
+        |mut commands: Commands, bones: Query<(Entity, &BoneName)>, parents: Query<&Parent>| {
+            for (entity, bone) in bones.iter() {
+                let player = parents.iter_ancestors(entity).last().unwrap_or(entity);
+
+                commands.entity(entity).insert(AnimationTarget {
+                    id: VRM_ANIMATION_TARGETS[bone],
+                    player,
+                });
+            }
+        },
+    );
+}
 
-        {
-            let mut chain = chain.clone();
-            push_bone!(map, chain, RightEye);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animations::humanoid::{build_humanoid_rig, validate_humanoid, HumanoidError};
+
+    #[test]
+    fn targets_skip_optional_bones() {
+        let id = |path: &[BoneName]| {
+            AnimationTargetId::from_names(
+                path.iter()
+                    .map(|b| Name::new(b.to_string()))
+                    .collect::<Vec<_>>()
+                    .iter(),
+            )
+        };
+
+        assert_eq!(
+            VRM_ANIMATION_TARGETS[&BoneName::UpperChest],
+            id(&[BoneName::Hips, BoneName::Spine, BoneName::UpperChest])
+        );
+        assert_eq!(
+            VRM_ANIMATION_TARGETS[&BoneName::Head],
+            id(&[BoneName::Hips, BoneName::Spine, BoneName::Head])
+        );
+        assert_eq!(
+            VRM_ANIMATION_TARGETS[&BoneName::LeftIndexDistal],
+            id(&[
+                BoneName::Hips,
+                BoneName::Spine,
+                BoneName::LeftUpperArm,
+                BoneName::LeftLowerArm,
+                BoneName::LeftHand,
+                BoneName::LeftIndexDistal
+            ])
+        );
+        assert_eq!(VRM_ANIMATION_BONES.len(), VRM_ANIMATION_TARGETS.len());
+    }
+
+    #[test]
+    fn targets_ignore_avatar_hierarchy() {
+        let mut world = World::new();
+        let root = world.spawn(TransformBundle::default()).id();
+
+        let mut spawn = |bone: BoneName, parent: Entity| {
+            world
+                .spawn((TransformBundle::default(), bone))
+                .set_parent(parent)
+                .id()
+        };
+
+        // No chest, neck or shoulders, and an unnamed node between the upper chest and head.
+        let hips = spawn(BoneName::Hips, root);
+        let spine = spawn(BoneName::Spine, hips);
+        let upper_chest = spawn(BoneName::UpperChest, spine);
+        let arm = spawn(BoneName::LeftUpperArm, upper_chest);
+        let head_parent = world
+            .spawn(TransformBundle::default())
+            .set_parent(upper_chest)
+            .id();
+        let head = world
+            .spawn((TransformBundle::default(), BoneName::Head))
+            .set_parent(head_parent)
+            .id();
+        // Placed under the hips instead of the upper chest.
+        let right_arm = world
+            .spawn((TransformBundle::default(), BoneName::RightUpperArm))
+            .set_parent(hips)
+            .id();
+
+        insert_humanoid_targets(&mut world);
+
+        // Misparented bones keep their canonical ID, so clips still animate them.
+        for (entity, bone) in [
+            (spine, BoneName::Spine),
+            (upper_chest, BoneName::UpperChest),
+            (arm, BoneName::LeftUpperArm),
+            (head, BoneName::Head),
+            (right_arm, BoneName::RightUpperArm),
+        ] {
+            let target = world.get::<AnimationTarget>(entity).unwrap();
+            assert_eq!(target.id, VRM_ANIMATION_TARGETS[&bone]);
+            assert_eq!(target.player, root);
         }
 
-        map
-    });
+        build_humanoid_rig(&mut world);
+        let errors = validate_humanoid(&mut world, root);
+
+        assert!(errors.contains(&HumanoidError::MissingBone(BoneName::LeftFoot)));
+        assert!(!errors.contains(&HumanoidError::MissingBone(BoneName::Head)));
+
+        let invalid = errors
+            .into_iter()
+            .filter(|e| matches!(e, HumanoidError::InvalidParent { .. }))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            invalid,
+            vec![HumanoidError::InvalidParent {
+                bone: BoneName::RightUpperArm,
+                expected: Some(BoneName::UpperChest),
+                found: Some(BoneName::Hips),
+            }]
+        );
+    }
+}
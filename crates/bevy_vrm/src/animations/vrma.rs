@@ -0,0 +1,466 @@
+//! Loader for [VRM Animation](https://vrm.dev/en/vrma/) (`.vrma`) files.
+//!
+//! Humanoid bone tracks are converted to normalized rotations and keyed by
+//! [VRM_ANIMATION_TARGETS], so a clip plays on any VRM through its [AnimationPlayer].
+//! Expression tracks are keyed by [expression_target_id].
+//!
+//! VRMA files face +Z like VRM 1.0, clips are converted to face -Z like VRM 0.0.
+
+use bevy::{
+    animation::{Interpolation, Keyframes, VariableCurve},
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    utils::HashMap,
+};
+use gltf_kun::{
+    graph::{
+        gltf::{
+            accessor::iter::{AccessorIter, AccessorIterCreateError},
+            animation::{Interpolation as GltfInterpolation, TargetPath},
+            GltfDocument, Node,
+        },
+        ByteNode, Extensions, Graph, GraphNodeWeight,
+    },
+    io::format::glb::{GlbImport, GlbImportError},
+};
+use gltf_kun_vrm::vrm1::vrmc_vrm_animation::VrmcVrmAnimation;
+use serde::{de::IntoDeserializer, Deserialize};
+use serde_vrm::vrm0::BoneName;
+use thiserror::Error;
+
+use crate::{
+    animations::{
+        expressions::expression_target_id,
+        retarget::{to_vrm0_rotation, to_vrm0_vec, HumanoidRestPose},
+        vrm::VRM_ANIMATION_TARGETS,
+    },
+    expressions::{ExpressionName, PresetName},
+};
+
+#[derive(Asset, TypePath, Debug)]
+pub struct Vrma {
+    pub clip: Handle<AnimationClip>,
+    /// Normalized rest pose of the humanoid bones, converted to face -Z.
+    /// Use with a [Retargeter](super::retarget::Retargeter) to play the clip on
+    /// avatars with a different rest pose.
+    pub rest: HumanoidRestPose,
+}
+
+#[derive(Default)]
+pub struct VrmaLoader;
+
+#[derive(Debug, Error)]
+pub enum VrmaError {
+    #[error("Failed to read file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to import glb: {0}")]
+    Import(#[from] GlbImportError),
+    #[error("Missing VRMC_vrm_animation extension")]
+    MissingExtension,
+    #[error("Failed to create accessor iterator: {0}")]
+    AccessorIter(#[from] AccessorIterCreateError),
+}
+
+impl AssetLoader for VrmaLoader {
+    type Asset = Vrma;
+    type Settings = ();
+    type Error = VrmaError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a Self::Settings,
+        load_context: &'a mut LoadContext,
+    ) -> impl bevy::utils::ConditionalSendFuture<Output = Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+
+            let mut graph = Graph::new();
+            let doc = GlbImport::<VrmcVrmAnimation>::import_slice(&mut graph, &bytes).await?;
+
+            let (clip, rest) = import_vrma(&graph, doc)?;
+            let clip = load_context.add_labeled_asset("Animation".to_string(), clip);
+
+            Ok(Vrma { clip, rest })
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["vrma"]
+    }
+}
+
+enum Track {
+    Bone(BoneName),
+    Expression(ExpressionName),
+}
+
+/// Converts a VRMA document into an [AnimationClip], along with the rest pose of its bones.
+pub fn import_vrma(
+    graph: &Graph,
+    doc: GltfDocument,
+) -> Result<(AnimationClip, HumanoidRestPose), VrmaError> {
+    let ext = doc
+        .get_extension::<VrmcVrmAnimation>(graph)
+        .ok_or(VrmaError::MissingExtension)?;
+
+    let mut tracks = HashMap::<Node, Track>::default();
+
+    for bone in ext.human_bones(graph) {
+        let weight = bone.read(graph);
+
+        let Some(bone_name) = bone_name_from_vrm1(&weight.name) else {
+            warn!("Unknown VRMA human bone: {}", weight.name);
+            continue;
+        };
+
+        if let Some(node) = bone.node(graph) {
+            tracks.insert(node, Track::Bone(bone_name));
+        }
+    }
+
+    for expression in ext.expressions(graph) {
+        let weight = expression.read(graph);
+
+        let name = if weight.is_preset {
+            expression_name_from_vrm1(&weight.name)
+        } else {
+            ExpressionName::Custom(weight.name)
+        };
+
+        if let Some(node) = expression.node(graph) {
+            tracks.insert(node, Track::Expression(name));
+        }
+    }
+
+    let globals = global_transforms(graph, doc);
+
+    let rest = HumanoidRestPose::normalized(tracks.iter().filter_map(|(node, track)| {
+        let Track::Bone(bone_name) = track else {
+            return None;
+        };
+
+        let global = globals.get(node).copied().unwrap_or_default();
+        Some((*bone_name, to_vrm0_vec(global.translation)))
+    }));
+
+    let parents = parents(graph, doc);
+
+    let mut clip = AnimationClip::default();
+
+    for animation in doc.animations(graph) {
+        for channel in animation.channels(graph) {
+            let Some(node) = channel.target(graph) else {
+                continue;
+            };
+
+            let Some(track) = tracks.get(&node) else {
+                continue;
+            };
+
+            let Some(sampler) = channel.sampler(graph) else {
+                continue;
+            };
+
+            let (Some(input), Some(output)) = (sampler.input(graph), sampler.output(graph)) else {
+                continue;
+            };
+
+            let keyframe_timestamps = match input.iter(graph)? {
+                AccessorIter::F32(iter) => iter.collect::<Vec<_>>(),
+                _ => {
+                    debug!("Input is not F32");
+                    continue;
+                }
+            };
+
+            let interpolation = match sampler.get(graph).interpolation {
+                GltfInterpolation::CubicSpline => Interpolation::CubicSpline,
+                GltfInterpolation::Linear => Interpolation::Linear,
+                GltfInterpolation::Step => Interpolation::Step,
+            };
+
+            // Cubic spline keyframes are stored as [in_tangent, value, out_tangent].
+            let is_cubic = matches!(interpolation, Interpolation::CubicSpline);
+            let is_value = |i: usize| !is_cubic || i % 3 == 1;
+
+            let parent_global = parents
+                .get(&node)
+                .and_then(|p| globals.get(p))
+                .copied()
+                .unwrap_or_default();
+
+            let output_iter = output.iter(graph)?;
+
+            let (target, keyframes) = match (track, &channel.get(graph).path, output_iter) {
+                (Track::Bone(bone_name), TargetPath::Rotation, AccessorIter::F32x4(iter)) => {
+                    let global = globals.get(&node).copied().unwrap_or_default();
+                    let parent_rotation = parent_global.rotation;
+                    let inverse_rotation = global.rotation.inverse();
+
+                    let rotations = iter
+                        .map(Quat::from_array)
+                        .map(|q| to_vrm0_rotation(parent_rotation * q * inverse_rotation))
+                        .collect();
+
+                    (
+                        VRM_ANIMATION_TARGETS[bone_name],
+                        Keyframes::Rotation(rotations),
+                    )
+                }
+                (
+                    Track::Bone(BoneName::Hips),
+                    TargetPath::Translation,
+                    AccessorIter::F32x3(iter),
+                ) => {
+                    let translations = iter
+                        .map(Vec3::from)
+                        .enumerate()
+                        .map(|(i, t)| {
+                            let t = if is_value(i) {
+                                parent_global.transform_point(t)
+                            } else {
+                                parent_global.rotation * (parent_global.scale * t)
+                            };
+
+                            to_vrm0_vec(t)
+                        })
+                        .collect();
+
+                    (
+                        VRM_ANIMATION_TARGETS[&BoneName::Hips],
+                        Keyframes::Translation(translations),
+                    )
+                }
+                (Track::Expression(name), TargetPath::Translation, AccessorIter::F32x3(iter)) => {
+                    let weights = iter.map(|t| t[0]).collect();
+                    (expression_target_id(name), Keyframes::Weights(weights))
+                }
+                _ => continue,
+            };
+
+            clip.add_curve_to_target(
+                target,
+                VariableCurve {
+                    keyframe_timestamps,
+                    keyframes,
+                    interpolation,
+                },
+            );
+        }
+    }
+
+    Ok((clip, rest))
+}
+
+/// Converts a VRM 1.0 human bone name to a [BoneName].
+/// VRM 1.0 adds a metacarpal bone to the thumb, which shifts the thumb bone names.
+pub fn bone_name_from_vrm1(name: &str) -> Option<BoneName> {
+    match name {
+        "leftThumbMetacarpal" => Some(BoneName::LeftThumbProximal),
+        "leftThumbProximal" => Some(BoneName::LeftThumbIntermediate),
+        "rightThumbMetacarpal" => Some(BoneName::RightThumbProximal),
+        "rightThumbProximal" => Some(BoneName::RightThumbIntermediate),
+        _ => {
+            let deserializer: serde::de::value::StrDeserializer<serde::de::value::Error> =
+                name.into_deserializer();
+            BoneName::deserialize(deserializer).ok()
+        }
+    }
+}
+
+/// Converts a VRM 1.0 expression preset name to an [ExpressionName].
+/// Presets without a VRM 0.0 equivalent become custom expressions.
+pub fn expression_name_from_vrm1(name: &str) -> ExpressionName {
+    let preset = match name {
+        "happy" => PresetName::Joy,
+        "angry" => PresetName::Angry,
+        "sad" => PresetName::Sorrow,
+        "relaxed" => PresetName::Fun,
+        "aa" => PresetName::A,
+        "ih" => PresetName::I,
+        "ou" => PresetName::U,
+        "ee" => PresetName::E,
+        "oh" => PresetName::O,
+        "blink" => PresetName::Blink,
+        "blinkLeft" => PresetName::BlinkLeft,
+        "blinkRight" => PresetName::BlinkRight,
+        "lookUp" => PresetName::LookUp,
+        "lookDown" => PresetName::LookDown,
+        "lookLeft" => PresetName::LookLeft,
+        "lookRight" => PresetName::LookRight,
+        "neutral" => PresetName::Neutral,
+        _ => return ExpressionName::Custom(name.to_string()),
+    };
+
+    ExpressionName::Preset(preset)
+}
+
+/// Converts a [BoneName] to a VRM 1.0 human bone name.
+pub fn bone_name_to_vrm1(bone: BoneName) -> String {
+    match bone {
+        BoneName::LeftThumbProximal => "leftThumbMetacarpal".to_string(),
+        BoneName::LeftThumbIntermediate => "leftThumbProximal".to_string(),
+        BoneName::RightThumbProximal => "rightThumbMetacarpal".to_string(),
+        BoneName::RightThumbIntermediate => "rightThumbProximal".to_string(),
+        _ => serde_json::to_value(bone)
+            .ok()
+            .and_then(|v| v.as_str().map(str::to_string))
+            .unwrap_or_default(),
+    }
+}
+
+/// Converts an [ExpressionName] to a VRM 1.0 expression name,
+/// and whether it is a preset.
+pub fn expression_name_to_vrm1(name: &ExpressionName) -> (String, bool) {
+    let preset = match name {
+        ExpressionName::Preset(PresetName::Joy) => "happy",
+        ExpressionName::Preset(PresetName::Angry) => "angry",
+        ExpressionName::Preset(PresetName::Sorrow) => "sad",
+        ExpressionName::Preset(PresetName::Fun) => "relaxed",
+        ExpressionName::Preset(PresetName::A) => "aa",
+        ExpressionName::Preset(PresetName::I) => "ih",
+        ExpressionName::Preset(PresetName::U) => "ou",
+        ExpressionName::Preset(PresetName::E) => "ee",
+        ExpressionName::Preset(PresetName::O) => "oh",
+        ExpressionName::Preset(PresetName::Blink) => "blink",
+        ExpressionName::Preset(PresetName::BlinkLeft) => "blinkLeft",
+        ExpressionName::Preset(PresetName::BlinkRight) => "blinkRight",
+        ExpressionName::Preset(PresetName::LookUp) => "lookUp",
+        ExpressionName::Preset(PresetName::LookDown) => "lookDown",
+        ExpressionName::Preset(PresetName::LookLeft) => "lookLeft",
+        ExpressionName::Preset(PresetName::LookRight) => "lookRight",
+        ExpressionName::Preset(PresetName::Neutral) => "neutral",
+        _ => return (name.to_string(), false),
+    };
+
+    (preset.to_string(), true)
+}
+
+fn parents(graph: &Graph, doc: GltfDocument) -> HashMap<Node, Node> {
+    let mut parents = HashMap::default();
+
+    for node in doc.nodes(graph) {
+        for child in node.children(graph) {
+            parents.insert(child, node);
+        }
+    }
+
+    parents
+}
+
+fn global_transforms(graph: &Graph, doc: GltfDocument) -> HashMap<Node, Transform> {
+    let parents = parents(graph, doc);
+    let mut globals = HashMap::default();
+
+    for node in doc.nodes(graph) {
+        let mut global = Transform::IDENTITY;
+        let mut current = Some(node);
+
+        while let Some(n) = current {
+            let weight = n.get(graph);
+            let local = Transform {
+                translation: weight.translation,
+                rotation: weight.rotation,
+                scale: weight.scale,
+            };
+
+            global = local * global;
+            current = parents.get(&n).copied();
+        }
+
+        globals.insert(node, global);
+    }
+
+    globals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vrm1_bone_names() {
+        assert_eq!(bone_name_from_vrm1("hips"), Some(BoneName::Hips));
+        assert_eq!(
+            bone_name_from_vrm1("leftThumbMetacarpal"),
+            Some(BoneName::LeftThumbProximal)
+        );
+        assert_eq!(
+            bone_name_from_vrm1("rightThumbDistal"),
+            Some(BoneName::RightThumbDistal)
+        );
+        assert_eq!(bone_name_from_vrm1("tail"), None);
+
+        for bone in [
+            BoneName::Hips,
+            BoneName::LeftThumbProximal,
+            BoneName::RightThumbIntermediate,
+            BoneName::UpperChest,
+        ] {
+            assert_eq!(bone_name_from_vrm1(&bone_name_to_vrm1(bone)), Some(bone));
+        }
+    }
+
+    #[test]
+    fn vrm1_expression_names() {
+        assert_eq!(
+            expression_name_from_vrm1("happy"),
+            ExpressionName::Preset(PresetName::Joy)
+        );
+        assert_eq!(
+            expression_name_from_vrm1("surprised"),
+            ExpressionName::Custom("surprised".to_string())
+        );
+        assert_eq!(
+            expression_name_to_vrm1(&PresetName::BlinkLeft.into()),
+            ("blinkLeft".to_string(), true)
+        );
+        assert_eq!(
+            expression_name_to_vrm1(&"surprised".into()),
+            ("surprised".to_string(), false)
+        );
+    }
+
+    fn load(name: &str) -> (AnimationClip, HumanoidRestPose) {
+        let path = format!("{}/../../docs/assets/{}", env!("CARGO_MANIFEST_DIR"), name);
+        let bytes = std::fs::read(path).unwrap();
+
+        let mut graph = Graph::new();
+        let doc = bevy::tasks::block_on(GlbImport::<VrmcVrmAnimation>::import_slice(
+            &mut graph, &bytes,
+        ))
+        .unwrap();
+
+        import_vrma(&graph, doc).unwrap()
+    }
+
+    #[test]
+    fn import_files() {
+        for name in ["lmc.vrma", "cafenotako_mafty_dance.vrma"] {
+            let (clip, rest) = load(name);
+
+            assert!(clip.duration() > 0.0);
+            assert!(clip
+                .curves_for_target(VRM_ANIMATION_TARGETS[&BoneName::Hips])
+                .is_some());
+            assert!(clip
+                .curves_for_target(VRM_ANIMATION_TARGETS[&BoneName::LeftUpperArm])
+                .is_some());
+
+            // Hips are above the ground.
+            assert!(rest.bones[&BoneName::Hips].global.translation.y > 0.0);
+        }
+    }
+
+    #[test]
+    fn rotation_faces_negative_z() {
+        let q = Quat::from_rotation_x(0.5) * Quat::from_rotation_y(0.3);
+        let flip = Quat::from_rotation_y(std::f32::consts::PI);
+        let expected = flip * q * flip.inverse();
+
+        let converted = to_vrm0_rotation(q);
+        assert!(converted.abs_diff_eq(expected, 1e-5) || converted.abs_diff_eq(-expected, 1e-5));
+    }
+}
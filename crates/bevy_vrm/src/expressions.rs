@@ -0,0 +1,207 @@
+use bevy::{
+    ecs::{entity::MapEntities, reflect::ReflectMapEntities},
+    prelude::*,
+    utils::HashSet,
+};
+use serde::{
+    de::{value::StrDeserializer, IntoDeserializer},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+pub use serde_vrm::vrm0::PresetName;
+
+/// Name of an expression, either one of the VRM presets or a custom name.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Reflect)]
+pub enum ExpressionName {
+    Preset(PresetName),
+    Custom(String),
+}
+
+impl ExpressionName {
+    /// Parses a VRM 0.0 preset name, such as `joy` or `blink_l`.
+    /// Any other name is treated as a custom expression.
+    pub fn from_name(name: &str) -> Self {
+        let deserializer: StrDeserializer<serde::de::value::Error> = name.into_deserializer();
+
+        match PresetName::deserialize(deserializer) {
+            Ok(PresetName::Unknown) | Err(_) => Self::Custom(name.to_string()),
+            Ok(preset) => Self::Preset(preset),
+        }
+    }
+}
+
+impl std::fmt::Display for ExpressionName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Preset(preset) => preset.fmt(f),
+            Self::Custom(name) => f.write_str(name),
+        }
+    }
+}
+
+impl Serialize for ExpressionName {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ExpressionName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Ok(Self::from_name(&name))
+    }
+}
+
+impl From<PresetName> for ExpressionName {
+    fn from(value: PresetName) -> Self {
+        Self::Preset(value)
+    }
+}
+
+impl From<&str> for ExpressionName {
+    fn from(value: &str) -> Self {
+        Self::from_name(value)
+    }
+}
+
+/// The expressions (blend shape groups) of a VRM avatar.
+/// Inserted on the root entity of the VRM scene.
+///
+/// Set an expression's weight and the morph target weights it binds to are updated.
+#[derive(Component, Default, Reflect)]
+#[reflect(Component, MapEntities)]
+pub struct VrmExpressions(pub Vec<Expression>);
+
+#[derive(Reflect)]
+pub struct Expression {
+    pub name: ExpressionName,
+    pub is_binary: bool,
+    pub binds: Vec<MorphTargetBind>,
+    pub weight: f32,
+}
+
+/// Binds an expression to a single morph target.
+#[derive(Reflect)]
+pub struct MorphTargetBind {
+    /// Entity with the [MorphWeights] of the mesh.
+    pub entity: Entity,
+    /// Index of the morph target.
+    pub index: usize,
+    /// Weight of the morph target when the expression is fully applied, from 0 to 1.
+    pub weight: f32,
+}
+
+impl VrmExpressions {
+    pub fn get(&self, name: impl Into<ExpressionName>) -> Option<&Expression> {
+        let name = name.into();
+        self.0.iter().find(|e| e.name == name)
+    }
+
+    pub fn get_mut(&mut self, name: impl Into<ExpressionName>) -> Option<&mut Expression> {
+        let name = name.into();
+        self.0.iter_mut().find(|e| e.name == name)
+    }
+
+    /// Returns the weight of an expression, or 0 if the avatar does not have it.
+    pub fn weight(&self, name: impl Into<ExpressionName>) -> f32 {
+        self.get(name).map(|e| e.weight).unwrap_or_default()
+    }
+
+    /// Sets the weight of an expression, clamped between 0 and 1.
+    /// Does nothing if the avatar does not have the expression.
+    pub fn set_weight(&mut self, name: impl Into<ExpressionName>, weight: f32) {
+        if let Some(expression) = self.get_mut(name) {
+            expression.weight = weight.clamp(0.0, 1.0);
+        }
+    }
+}
+
+impl Expression {
+    /// Weight after applying [Expression::is_binary].
+    pub fn applied_weight(&self) -> f32 {
+        if self.is_binary {
+            if self.weight > 0.5 {
+                1.0
+            } else {
+                0.0
+            }
+        } else {
+            self.weight
+        }
+    }
+}
+
+impl MapEntities for VrmExpressions {
+    fn map_entities<M: EntityMapper>(&mut self, entity_mapper: &mut M) {
+        for expression in &mut self.0 {
+            for bind in &mut expression.binds {
+                bind.entity = entity_mapper.map_entity(bind.entity);
+            }
+        }
+    }
+}
+
+pub(crate) fn apply_expressions(
+    expressions: Query<&VrmExpressions, Changed<VrmExpressions>>,
+    mut morph_weights: Query<&mut MorphWeights>,
+) {
+    for expressions in expressions.iter() {
+        let mut targets = HashSet::new();
+
+        for bind in expressions.0.iter().flat_map(|e| e.binds.iter()) {
+            targets.insert((bind.entity, bind.index));
+        }
+
+        for (entity, index) in targets {
+            if let Ok(mut weights) = morph_weights.get_mut(entity) {
+                if let Some(w) = weights.weights_mut().get_mut(index) {
+                    *w = 0.0;
+                }
+            }
+        }
+
+        for expression in expressions.0.iter() {
+            let weight = expression.applied_weight();
+
+            if weight == 0.0 {
+                continue;
+            }
+
+            for bind in expression.binds.iter() {
+                let Ok(mut weights) = morph_weights.get_mut(bind.entity) else {
+                    continue;
+                };
+
+                if let Some(w) = weights.weights_mut().get_mut(bind.index) {
+                    *w = (*w + bind.weight * weight).min(1.0);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_from_str() {
+        assert_eq!(ExpressionName::from("joy"), PresetName::Joy.into());
+        assert_eq!(
+            ExpressionName::from("blink_l"),
+            PresetName::BlinkLeft.into()
+        );
+        assert_eq!(
+            ExpressionName::from("wink"),
+            ExpressionName::Custom("wink".to_string())
+        );
+
+        let expressions = VrmExpressions(vec![Expression {
+            name: PresetName::Joy.into(),
+            is_binary: false,
+            binds: Vec::new(),
+            weight: 0.5,
+        }]);
+        assert_eq!(expressions.weight("joy"), 0.5);
+    }
+}
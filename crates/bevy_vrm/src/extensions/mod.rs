@@ -1,44 +1,36 @@
-use bevy::{
-    animation::AnimationTarget,
-    ecs::system::RunSystemOnce,
-    prelude::*,
-    transform::systems::{propagate_transforms, sync_simple_transforms},
-};
+use std::marker::PhantomData;
+
+use bevy::prelude::*;
 use bevy_gltf_kun::import::{extensions::BevyExtensionImport, gltf::document::ImportContext};
 use gltf_kun::{
     extensions::ExtensionImport,
     graph::{
-        gltf::{GltfDocument, GltfWeight, Material, Node, Primitive, Scene},
-        ByteNode, Edge, Extensions, Graph, Weight,
+        gltf::{GltfDocument, Material, Node, Primitive, Scene},
+        Graph,
     },
     io::format::gltf::GltfFormat,
 };
-use gltf_kun_vrm::vrm0::{
-    mesh_annotation::{MeshAnnotation, MeshAnnotationEdges},
-    Vrm,
-};
-use petgraph::{visit::EdgeRef, Direction};
-use serde_vrm::vrm0::{BoneName, FirstPersonFlag};
 
-use crate::{
-    animations::vrm::VRM_ANIMATION_TARGETS,
-    spring_bones::{SpringBone, SpringBoneLogicState, SpringBones},
-};
+use crate::morph_targets::{read_target_names, MorphTargetNames};
 
-use self::vrm0::{import_material, import_primitive_material};
+#[cfg(all(feature = "vrm0", feature = "mtoon"))]
+use gltf_kun::graph::Extensions;
 
+#[cfg(feature = "vrm0")]
 pub mod vrm0;
+#[cfg(feature = "vrm1")]
 pub mod vrm1;
 
 pub struct VrmExtensions;
 
 impl ExtensionImport<GltfDocument, GltfFormat> for VrmExtensions {
     fn import(
-        graph: &mut Graph,
-        format: &mut GltfFormat,
-        doc: &GltfDocument,
+        _graph: &mut Graph,
+        _format: &mut GltfFormat,
+        _doc: &GltfDocument,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        Vrm::import(graph, format, doc)?;
+        #[cfg(feature = "vrm0")]
+        gltf_kun_vrm::vrm0::Vrm::import(_graph, _format, _doc)?;
 
         Ok(())
     }
@@ -46,12 +38,16 @@ impl ExtensionImport<GltfDocument, GltfFormat> for VrmExtensions {
 
 impl BevyExtensionImport<GltfDocument> for VrmExtensions {
     fn import_material(
-        context: &mut ImportContext,
+        _context: &mut ImportContext,
         _standard_material: &mut StandardMaterial,
-        material: Material,
+        _material: Material,
     ) {
-        if let Some(ext) = context.doc.get_extension::<Vrm>(context.graph) {
-            import_material(context, material, ext);
+        #[cfg(all(feature = "vrm0", feature = "mtoon"))]
+        if let Some(ext) = _context
+            .doc
+            .get_extension::<gltf_kun_vrm::vrm0::Vrm>(_context.graph)
+        {
+            vrm0::mtoon::import_material(_context, _material, ext);
         }
     }
 
@@ -62,330 +58,109 @@ impl BevyExtensionImport<GltfDocument> for VrmExtensions {
         entity: &mut EntityWorldMut,
         primitive: Primitive,
     ) {
-        if let Some(ext) = context.doc.get_extension::<Vrm>(context.graph) {
-            import_primitive_material(context, entity, ext, primitive);
+        #[cfg(all(feature = "vrm0", feature = "mtoon"))]
+        if let Some(ext) = context
+            .doc
+            .get_extension::<gltf_kun_vrm::vrm0::Vrm>(context.graph)
+        {
+            vrm0::mtoon::import_primitive_material(context, entity, ext, primitive);
         }
 
-        let mut flag = context
-            .graph
-            .edges_directed(primitive.0, Direction::Incoming)
-            .find_map(|edge| {
-                if let Edge::Other(name) = edge.weight() {
-                    if name == MeshAnnotationEdges::Mesh.to_string().as_str() {
-                        let annotation = MeshAnnotation(edge.source());
-                        let weight = annotation.read(context.graph);
-                        return Some(weight.first_person_flag);
-                    }
-                }
-
-                None
-            })
-            .unwrap_or_default();
-
-        if flag == FirstPersonFlag::Auto {
-            let mesh = primitive.mesh(context.graph).unwrap();
-            let nodes = mesh.nodes(context.graph);
-
-            let Some(ext) = get_vrm_extension(context.graph) else {
-                warn!("VRM extension not found");
-                return;
-            };
-
-            let head = ext
-                .human_bones(context.graph)
-                .into_iter()
-                .find(|b| {
-                    let b_weight = b.read(context.graph);
-                    b_weight.name == Some(BoneName::Head)
-                })
-                .unwrap();
-
-            let head_node = head.node(context.graph).unwrap();
-
-            for node in nodes {
-                let is_child = find_child(context.graph, node, head_node);
-
-                if is_child {
-                    flag = FirstPersonFlag::ThirdPersonOnly;
-                    break;
-                }
+        if let Some(names) = read_target_names(context.graph, primitive) {
+            if !names.is_empty() {
+                entity.insert(MorphTargetNames(names));
             }
         }
 
-        entity.insert(flag);
+        #[cfg(all(feature = "vrm0", feature = "first_person"))]
+        vrm0::import_first_person_flag(context, entity, primitive);
     }
 
     fn import_root(_context: &mut ImportContext) {}
 
-    fn import_scene(context: &mut ImportContext, _scene: Scene, world: &mut World) {
-        world.run_system_once(sync_simple_transforms);
-        world.run_system_once(propagate_transforms);
-
-        let graph = &context.graph;
-
-        let names: Vec<(Entity, Name)> =
-            world.run_system_once(|names: Query<(Entity, &Name)>| -> Vec<(Entity, Name)> {
-                names
-                    .iter()
-                    .map(|(a, b)| (a, b.clone()))
-                    .collect::<Vec<_>>()
-            });
-
-        let Some(ext) = get_vrm_extension(graph) else {
-            warn!("VRM extension not found");
-            return;
-        };
-
-        let mut spring_bones = vec![];
-
-        for bone_group in ext.bone_groups(graph) {
-            let bones = bone_group
-                .bones(graph)
-                .into_iter()
-                .filter_map(|node| {
-                    let node_handle = context.gltf.node_handles.get(&node).unwrap();
-
-                    let node_name = context.gltf.named_nodes.iter().find_map(|(name, node)| {
-                        if node == node_handle {
-                            Some(name.clone())
-                        } else {
-                            None
-                        }
-                    });
-
-                    let node_name = match node_name {
-                        Some(name) => name,
-                        None => return None,
-                    };
-
-                    names.iter().find_map(|(entity, name)| {
-                        if name.as_str() == node_name.as_str() {
-                            Some(entity)
-                        } else {
-                            None
-                        }
-                    })
-                })
-                .collect::<Vec<_>>();
-
-            let weight = bone_group.read(graph);
-
-            let gravity_dir = Vec3::new(
-                weight.gravity_dir.x,
-                weight.gravity_dir.y,
-                weight.gravity_dir.z,
-            );
-
-            spring_bones.push(SpringBone {
-                bones: bones.clone().into_iter().copied().collect(),
-                center: weight.center.unwrap_or_default(),
-                drag_force: weight.drag_force.unwrap_or_default(),
-                gravity_dir,
-                gravity_power: weight.gravity_power.unwrap_or_default(),
-                hit_radius: weight.hit_radius.unwrap_or_default(),
-                stiffness: weight.stiffiness.unwrap_or_default(),
-            });
-        }
-
-        world.run_system_once_with(
-            spring_bones,
-            |In(spring_bones): In<Vec<SpringBone>>,
-             mut commands: Commands,
-             query: Query<Entity, Without<Parent>>| {
-                commands
-                    .entity(query.single())
-                    .insert(SpringBones(spring_bones));
-            },
-        );
-
-        world.run_system_once(
-            |mut spring_boness: Query<&mut SpringBones>, children: Query<&Children>| {
-                for mut spring_bones in spring_boness.iter_mut() {
-                    for spring_bone in spring_bones.0.iter_mut() {
-                        let bones = spring_bone.bones.clone();
-                        for bone in bones {
-                            for child in children.iter_descendants(bone) {
-                                if !spring_bone.bones.contains(&child) {
-                                    spring_bone.bones.push(child);
-                                }
-                            }
-                        }
-                    }
-                }
-            },
-        );
-
-        world.run_system_once(add_springbone_logic_state);
-
-        for bone in ext.human_bones(graph) {
-            let node = match bone.node(graph) {
-                Some(n) => n,
-                None => continue,
-            };
-
-            let weight = bone.read(graph);
-
-            let bone_name = match weight.name {
-                Some(b) => b,
-                None => continue,
-            };
-
-            let node_handle = match context.gltf.node_handles.get(&node) {
-                Some(handle) => handle.clone(),
-                None => continue,
-            };
-
-            let node_name = context.gltf.named_nodes.iter().find_map(|(name, n)| {
-                if *n == node_handle {
-                    Some(name.clone())
-                } else {
-                    None
-                }
-            });
-
-            let node_name = match node_name {
-                Some(n) => n,
-                None => continue,
-            };
-
-            world.run_system_once_with(
-                (node_name, bone_name),
-                |In((node_name, bone_name)): In<(String, BoneName)>,
-                 mut commands: Commands,
-                 names: Query<(Entity, &Name)>,
-                 parents: Query<&Parent>| {
-                    let node_entity = match names.iter().find_map(|(entity, name)| {
-                        if name.as_str() == node_name.as_str() {
-                            Some(entity)
-                        } else {
-                            None
-                        }
-                    }) {
-                        Some(e) => e,
-                        None => {
-                            warn!("Could not find entity for bone: {}", bone_name);
-                            return;
-                        }
-                    };
-
-                    let mut root_entity = node_entity;
-                    while let Ok(parent) = parents.get(root_entity) {
-                        root_entity = parent.get();
-                    }
-
-                    commands
-                        .entity(root_entity)
-                        .insert(AnimationPlayer::default());
+    fn import_scene(_context: &mut ImportContext, _scene: Scene, _world: &mut World) {
+        #[cfg(feature = "vrm0")]
+        vrm0::import_scene(_context, _world);
+    }
+}
 
-                    let id = VRM_ANIMATION_TARGETS[&bone_name];
+/// Imports the VRM extensions, followed by the extensions `E`.
+pub struct VrmExtensionsWith<E>(PhantomData<E>);
 
-                    commands.entity(node_entity).insert((
-                        AnimationTarget {
-                            id,
-                            player: root_entity,
-                        },
-                        bone_name,
-                    ));
-                },
-            );
-        }
+impl<E: ExtensionImport<GltfDocument, GltfFormat>> ExtensionImport<GltfDocument, GltfFormat>
+    for VrmExtensionsWith<E>
+{
+    fn import(
+        graph: &mut Graph,
+        format: &mut GltfFormat,
+        doc: &GltfDocument,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        VrmExtensions::import(graph, format, doc)?;
+        E::import(graph, format, doc)
     }
 }
 
-fn find_child(graph: &Graph, target: Node, parent: Node) -> bool {
-    if target == parent {
-        return true;
+impl<E: BevyExtensionImport<GltfDocument>> BevyExtensionImport<GltfDocument>
+    for VrmExtensionsWith<E>
+{
+    fn import_material(
+        context: &mut ImportContext,
+        standard_material: &mut StandardMaterial,
+        material: Material,
+    ) {
+        VrmExtensions::import_material(context, standard_material, material);
+        E::import_material(context, standard_material, material);
     }
 
-    for child in parent.children(graph) {
-        if find_child(graph, target, child) {
-            return true;
-        }
+    fn import_node(context: &mut ImportContext, entity: &mut EntityWorldMut, node: Node) {
+        VrmExtensions::import_node(context, entity, node);
+        E::import_node(context, entity, node);
     }
 
-    false
-}
-
-fn get_vrm_extension(graph: &Graph) -> Option<Vrm> {
-    let doc_idx = graph.node_indices().find(|n| {
-        let weight = graph.node_weight(*n);
-        matches!(weight, Some(Weight::Gltf(GltfWeight::Document)))
-    })?;
-
-    let doc = GltfDocument(doc_idx);
+    fn import_primitive(
+        context: &mut ImportContext,
+        entity: &mut EntityWorldMut,
+        primitive: Primitive,
+    ) {
+        VrmExtensions::import_primitive(context, entity, primitive);
+        E::import_primitive(context, entity, primitive);
+    }
 
-    let ext = doc.get_extension::<Vrm>(graph)?;
+    fn import_root(context: &mut ImportContext) {
+        VrmExtensions::import_root(context);
+        E::import_root(context);
+    }
 
-    Some(ext)
+    fn import_scene(context: &mut ImportContext, scene: Scene, world: &mut World) {
+        VrmExtensions::import_scene(context, scene, world);
+        E::import_scene(context, scene, world);
+    }
 }
 
-fn add_springbone_logic_state(
-    children: Query<&Children>,
-    global_transforms: Query<&GlobalTransform>,
-    local_transforms: Query<&Transform>,
-    logic_states: Query<&mut SpringBoneLogicState>,
-    mut commands: Commands,
-    names: Query<&Name>,
-    spring_boness: Query<(Entity, &SpringBones)>,
-) {
-    for (_skel_e, spring_bones) in spring_boness.iter() {
-        for spring_bone in spring_bones.0.iter() {
-            for bone in spring_bone.bones.iter() {
-                if !logic_states.contains(*bone) {
-                    let child = match children.get(*bone) {
-                        Ok(c) => c,
-                        Err(_) => {
-                            // Adds an extra spring bone below it to make it look even better.
-                            if let Ok(name) = names.get(*bone) {
-                                if name.as_str() == "donotaddmore" {
-                                    continue;
-                                }
-                            }
-                            let child = commands
-                                .spawn((
-                                    TransformBundle {
-                                        local: Transform::from_xyz(0.0, -0.07, 0.0),
-                                        global: Default::default(),
-                                    },
-                                    Name::new("donotaddmore"),
-                                ))
-                                .id();
-
-                            commands.entity(*bone).add_child(child);
-                            continue;
-                        }
-                    };
-
-                    let mut next_bone = None;
-
-                    if let Some(c) = child.iter().next() {
-                        next_bone.replace(*c);
-                    }
-
-                    let next_bone = match next_bone {
-                        None => continue,
-                        Some(next_bone) => next_bone,
-                    };
-
-                    let global_this_bone = global_transforms.get(*bone).unwrap();
-                    let local_next_bone = local_transforms.get(next_bone).unwrap();
-                    let local_this_bone = local_transforms.get(*bone).unwrap();
+/// Finds the entity in the scene world that was spawned for a glTF node.
+#[cfg(feature = "vrm0")]
+pub(crate) fn node_entity(
+    context: &ImportContext,
+    world: &mut World,
+    node: Node,
+) -> Option<Entity> {
+    let node_handle = context.gltf.node_handles.get(&node)?;
+
+    let node_name = context.gltf.named_nodes.iter().find_map(|(name, n)| {
+        if n == node_handle {
+            Some(name.clone())
+        } else {
+            None
+        }
+    })?;
 
-                    let bone_axis = local_next_bone.translation.normalize();
-                    let bone_length = local_next_bone.translation.length();
-                    let initial_local_matrix = local_this_bone.compute_matrix();
-                    let initial_local_rotation = local_this_bone.rotation;
+    let mut names = world.query::<(Entity, &Name)>();
 
-                    commands.entity(*bone).insert(SpringBoneLogicState {
-                        prev_tail: global_this_bone.translation(),
-                        current_tail: global_this_bone.translation(),
-                        bone_axis,
-                        bone_length,
-                        initial_local_matrix,
-                        initial_local_rotation,
-                    });
-                }
-            }
+    names.iter(world).find_map(|(entity, name)| {
+        if name.as_str() == node_name.as_str() {
+            Some(entity)
+        } else {
+            None
         }
-    }
+    })
 }
@@ -1,202 +1,375 @@
-use bevy::{asset::LoadedAsset, prelude::*};
+use bevy::{
+    ecs::system::RunSystemOnce,
+    prelude::*,
+    transform::systems::{propagate_transforms, sync_simple_transforms},
+};
 use bevy_gltf_kun::import::gltf::document::ImportContext;
-use bevy_shader_mtoon::{MtoonMaterial, OutlineMode, OutlineSync};
 use gltf_kun::graph::{
-    gltf::{Material, Primitive},
-    ByteNode,
+    gltf::{GltfDocument, GltfWeight},
+    ByteNode, Extensions, Graph, Weight,
 };
-use gltf_kun_vrm::vrm0::{material_property::MaterialProperty, Vrm};
-use serde_vrm::vrm0::Shader;
-
-pub fn import_material(context: &mut ImportContext, material: Material, ext: Vrm) {
-    for (i, material_property) in ext.material_properties(context.graph).iter().enumerate() {
-        let m = match material_property.material(context.graph) {
-            Some(material) => material,
-            None => {
-                warn!("Material not found for property {}", i);
-                continue;
+use gltf_kun_vrm::vrm0::Vrm;
+use serde_vrm::vrm0::{BoneName, PresetName};
+
+use crate::{
+    expressions::{Expression, ExpressionName, MorphTargetBind, VrmExpressions},
+    look_at::{LookAtEye, LookAtRangeMap, LookAtType, VrmLookAt},
+    morph_targets::collect_morph_targets,
+};
+
+use super::node_entity;
+
+#[cfg(feature = "animations")]
+use crate::animations::{
+    expressions::spawn_expression_targets,
+    humanoid::{build_humanoid_rig, validate_humanoid},
+    params::HumanoidParams,
+    vrm::insert_humanoid_targets,
+};
+#[cfg(feature = "animations")]
+use gltf_kun_vrm::vrm0::weight::Humanoid;
+#[cfg(feature = "first_person")]
+use {
+    gltf_kun::graph::{
+        gltf::{Node, Primitive},
+        Edge,
+    },
+    gltf_kun_vrm::vrm0::mesh_annotation::{MeshAnnotation, MeshAnnotationEdges},
+    petgraph::{visit::EdgeRef, Direction},
+    serde_vrm::vrm0::FirstPersonFlag,
+};
+
+#[cfg(feature = "mtoon")]
+pub mod mtoon;
+#[cfg(feature = "spring_bones")]
+pub mod spring_bones;
+
+/// Imports the VRM 0.0 extension into the scene world.
+pub fn import_scene(context: &mut ImportContext, world: &mut World) {
+    world.run_system_once(sync_simple_transforms);
+    world.run_system_once(propagate_transforms);
+
+    let Some(ext) = get_vrm_extension(context.graph) else {
+        warn!("VRM extension not found");
+        return;
+    };
+
+    #[cfg(feature = "spring_bones")]
+    spring_bones::import_spring_bones(context, world, ext);
+
+    let graph = &context.graph;
+
+    for bone in ext.human_bones(graph) {
+        let node = match bone.node(graph) {
+            Some(n) => n,
+            None => continue,
+        };
+
+        let weight = bone.read(graph);
+
+        let bone_name = match weight.name {
+            Some(b) => b,
+            None => continue,
+        };
+
+        let node_handle = match context.gltf.node_handles.get(&node) {
+            Some(handle) => handle.clone(),
+            None => continue,
+        };
+
+        let node_name = context.gltf.named_nodes.iter().find_map(|(name, n)| {
+            if *n == node_handle {
+                Some(name.clone())
+            } else {
+                None
             }
+        });
+
+        let node_name = match node_name {
+            Some(n) => n,
+            None => continue,
         };
 
-        if m.0 != material.0 {
-            continue;
-        }
+        world.run_system_once_with(
+            (node_name, bone_name),
+            |In((node_name, bone_name)): In<(String, BoneName)>,
+             mut commands: Commands,
+             names: Query<(Entity, &Name)>| {
+                let node_entity = match names.iter().find_map(|(entity, name)| {
+                    if name.as_str() == node_name.as_str() {
+                        Some(entity)
+                    } else {
+                        None
+                    }
+                }) {
+                    Some(e) => e,
+                    None => {
+                        warn!("Could not find entity for bone: {}", bone_name);
+                        return;
+                    }
+                };
+
+                commands.entity(node_entity).insert(bone_name);
+            },
+        );
+    }
 
-        let weight = material_property.read(context.graph);
+    #[cfg(feature = "animations")]
+    import_humanoid(world, ext.read(graph).humanoid);
 
-        match weight.shader {
-            Some(Shader::MToon) => {
-                let label = mtoon_label(i);
+    collect_morph_targets(world);
+    import_expressions(context, world, ext);
+    #[cfg(feature = "animations")]
+    spawn_expression_targets(world);
+    import_look_at(context, world, ext);
+}
 
-                if !context.load_context.has_labeled_asset(label.clone()) {
-                    let mtoon = load_mtoon_shader(context, *material_property);
+/// Sets up animation of the humanoid bones, on the root entity of the scene.
+#[cfg(feature = "animations")]
+fn import_humanoid(world: &mut World, humanoid: Humanoid) {
+    insert_humanoid_targets(world);
+    build_humanoid_rig(world);
 
-                    context.load_context.add_loaded_labeled_asset(
-                        label,
-                        LoadedAsset::new_with_dependencies(mtoon, None),
-                    );
-                }
-            }
-            Some(Shader::Gltf) => {}
-            Some(other) => {
-                warn!("Unsupported shader: {:?}", other);
-            }
-            None => {}
+    let mut roots = world.query_filtered::<Entity, Without<Parent>>();
+    if let Ok(root) = roots.get_single(world) {
+        for error in validate_humanoid(world, root) {
+            warn!("Invalid VRM humanoid: {}", error);
         }
+
+        world
+            .entity_mut(root)
+            .insert((AnimationPlayer::default(), HumanoidParams::from(&humanoid)));
     }
 }
 
-pub fn import_primitive_material(
+/// Inserts the [FirstPersonFlag] of a primitive's mesh.
+#[cfg(feature = "first_person")]
+pub fn import_first_person_flag(
     context: &mut ImportContext,
     entity: &mut EntityWorldMut,
-    ext: Vrm,
     primitive: Primitive,
 ) {
-    let primitive_material = match primitive.material(context.graph) {
-        Some(material) => material,
-        None => return,
-    };
-
-    for (i, material_property) in ext.material_properties(context.graph).iter().enumerate() {
-        let material = match material_property.material(context.graph) {
-            Some(material) => material,
-            None => {
-                warn!("Material not found for property {}", i);
-                continue;
+    let mut flag = context
+        .graph
+        .edges_directed(primitive.0, Direction::Incoming)
+        .find_map(|edge| {
+            if let Edge::Other(name) = edge.weight() {
+                if name == MeshAnnotationEdges::Mesh.to_string().as_str() {
+                    let annotation = MeshAnnotation(edge.source());
+                    let weight = annotation.read(context.graph);
+                    return Some(weight.first_person_flag);
+                }
             }
-        };
 
-        if material.0 != primitive_material.0 {
-            continue;
-        }
+            None
+        })
+        .unwrap_or_default();
 
-        let weight = material_property.read(context.graph);
+    if flag == FirstPersonFlag::Auto {
+        let mesh = primitive.mesh(context.graph).unwrap();
+        let nodes = mesh.nodes(context.graph);
 
-        match weight.shader {
-            Some(Shader::MToon) => {
-                let label = mtoon_label(i);
+        let Some(ext) = get_vrm_extension(context.graph) else {
+            warn!("VRM extension not found");
+            return;
+        };
 
-                if !context.load_context.has_labeled_asset(label.clone()) {
-                    warn!("MToon material not found for property {}", i);
-                    continue;
-                }
+        let head = ext
+            .human_bones(context.graph)
+            .into_iter()
+            .find(|b| {
+                let b_weight = b.read(context.graph);
+                b_weight.name == Some(BoneName::Head)
+            })
+            .unwrap();
 
-                let handle = context
-                    .load_context
-                    .get_label_handle::<MtoonMaterial>(&label);
+        let head_node = head.node(context.graph).unwrap();
 
-                entity
-                    .remove::<Handle<StandardMaterial>>()
-                    .insert((handle, OutlineSync));
-            }
-            Some(other) => {
-                warn!("Unsupported shader: {:?}", other);
+        for node in nodes {
+            let is_child = find_child(context.graph, node, head_node);
+
+            if is_child {
+                flag = FirstPersonFlag::ThirdPersonOnly;
+                break;
             }
-            None => {}
         }
     }
+
+    entity.insert(flag);
 }
 
-fn load_mtoon_shader(
-    context: &mut ImportContext,
-    material_property: MaterialProperty,
-) -> MtoonMaterial {
-    let mut mtoon = MtoonMaterial::default();
+pub fn import_expressions(context: &mut ImportContext, world: &mut World, ext: Vrm) {
+    let mut expressions = Vec::new();
 
-    let weight = material_property.read(context.graph);
+    for group in ext.blend_shape_groups(context.graph) {
+        let weight = group.read(context.graph);
 
-    if let Some(value) = weight.float.double_sided {
-        mtoon.double_sided = value == 0.0;
-    }
+        let name = match weight.preset_name {
+            Some(PresetName::Unknown) | None => match weight.name {
+                Some(name) => ExpressionName::Custom(name),
+                None => continue,
+            },
+            Some(preset) => ExpressionName::Preset(preset),
+        };
 
-    if let Some(value) = weight.float.cutoff {
-        mtoon.alpha_mode = AlphaMode::Mask(value);
-    }
+        let mut binds = Vec::new();
 
-    if let Some(value) = weight.vector.color {
-        mtoon.base_color = LinearRgba::from_f32_array(value).into();
-    }
+        for bind in group.binds(context.graph) {
+            let Some(mesh) = bind.mesh(context.graph) else {
+                continue;
+            };
 
-    if let Some(texture) = material_property.main_texture(context.graph) {
-        let index = context.doc.texture_index(context.graph, texture).unwrap();
-        let label = texture_label(index);
-        let handle = context.load_context.get_label_handle(&label);
-        mtoon.base_color_texture = Some(handle);
-    }
+            let bind_weight = bind.read(context.graph);
 
-    if let Some(value) = weight.float.normal_scale {
-        mtoon.normal_map_scale = value;
-    }
+            let Some(index) = bind_weight.index else {
+                continue;
+            };
 
-    if let Some(texture) = material_property.bump_map(context.graph) {
-        let index = context.doc.texture_index(context.graph, texture).unwrap();
-        let label = texture_label(index);
-        let handle = context.load_context.get_label_handle(&label);
-        mtoon.normal_map_texture = Some(handle);
-    }
+            for node in mesh.nodes(context.graph) {
+                let Some(entity) = node_entity(context, world, node) else {
+                    warn!("Could not find entity for blend shape bind");
+                    continue;
+                };
 
-    if let Some(value) = weight.vector.emissive_factor {
-        mtoon.emissive_factor = LinearRgba::from_f32_array(value).into();
-    }
+                binds.push(MorphTargetBind {
+                    entity,
+                    index: index as usize,
+                    weight: bind_weight.weight.unwrap_or_default() / 100.0,
+                });
+            }
+        }
 
-    if let Some(texture) = material_property.emission_map(context.graph) {
-        let index = context.doc.texture_index(context.graph, texture).unwrap();
-        let label = texture_label(index);
-        let handle = context.load_context.get_label_handle(&label);
-        mtoon.emissive_texture = Some(handle);
+        expressions.push(Expression {
+            name,
+            is_binary: weight.is_binary.unwrap_or_default(),
+            binds,
+            weight: 0.0,
+        });
     }
 
-    if let Some(value) = weight.float.outline_factor {
-        mtoon.outline_width = value;
-    }
+    let mut roots = world.query_filtered::<Entity, Without<Parent>>();
+    let root = roots.single(world);
 
-    if let Some(value) = weight.vector.outline_color {
-        mtoon.outline_color = LinearRgba::from_f32_array(value).into();
-    }
+    world.entity_mut(root).insert(VrmExpressions(expressions));
+}
 
-    if let Some(value) = weight.keyword_map.outline_width_world {
-        if value {
-            mtoon.outline_mode = OutlineMode::World;
-        } else {
-            mtoon.outline_mode = OutlineMode::Screen;
+pub fn import_look_at(context: &mut ImportContext, world: &mut World, ext: Vrm) {
+    let first_person = ext.read(context.graph).first_person;
+
+    let head_bone = ext.first_person_bone(context.graph).or_else(|| {
+        ext.human_bones(context.graph)
+            .into_iter()
+            .find(|b| b.read(context.graph).name == Some(BoneName::Head))
+    });
+
+    let Some(head_node) = head_bone.and_then(|b| b.node(context.graph)) else {
+        warn!("No head bone found for look at");
+        return;
+    };
+
+    let Some(head) = node_entity(context, world, head_node) else {
+        warn!("Could not find entity for look at head bone");
+        return;
+    };
+
+    let head_rest = world
+        .get::<GlobalTransform>(head)
+        .map(|t| t.to_scale_rotation_translation().1)
+        .unwrap_or_default();
+
+    let kind = first_person
+        .look_at_type_name
+        .as_deref()
+        .and_then(LookAtType::from_type_name)
+        .unwrap_or_default();
+
+    let mut look_at = VrmLookAt::new(kind, head, head_rest);
+
+    // VRM 0.0 stores the offset in Unity's left-handed coordinates.
+    let offset = first_person.first_person_bone_offset;
+    look_at.offset = Vec3::new(offset.x, offset.y, -offset.z);
+
+    let default = kind.default_range_map();
+
+    for (curve, range) in [
+        (
+            &first_person.look_at_horizontal_inner,
+            &mut look_at.horizontal_inner,
+        ),
+        (
+            &first_person.look_at_horizontal_outer,
+            &mut look_at.horizontal_outer,
+        ),
+        (
+            &first_person.look_at_vertical_down,
+            &mut look_at.vertical_down,
+        ),
+        (&first_person.look_at_vertical_up, &mut look_at.vertical_up),
+    ] {
+        if let Some(curve) = curve {
+            *range = LookAtRangeMap::from_curve(curve, default.clone());
         }
     }
 
-    if let Some(value) = weight.float.gi_intensity_factor {
-        mtoon.gi_equalization_factor = 1.0 - value;
-    }
+    let mut bones = world.query::<(Entity, &BoneName, &Parent)>();
 
-    if let Some(value) = weight.float.shade_shift {
-        mtoon.shading_shift_factor = -value;
-    }
+    let eyes = bones
+        .iter(world)
+        .filter(|(_, name, _)| matches!(name, BoneName::LeftEye | BoneName::RightEye))
+        .map(|(entity, name, parent)| (entity, *name, parent.get()))
+        .collect::<Vec<_>>();
 
-    if let Some(value) = weight.float.shade_toony {
-        mtoon.shading_toony_factor = value;
-    }
+    for (entity, name, parent) in eyes {
+        let rotation = |entity: Entity| {
+            world
+                .get::<GlobalTransform>(entity)
+                .map(|t| t.to_scale_rotation_translation().1)
+                .unwrap_or_default()
+        };
 
-    if let Some(value) = weight.vector.shade_color {
-        mtoon.shade_factor = LinearRgba::from_f32_array(value).into();
-    }
+        let eye = LookAtEye {
+            entity,
+            rest: rotation(entity),
+            parent_rest: rotation(parent),
+        };
 
-    if let Some(texture) = material_property.shade_texture(context.graph) {
-        let index = context
-            .doc
-            .textures(context.graph)
-            .iter()
-            .position(|t| t.0 == texture.0)
-            .unwrap();
-        let label = texture_label(index);
-        let handle = context.load_context.get_label_handle(&label);
-        mtoon.shade_multiply_texture = Some(handle);
+        if name == BoneName::LeftEye {
+            look_at.left_eye = Some(eye);
+        } else {
+            look_at.right_eye = Some(eye);
+        }
     }
 
-    mtoon
+    let mut roots = world.query_filtered::<Entity, Without<Parent>>();
+    let root = roots.single(world);
+
+    world.entity_mut(root).insert(look_at);
 }
 
-fn mtoon_label(index: usize) -> String {
-    format!("MaterialMtoon{}", index)
+fn get_vrm_extension(graph: &Graph) -> Option<Vrm> {
+    let doc_idx = graph.node_indices().find(|n| {
+        let weight = graph.node_weight(*n);
+        matches!(weight, Some(Weight::Gltf(GltfWeight::Document)))
+    })?;
+
+    let doc = GltfDocument(doc_idx);
+
+    let ext = doc.get_extension::<Vrm>(graph)?;
+
+    Some(ext)
 }
 
-fn texture_label(index: usize) -> String {
-    format!("Texture{}", index)
+#[cfg(feature = "first_person")]
+fn find_child(graph: &Graph, target: Node, parent: Node) -> bool {
+    if target == parent {
+        return true;
+    }
+
+    for child in parent.children(graph) {
+        if find_child(graph, target, child) {
+            return true;
+        }
+    }
+
+    false
 }
@@ -0,0 +1,171 @@
+use bevy::{ecs::system::RunSystemOnce, prelude::*};
+use bevy_gltf_kun::import::gltf::document::ImportContext;
+use gltf_kun::graph::ByteNode;
+use gltf_kun_vrm::vrm0::Vrm;
+
+use crate::spring_bones::{SpringBone, SpringBoneLogicState, SpringBones};
+
+/// Inserts the [SpringBones] of the VRM on the root entity of the scene.
+pub fn import_spring_bones(context: &mut ImportContext, world: &mut World, ext: Vrm) {
+    let graph = &context.graph;
+
+    let names: Vec<(Entity, Name)> =
+        world.run_system_once(|names: Query<(Entity, &Name)>| -> Vec<(Entity, Name)> {
+            names
+                .iter()
+                .map(|(a, b)| (a, b.clone()))
+                .collect::<Vec<_>>()
+        });
+
+    let mut spring_bones = vec![];
+
+    for bone_group in ext.bone_groups(graph) {
+        let bones = bone_group
+            .bones(graph)
+            .into_iter()
+            .filter_map(|node| {
+                let node_handle = context.gltf.node_handles.get(&node).unwrap();
+
+                let node_name = context.gltf.named_nodes.iter().find_map(|(name, node)| {
+                    if node == node_handle {
+                        Some(name.clone())
+                    } else {
+                        None
+                    }
+                });
+
+                let node_name = match node_name {
+                    Some(name) => name,
+                    None => return None,
+                };
+
+                names.iter().find_map(|(entity, name)| {
+                    if name.as_str() == node_name.as_str() {
+                        Some(entity)
+                    } else {
+                        None
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let weight = bone_group.read(graph);
+
+        let gravity_dir = Vec3::new(
+            weight.gravity_dir.x,
+            weight.gravity_dir.y,
+            weight.gravity_dir.z,
+        );
+
+        spring_bones.push(SpringBone {
+            bones: bones.clone().into_iter().copied().collect(),
+            center: weight.center.unwrap_or_default(),
+            drag_force: weight.drag_force.unwrap_or_default(),
+            gravity_dir,
+            gravity_power: weight.gravity_power.unwrap_or_default(),
+            hit_radius: weight.hit_radius.unwrap_or_default(),
+            stiffness: weight.stiffiness.unwrap_or_default(),
+        });
+    }
+
+    world.run_system_once_with(
+        spring_bones,
+        |In(spring_bones): In<Vec<SpringBone>>,
+         mut commands: Commands,
+         query: Query<Entity, Without<Parent>>| {
+            commands
+                .entity(query.single())
+                .insert(SpringBones(spring_bones));
+        },
+    );
+
+    world.run_system_once(
+        |mut spring_boness: Query<&mut SpringBones>, children: Query<&Children>| {
+            for mut spring_bones in spring_boness.iter_mut() {
+                for spring_bone in spring_bones.0.iter_mut() {
+                    let bones = spring_bone.bones.clone();
+                    for bone in bones {
+                        for child in children.iter_descendants(bone) {
+                            if !spring_bone.bones.contains(&child) {
+                                spring_bone.bones.push(child);
+                            }
+                        }
+                    }
+                }
+            }
+        },
+    );
+
+    world.run_system_once(add_springbone_logic_state);
+}
+
+fn add_springbone_logic_state(
+    children: Query<&Children>,
+    global_transforms: Query<&GlobalTransform>,
+    local_transforms: Query<&Transform>,
+    logic_states: Query<&mut SpringBoneLogicState>,
+    mut commands: Commands,
+    names: Query<&Name>,
+    spring_boness: Query<(Entity, &SpringBones)>,
+) {
+    for (_skel_e, spring_bones) in spring_boness.iter() {
+        for spring_bone in spring_bones.0.iter() {
+            for bone in spring_bone.bones.iter() {
+                if !logic_states.contains(*bone) {
+                    let child = match children.get(*bone) {
+                        Ok(c) => c,
+                        Err(_) => {
+                            // Adds an extra spring bone below it to make it look even better.
+                            if let Ok(name) = names.get(*bone) {
+                                if name.as_str() == "donotaddmore" {
+                                    continue;
+                                }
+                            }
+                            let child = commands
+                                .spawn((
+                                    TransformBundle {
+                                        local: Transform::from_xyz(0.0, -0.07, 0.0),
+                                        global: Default::default(),
+                                    },
+                                    Name::new("donotaddmore"),
+                                ))
+                                .id();
+
+                            commands.entity(*bone).add_child(child);
+                            continue;
+                        }
+                    };
+
+                    let mut next_bone = None;
+
+                    if let Some(c) = child.iter().next() {
+                        next_bone.replace(*c);
+                    }
+
+                    let next_bone = match next_bone {
+                        None => continue,
+                        Some(next_bone) => next_bone,
+                    };
+
+                    let global_this_bone = global_transforms.get(*bone).unwrap();
+                    let local_next_bone = local_transforms.get(next_bone).unwrap();
+                    let local_this_bone = local_transforms.get(*bone).unwrap();
+
+                    let bone_axis = local_next_bone.translation.normalize();
+                    let bone_length = local_next_bone.translation.length();
+                    let initial_local_matrix = local_this_bone.compute_matrix();
+                    let initial_local_rotation = local_this_bone.rotation;
+
+                    commands.entity(*bone).insert(SpringBoneLogicState {
+                        prev_tail: global_this_bone.translation(),
+                        current_tail: global_this_bone.translation(),
+                        bone_axis,
+                        bone_length,
+                        initial_local_matrix,
+                        initial_local_rotation,
+                    });
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,611 @@
+//! Drives VRM expressions from ARKit-style face capture.
+//!
+//! Face capture apps output the 52 ARKit blend shape coefficients, such as `jawOpen` or
+//! `eyeBlinkLeft`, from 0 to 1. A [FaceMapping] maps these to VRM expressions.
+//! Avatars made for "perfect sync" have custom expressions or morph targets named after
+//! the ARKit blend shapes, which are driven directly.
+//!
+//! Coefficients are read from [ArkitBlendShapes], which can be filled manually or
+//! received over UDP using [FaceTrackingPlugin::address].
+
+use std::{
+    io::ErrorKind,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+};
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    expressions::{ExpressionName, PresetName, VrmExpressions},
+    morph_targets::{MorphTarget, VrmMorphTargets},
+};
+
+/// Names of the 52 ARKit blend shapes.
+pub const ARKIT_BLEND_SHAPES: [&str; 52] = [
+    "browDownLeft",
+    "browDownRight",
+    "browInnerUp",
+    "browOuterUpLeft",
+    "browOuterUpRight",
+    "cheekPuff",
+    "cheekSquintLeft",
+    "cheekSquintRight",
+    "eyeBlinkLeft",
+    "eyeBlinkRight",
+    "eyeLookDownLeft",
+    "eyeLookDownRight",
+    "eyeLookInLeft",
+    "eyeLookInRight",
+    "eyeLookOutLeft",
+    "eyeLookOutRight",
+    "eyeLookUpLeft",
+    "eyeLookUpRight",
+    "eyeSquintLeft",
+    "eyeSquintRight",
+    "eyeWideLeft",
+    "eyeWideRight",
+    "jawForward",
+    "jawLeft",
+    "jawOpen",
+    "jawRight",
+    "mouthClose",
+    "mouthDimpleLeft",
+    "mouthDimpleRight",
+    "mouthFrownLeft",
+    "mouthFrownRight",
+    "mouthFunnel",
+    "mouthLeft",
+    "mouthLowerDownLeft",
+    "mouthLowerDownRight",
+    "mouthPressLeft",
+    "mouthPressRight",
+    "mouthPucker",
+    "mouthRight",
+    "mouthRollLower",
+    "mouthRollUpper",
+    "mouthShrugLower",
+    "mouthShrugUpper",
+    "mouthSmileLeft",
+    "mouthSmileRight",
+    "mouthStretchLeft",
+    "mouthStretchRight",
+    "mouthUpperUpLeft",
+    "mouthUpperUpRight",
+    "noseSneerLeft",
+    "noseSneerRight",
+    "tongueOut",
+];
+
+/// Latest ARKit blend shape coefficients, from 0 to 1, keyed by name.
+#[derive(Resource, Default, Debug, Clone)]
+pub struct ArkitBlendShapes(pub HashMap<String, f32>);
+
+/// Maps ARKit blend shapes to VRM expressions.
+///
+/// Can be loaded from `.face.ron` or `.face.json` files.
+#[derive(Asset, TypePath, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct FaceMapping {
+    /// Drive custom expressions or morph targets named after ARKit blend shapes directly,
+    /// ignoring [FaceMapping::rules], if the avatar has any.
+    /// Morph targets are driven for blend shapes without a matching expression.
+    #[serde(default = "default_perfect_sync")]
+    pub perfect_sync: bool,
+    #[serde(default)]
+    pub rules: Vec<FaceMappingRule>,
+}
+
+fn default_perfect_sync() -> bool {
+    true
+}
+
+/// Adds `blend_shape * weight` to an expression.
+/// The sum of all rules for an expression is clamped between 0 and 1.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct FaceMappingRule {
+    pub blend_shape: String,
+    pub expression: ExpressionName,
+    #[serde(default = "default_rule_weight")]
+    pub weight: f32,
+}
+
+fn default_rule_weight() -> f32 {
+    1.0
+}
+
+impl FaceMappingRule {
+    pub fn new(blend_shape: &str, expression: impl Into<ExpressionName>, weight: f32) -> Self {
+        Self {
+            blend_shape: blend_shape.to_string(),
+            expression: expression.into(),
+            weight,
+        }
+    }
+}
+
+impl Default for FaceMapping {
+    fn default() -> Self {
+        Self {
+            perfect_sync: true,
+            rules: vec![
+                FaceMappingRule::new("jawOpen", PresetName::A, 1.0),
+                FaceMappingRule::new("mouthStretchLeft", PresetName::I, 0.5),
+                FaceMappingRule::new("mouthStretchRight", PresetName::I, 0.5),
+                FaceMappingRule::new("mouthPucker", PresetName::U, 1.0),
+                FaceMappingRule::new("mouthLowerDownLeft", PresetName::E, 0.5),
+                FaceMappingRule::new("mouthLowerDownRight", PresetName::E, 0.5),
+                FaceMappingRule::new("mouthFunnel", PresetName::O, 1.0),
+                FaceMappingRule::new("eyeBlinkLeft", PresetName::BlinkLeft, 1.0),
+                FaceMappingRule::new("eyeBlinkRight", PresetName::BlinkRight, 1.0),
+                FaceMappingRule::new("mouthSmileLeft", PresetName::Joy, 0.5),
+                FaceMappingRule::new("mouthSmileRight", PresetName::Joy, 0.5),
+                FaceMappingRule::new("browDownLeft", PresetName::Angry, 0.5),
+                FaceMappingRule::new("browDownRight", PresetName::Angry, 0.5),
+                FaceMappingRule::new("mouthFrownLeft", PresetName::Sorrow, 0.5),
+                FaceMappingRule::new("mouthFrownRight", PresetName::Sorrow, 0.5),
+            ],
+        }
+    }
+}
+
+impl FaceMapping {
+    pub fn from_ron(s: &str) -> Result<Self, FaceMappingError> {
+        Ok(ron::from_str(s)?)
+    }
+
+    pub fn from_json(s: &str) -> Result<Self, FaceMappingError> {
+        Ok(serde_json::from_str(s)?)
+    }
+
+    /// Calculates expression weights for the given coefficients.
+    ///
+    /// Every expression the mapping targets is included, with a weight of 0 if none
+    /// of its blend shapes are present.
+    pub fn evaluate(
+        &self,
+        blend_shapes: &ArkitBlendShapes,
+        expressions: &VrmExpressions,
+    ) -> HashMap<ExpressionName, f32> {
+        let mut weights = HashMap::default();
+
+        if self.perfect_sync {
+            for expression in expressions.0.iter() {
+                let ExpressionName::Custom(name) = &expression.name else {
+                    continue;
+                };
+
+                let Some(blend_shape) = arkit_blend_shape(name) else {
+                    continue;
+                };
+
+                let value = blend_shapes.0.get(blend_shape).copied();
+                weights.insert(expression.name.clone(), value.unwrap_or_default());
+            }
+
+            if !weights.is_empty() {
+                return weights;
+            }
+        }
+
+        for rule in self.rules.iter() {
+            let value = blend_shapes
+                .0
+                .get(&rule.blend_shape)
+                .copied()
+                .unwrap_or_default();
+
+            *weights.entry(rule.expression.clone()).or_default() += value * rule.weight;
+        }
+
+        for weight in weights.values_mut() {
+            *weight = weight.clamp(0.0, 1.0);
+        }
+
+        weights
+    }
+}
+
+/// Returns the ARKit blend shape a morph target or expression is named after.
+/// Ignores case and any prefix before a `.`, such as `blendShape1.jawOpen`.
+pub fn arkit_blend_shape(name: &str) -> Option<&'static str> {
+    let name = name.rsplit('.').next().unwrap_or(name);
+
+    ARKIT_BLEND_SHAPES
+        .iter()
+        .find(|b| b.eq_ignore_ascii_case(name))
+        .copied()
+}
+
+/// Finds the morph targets named after ARKit blend shapes.
+pub fn perfect_sync_morph_targets(
+    morph_targets: &VrmMorphTargets,
+) -> Vec<(&'static str, MorphTarget)> {
+    morph_targets
+        .0
+        .iter()
+        .flat_map(|mesh| {
+            mesh.names.iter().enumerate().filter_map(|(index, name)| {
+                arkit_blend_shape(name).map(|blend_shape| {
+                    (
+                        blend_shape,
+                        MorphTarget {
+                            entity: mesh.entity,
+                            index,
+                        },
+                    )
+                })
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Error)]
+pub enum FaceMappingError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Ron(#[from] ron::error::SpannedError),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+#[derive(Default)]
+pub struct FaceMappingLoader;
+
+impl AssetLoader for FaceMappingLoader {
+    type Asset = FaceMapping;
+    type Settings = ();
+    type Error = FaceMappingError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a Self::Settings,
+        load_context: &'a mut LoadContext,
+    ) -> impl bevy::utils::ConditionalSendFuture<Output = Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut s = String::new();
+            reader.read_to_string(&mut s).await?;
+
+            let is_json = load_context
+                .path()
+                .extension()
+                .is_some_and(|ext| ext == "json");
+
+            if is_json {
+                FaceMapping::from_json(&s)
+            } else {
+                FaceMapping::from_ron(&s)
+            }
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["face.ron", "face.json"]
+    }
+}
+
+/// Drives the [VrmExpressions] of this entity from [ArkitBlendShapes].
+/// Insert on the root entity of the VRM scene.
+///
+/// Uses [FaceMapping::default] if no mapping is set.
+#[derive(Component, Default, Reflect)]
+#[reflect(Component)]
+pub struct FaceTracking {
+    pub mapping: Option<Handle<FaceMapping>>,
+}
+
+/// Receives [ArkitBlendShapes] over UDP.
+///
+/// Each packet is a JSON object of blend shape names to coefficients,
+/// such as `{"jawOpen": 0.5, "eyeBlinkLeft": 1.0}`.
+#[derive(Resource)]
+pub struct FaceTrackingSocket(UdpSocket);
+
+impl FaceTrackingSocket {
+    pub fn bind(address: impl ToSocketAddrs) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind(address)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self(socket))
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.0.local_addr()
+    }
+}
+
+#[derive(Default)]
+pub struct FaceTrackingPlugin {
+    /// Address to receive blend shapes on.
+    /// If `None`, [ArkitBlendShapes] must be set manually.
+    pub address: Option<SocketAddr>,
+}
+
+impl Plugin for FaceTrackingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<FaceMapping>()
+            .init_asset_loader::<FaceMappingLoader>()
+            .init_resource::<ArkitBlendShapes>()
+            .register_type::<FaceTracking>()
+            .add_systems(
+                Update,
+                (
+                    receive_blend_shapes.run_if(resource_exists::<FaceTrackingSocket>),
+                    apply_face_tracking,
+                )
+                    .chain(),
+            );
+
+        if let Some(address) = self.address {
+            match FaceTrackingSocket::bind(address) {
+                Ok(socket) => {
+                    app.insert_resource(socket);
+                }
+                Err(e) => {
+                    error!("Failed to bind face tracking socket {}: {}", address, e);
+                }
+            }
+        }
+    }
+}
+
+fn receive_blend_shapes(
+    socket: Res<FaceTrackingSocket>,
+    mut blend_shapes: ResMut<ArkitBlendShapes>,
+) {
+    let mut buf = [0; 4096];
+
+    loop {
+        let len = match socket.0.recv(&mut buf) {
+            Ok(len) => len,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+            Err(e) => {
+                warn!("Failed to receive face tracking data: {}", e);
+                break;
+            }
+        };
+
+        match serde_json::from_slice::<HashMap<String, f32>>(&buf[..len]) {
+            Ok(values) => blend_shapes.0.extend(values),
+            Err(e) => warn!("Invalid face tracking data: {}", e),
+        }
+    }
+}
+
+fn apply_face_tracking(
+    blend_shapes: Res<ArkitBlendShapes>,
+    mappings: Res<Assets<FaceMapping>>,
+    mut avatars: Query<(&FaceTracking, &mut VrmExpressions, Option<&VrmMorphTargets>)>,
+    mut morph_weights: Query<&mut MorphWeights>,
+    default_mapping: Local<FaceMapping>,
+) {
+    for (face_tracking, mut expressions, morph_targets) in avatars.iter_mut() {
+        let mapping = match &face_tracking.mapping {
+            Some(handle) => match mappings.get(handle) {
+                Some(mapping) => mapping,
+                None => continue,
+            },
+            None => &default_mapping,
+        };
+
+        if mapping.perfect_sync {
+            let sync_expressions = expressions
+                .0
+                .iter()
+                .filter_map(|e| match &e.name {
+                    ExpressionName::Custom(name) => arkit_blend_shape(name),
+                    ExpressionName::Preset(_) => None,
+                })
+                .collect::<HashSet<_>>();
+
+            let targets = morph_targets
+                .map(perfect_sync_morph_targets)
+                .unwrap_or_default();
+
+            // Blend shapes without a matching expression drive their morph targets directly.
+            for (blend_shape, target) in targets.iter() {
+                if sync_expressions.contains(blend_shape) {
+                    continue;
+                }
+
+                let Ok(mut weights) = morph_weights.get_mut(target.entity) else {
+                    continue;
+                };
+
+                if let Some(w) = weights.weights_mut().get_mut(target.index) {
+                    *w = blend_shapes
+                        .0
+                        .get(*blend_shape)
+                        .copied()
+                        .unwrap_or_default();
+                }
+            }
+
+            if sync_expressions.is_empty() && !targets.is_empty() {
+                continue;
+            }
+        }
+
+        let weights = mapping.evaluate(&blend_shapes, &expressions);
+
+        for (name, weight) in weights {
+            expressions.set_weight(name, weight);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{expressions::Expression, morph_targets::MorphTargetMesh};
+
+    use super::*;
+
+    fn expressions(names: &[ExpressionName]) -> VrmExpressions {
+        VrmExpressions(
+            names
+                .iter()
+                .map(|name| Expression {
+                    name: name.clone(),
+                    is_binary: false,
+                    binds: Vec::new(),
+                    weight: 0.0,
+                })
+                .collect(),
+        )
+    }
+
+    fn blend_shapes(values: &[(&str, f32)]) -> ArkitBlendShapes {
+        ArkitBlendShapes(
+            values
+                .iter()
+                .map(|(name, value)| (name.to_string(), *value))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn parse_ron_and_json() {
+        let ron = r#"(
+            perfect_sync: false,
+            rules: [
+                (blend_shape: "jawOpen", expression: "a"),
+                (blend_shape: "cheekPuff", expression: "Puff", weight: 0.5),
+            ],
+        )"#;
+
+        let json = r#"{
+            "perfect_sync": false,
+            "rules": [
+                { "blend_shape": "jawOpen", "expression": "a" },
+                { "blend_shape": "cheekPuff", "expression": "Puff", "weight": 0.5 }
+            ]
+        }"#;
+
+        let expected = FaceMapping {
+            perfect_sync: false,
+            rules: vec![
+                FaceMappingRule::new("jawOpen", PresetName::A, 1.0),
+                FaceMappingRule::new("cheekPuff", "Puff", 0.5),
+            ],
+        };
+
+        assert_eq!(FaceMapping::from_ron(ron).unwrap(), expected);
+        assert_eq!(FaceMapping::from_json(json).unwrap(), expected);
+    }
+
+    #[test]
+    fn rules_are_summed_and_clamped() {
+        let mapping = FaceMapping::default();
+        let expressions = expressions(&[PresetName::Joy.into(), PresetName::A.into()]);
+
+        let weights = mapping.evaluate(
+            &blend_shapes(&[("mouthSmileLeft", 1.0), ("mouthSmileRight", 0.5)]),
+            &expressions,
+        );
+        assert_eq!(weights[&ExpressionName::Preset(PresetName::Joy)], 0.75);
+        assert_eq!(weights[&ExpressionName::Preset(PresetName::A)], 0.0);
+
+        let weights = mapping.evaluate(&blend_shapes(&[("jawOpen", 2.0)]), &expressions);
+        assert_eq!(weights[&ExpressionName::Preset(PresetName::A)], 1.0);
+    }
+
+    #[test]
+    fn perfect_sync_overrides_rules() {
+        let mapping = FaceMapping::default();
+        let expressions = expressions(&[PresetName::A.into(), "JawOpen".into()]);
+
+        let weights = mapping.evaluate(&blend_shapes(&[("jawOpen", 0.3)]), &expressions);
+        assert_eq!(weights.len(), 1);
+        assert_eq!(weights[&ExpressionName::from("JawOpen")], 0.3);
+    }
+
+    #[test]
+    fn match_arkit_names() {
+        assert_eq!(arkit_blend_shape("JawOpen"), Some("jawOpen"));
+        assert_eq!(
+            arkit_blend_shape("blendShape1.eyeBlinkLeft"),
+            Some("eyeBlinkLeft")
+        );
+        assert_eq!(arkit_blend_shape("Fcl_MTH_A"), None);
+    }
+
+    #[test]
+    fn receive_over_udp() {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+            .add_plugins(FaceTrackingPlugin::default());
+
+        let socket = FaceTrackingSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        app.insert_resource(socket);
+
+        let avatar = app
+            .world_mut()
+            .spawn((
+                FaceTracking::default(),
+                expressions(&[PresetName::A.into(), PresetName::BlinkLeft.into()]),
+            ))
+            .id();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender
+            .send_to(br#"{"jawOpen": 0.5, "eyeBlinkLeft": 1.0}"#, address)
+            .unwrap();
+
+        for _ in 0..100 {
+            app.update();
+
+            if !app.world().resource::<ArkitBlendShapes>().0.is_empty() {
+                break;
+            }
+
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let expressions = app.world().get::<VrmExpressions>(avatar).unwrap();
+        assert_eq!(expressions.weight(PresetName::A), 0.5);
+        assert_eq!(expressions.weight(PresetName::BlinkLeft), 1.0);
+    }
+
+    #[test]
+    fn perfect_sync_falls_back_per_blend_shape() {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+            .add_plugins(FaceTrackingPlugin::default())
+            .insert_resource(blend_shapes(&[("jawOpen", 0.5), ("eyeBlinkLeft", 1.0)]));
+
+        let mesh = app
+            .world_mut()
+            .spawn(MorphWeights::new(vec![0.0, 0.0], None).unwrap())
+            .id();
+
+        let avatar = app
+            .world_mut()
+            .spawn((
+                FaceTracking::default(),
+                expressions(&["JawOpen".into()]),
+                VrmMorphTargets(vec![MorphTargetMesh {
+                    entity: mesh,
+                    primitives: Vec::new(),
+                    names: vec!["jawOpen".to_string(), "eyeBlinkLeft".to_string()],
+                }]),
+            ))
+            .id();
+
+        app.update();
+
+        // jawOpen is driven through its expression, eyeBlinkLeft has none.
+        let expressions = app.world().get::<VrmExpressions>(avatar).unwrap();
+        assert_eq!(expressions.weight("JawOpen"), 0.5);
+
+        let weights = app.world().get::<MorphWeights>(mesh).unwrap();
+        assert_eq!(weights.weights(), &[0.0, 1.0]);
+    }
+}
@@ -8,7 +8,6 @@ use bevy::{
     },
     utils::{HashMap, HashSet},
 };
-use bevy_shader_mtoon::MtoonMaterial;
 use serde_vrm::vrm0::BoneName;
 
 pub use serde_vrm::vrm0::FirstPersonFlag;
@@ -50,14 +49,14 @@ pub(crate) fn handle_setup_events(
         &Handle<Mesh>,
         Option<&Name>,
         Option<&Handle<StandardMaterial>>,
-        Option<&Handle<MtoonMaterial>>,
         Option<&MeshMorphWeights>,
+        Option<&SkinnedMesh>,
     )>,
+    #[cfg(feature = "mtoon")] mtoon_materials: Query<&Handle<crate::mtoon::MtoonMaterial>>,
     mut commands: Commands,
     mut events: EventReader<SetupFirstPerson>,
     mut meshes: ResMut<Assets<Mesh>>,
     parents: Query<&Parent>,
-    skins: Query<&SkinnedMesh>,
 ) {
     if bones.is_empty() {
         return;
@@ -69,7 +68,7 @@ pub(crate) fn handle_setup_events(
             .find(|(e, name)| **name == BoneName::Head && is_child(*e, event.0, &parents))
             .unwrap();
 
-        for (ent, mut flag, mesh_handle, name, standard_material, mtoon_material, morph_weights) in
+        for (ent, mut flag, mesh_handle, name, standard_material, morph_weights, skin) in
             flags.iter_mut()
         {
             // If auto, split the mesh into first-person and third-person variants.
@@ -94,7 +93,7 @@ pub(crate) fn handle_setup_events(
                     continue;
                 };
 
-                let Ok(skin) = skins.get(ent) else {
+                let Some(skin) = skin else {
                     continue;
                 };
 
@@ -140,7 +139,8 @@ pub(crate) fn handle_setup_events(
                     ))
                     .id();
 
-                if let Some(v) = mtoon_material {
+                #[cfg(feature = "mtoon")]
+                if let Ok(v) = mtoon_materials.get(ent) {
                     commands.entity(new_ent).insert(v.clone());
                 }
 
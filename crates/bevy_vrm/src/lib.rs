@@ -2,53 +2,194 @@
 //! Aims to support both the VRM 0.0 and VRM 1.0 standards.
 
 use auto_scene::AutoScene;
-use bevy::{app::PluginGroupBuilder, prelude::*};
+use bevy::{
+    app::PluginGroupBuilder,
+    asset::Asset,
+    prelude::*,
+    render::{
+        mesh::{
+            morph::{inherit_weights, MeshMorphWeights, MorphWeights},
+            skinning::{SkinnedMesh, SkinnedMeshInverseBindposes},
+        },
+        primitives::Aabb,
+        view::RenderLayers,
+        RenderApp,
+    },
+    transform::TransformSystem,
+};
 use bevy_gltf_kun::GltfKunPlugin;
-use bevy_shader_mtoon::MtoonPlugin;
-use first_person::SetupFirstPerson;
-use loader::{Vrm, VrmLoader};
-use serde_vrm::vrm0::FirstPersonFlag;
-
-use crate::spring_bones::SpringBonePlugin;
+use expressions::{ExpressionName, VrmExpressions};
+use loader::{Vrm, VrmLoader, VrmLoaderRegistered};
+use look_at::VrmLookAt;
+use morph_targets::{MorphTargetNames, VrmMorphTargets};
 
 #[cfg(feature = "animations")]
 pub mod animations;
 pub mod auto_scene;
+pub mod expressions;
 pub mod extensions;
+pub mod face_tracking;
+#[cfg(feature = "first_person")]
 pub mod first_person;
 pub mod loader;
+pub mod look_at;
+pub mod morph_targets;
+#[cfg(feature = "spring_bones")]
 pub mod spring_bones;
 
+#[cfg(feature = "mtoon")]
 pub mod mtoon {
     pub use bevy_shader_mtoon::*;
 }
 
 pub use serde_vrm::vrm0::BoneName;
 
+/// Loads VRM avatars.
+///
+/// Works without a renderer, such as with `MinimalPlugins` and `AssetPlugin` on a server.
+/// In that case MToon materials are still loaded, but not rendered.
+/// Spawning avatars also needs `TransformPlugin`, `HierarchyPlugin` and `ScenePlugin`.
+///
+/// Adds a default [GltfKunPlugin], unless the app has already added its own.
+/// To import other glTF extensions alongside the VRM ones, add a
+/// [VrmLoaderPlugin](loader::VrmLoaderPlugin) before this plugin.
 pub struct VrmPlugin;
 pub struct VrmPlugins;
 
 impl PluginGroup for VrmPlugins {
     fn build(self) -> PluginGroupBuilder {
-        PluginGroupBuilder::start::<Self>()
-            .add(VrmPlugin)
-            .add(SpringBonePlugin)
+        let group = PluginGroupBuilder::start::<Self>().add(VrmPlugin);
+
+        #[cfg(feature = "spring_bones")]
+        let group = group.add(spring_bones::SpringBonePlugin);
+
+        #[cfg(feature = "animations")]
+        let group = group.add(animations::ik::HumanoidIkPlugin);
+
+        group
     }
 }
 
 impl Plugin for VrmPlugin {
     fn build(&self, app: &mut App) {
-        // TODO: Dont use default GltfKunPlugin
-        app.add_plugins((GltfKunPlugin::default(), MtoonPlugin))
-            .add_event::<SetupFirstPerson>()
-            .init_asset::<Vrm>()
-            .init_asset_loader::<VrmLoader>()
+        let headless = app.get_sub_app(RenderApp).is_none();
+
+        if headless {
+            // Assets normally registered by the render plugins, which the loader needs.
+            init_missing_asset::<Image>(app);
+            init_missing_asset::<Mesh>(app);
+            init_missing_asset::<SkinnedMeshInverseBindposes>(app);
+            init_missing_asset::<StandardMaterial>(app);
+
+            // Components of imported scenes, normally registered by the render plugins.
+            app.register_type::<Visibility>()
+                .register_type::<InheritedVisibility>()
+                .register_type::<ViewVisibility>()
+                .register_type::<Handle<Mesh>>()
+                .register_type::<Handle<StandardMaterial>>()
+                .register_type::<SkinnedMesh>()
+                .register_type::<MeshMorphWeights>()
+                .register_type::<MorphWeights>()
+                .register_type::<Aabb>()
+                .register_type::<RenderLayers>();
+
+            #[cfg(feature = "animations")]
+            {
+                init_missing_asset::<AnimationClip>(app);
+                init_missing_asset::<AnimationGraph>(app);
+                app.register_type::<AnimationPlayer>()
+                    .register_type::<bevy::animation::AnimationTarget>();
+            }
+        }
+
+        #[cfg(feature = "mtoon")]
+        if headless {
+            init_missing_asset::<mtoon::MtoonMaterial>(app);
+            app.register_type::<Handle<mtoon::MtoonMaterial>>()
+                .register_type::<mtoon::OutlineSync>();
+        } else {
+            app.add_plugins(mtoon::MtoonPlugin);
+        }
+
+        if !app.is_plugin_added::<GltfKunPlugin>() {
+            app.add_plugins(GltfKunPlugin::default());
+        }
+
+        if !app.world().contains_resource::<VrmLoaderRegistered>() {
+            app.init_asset_loader::<VrmLoader>();
+        }
+
+        app.init_asset::<Vrm>()
             .register_type::<BoneName>()
-            .register_type::<FirstPersonFlag>()
+            .register_type::<VrmExpressions>()
+            .register_type::<VrmLookAt>()
+            .register_type::<ExpressionName>()
+            .register_type::<MorphTargetNames>()
+            .register_type::<VrmMorphTargets>()
             .add_systems(
                 Update,
-                (auto_scene::set_vrm_scene, first_person::handle_setup_events).chain(),
+                (auto_scene::set_vrm_scene, loader::release_vrm_bytes),
             );
+
+        #[cfg(feature = "first_person")]
+        app.add_event::<first_person::SetupFirstPerson>()
+            .register_type::<first_person::FirstPersonFlag>()
+            .add_systems(
+                Update,
+                first_person::handle_setup_events.after(auto_scene::set_vrm_scene),
+            );
+
+        let avatar_systems = (look_at::update_look_at, expressions::apply_expressions)
+            .chain()
+            .before(inherit_weights)
+            .before(TransformSystem::TransformPropagate);
+
+        #[cfg(feature = "animations")]
+        let avatar_systems = (
+            animations::params::restore_twist,
+            animations::expressions::apply_expression_targets,
+            animations::replication::apply_pose_replicas,
+            animations::layers::apply_humanoid_layers,
+            animations::humanoid::apply_humanoid_pose,
+            animations::root_motion::apply_root_motion,
+            animations::idle::apply_idle_motion,
+            animations::hand::apply_hand_poses,
+            animations::params::distribute_twist,
+            avatar_systems,
+        )
+            .chain()
+            .after(bevy::animation::animate_targets);
+
+        #[cfg(feature = "animations")]
+        app.register_type::<animations::expressions::ExpressionTarget>()
+            .register_type::<animations::hand::HandPoses>()
+            .register_type::<animations::humanoid::HumanoidRig>()
+            .register_type::<animations::idle::IdleMotion>()
+            .register_type::<animations::humanoid::HumanoidPose>()
+            .register_type::<animations::layers::HumanoidLayers>()
+            .register_type::<animations::params::HumanoidParams>()
+            .register_type::<animations::root_motion::RootMotion>()
+            .init_asset::<animations::bvh::BvhAnimation>()
+            .init_asset_loader::<animations::bvh::BvhLoader>();
+
+        #[cfg(all(feature = "animations", feature = "vrm1"))]
+        app.register_type::<animations::recorder::VrmaRecorder>()
+            .init_asset::<animations::vrma::Vrma>()
+            .init_asset_loader::<animations::vrma::VrmaLoader>()
+            .add_systems(
+                PostUpdate,
+                animations::recorder::record_vrma.after(TransformSystem::TransformPropagate),
+            );
+
+        app.add_systems(PostUpdate, avatar_systems);
+    }
+}
+
+/// Registers an asset type, unless it already is.
+/// Registering an asset twice would replace its storage.
+fn init_missing_asset<A: Asset>(app: &mut App) {
+    if !app.world().contains_resource::<Assets<A>>() {
+        app.init_asset::<A>();
     }
 }
 
@@ -58,3 +199,217 @@ pub struct VrmBundle {
     pub scene_bundle: SceneBundle,
     pub vrm: Handle<Vrm>,
 }
+
+#[cfg(all(test, feature = "animations", feature = "vrm0"))]
+mod tests {
+    use bevy::{
+        asset::{io::embedded::EmbeddedAssetRegistry, AsyncReadExt},
+        scene::ScenePlugin,
+    };
+    use bevy_gltf_kun::import::{extensions::BevyExtensionImport, gltf::document::ImportContext};
+    use gltf_kun::{
+        extensions::ExtensionImport,
+        graph::{
+            gltf::{GltfDocument, Node},
+            Graph,
+        },
+        io::format::gltf::GltfFormat,
+    };
+
+    use super::*;
+    use crate::{
+        animations::humanoid::{validate_humanoid, HumanoidRig},
+        loader::{VrmGltfLoaderPlugin, VrmLoaderPlugin},
+    };
+
+    fn headless_app() -> App {
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins,
+            AssetPlugin {
+                file_path: "../../assets".to_string(),
+                ..default()
+            },
+            TransformPlugin,
+            HierarchyPlugin,
+            ScenePlugin,
+        ));
+        app
+    }
+
+    fn load_catbot(app: &App) -> Handle<Vrm> {
+        app.world()
+            .resource::<AssetServer>()
+            .load::<Vrm>("catbot.vrm")
+    }
+
+    /// Spawns a VRM and waits for its humanoid rig to be built.
+    fn spawn_vrm(app: &mut App, vrm: Handle<Vrm>) -> Entity {
+        app.world_mut().spawn(VrmBundle { vrm, ..default() });
+
+        let mut rigs = app.world_mut().query::<(Entity, &HumanoidRig)>();
+
+        for _ in 0..1000 {
+            app.update();
+
+            if rigs.iter(app.world()).next().is_some() {
+                break;
+            }
+
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+
+        let (root, _) = rigs.single(app.world());
+        root
+    }
+
+    #[test]
+    fn load_headless() {
+        let mut app = headless_app();
+        app.add_plugins(VrmPlugins);
+
+        let vrm = load_catbot(&app);
+        let root = spawn_vrm(&mut app, vrm);
+        let rig = app.world().get::<HumanoidRig>(root).unwrap();
+        assert!(rig.bones.contains_key(&BoneName::Head));
+
+        let errors = validate_humanoid(app.world_mut(), root);
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let expressions = app
+            .world_mut()
+            .query::<&VrmExpressions>()
+            .single(app.world());
+        assert!(!expressions.0.is_empty());
+    }
+
+    #[derive(Component, Reflect)]
+    #[reflect(Component)]
+    struct Imported;
+
+    struct MarkerExtension;
+
+    impl ExtensionImport<GltfDocument, GltfFormat> for MarkerExtension {
+        fn import(
+            _graph: &mut Graph,
+            _format: &mut GltfFormat,
+            _doc: &GltfDocument,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            Ok(())
+        }
+    }
+
+    impl BevyExtensionImport<GltfDocument> for MarkerExtension {
+        fn import_material(
+            _context: &mut ImportContext,
+            _standard_material: &mut StandardMaterial,
+            _material: gltf_kun::graph::gltf::Material,
+        ) {
+        }
+
+        fn import_node(_context: &mut ImportContext, entity: &mut EntityWorldMut, _node: Node) {
+            entity.insert(Imported);
+        }
+
+        fn import_primitive(
+            _context: &mut ImportContext,
+            _entity: &mut EntityWorldMut,
+            _primitive: gltf_kun::graph::gltf::Primitive,
+        ) {
+        }
+
+        fn import_root(_context: &mut ImportContext) {}
+
+        fn import_scene(
+            _context: &mut ImportContext,
+            _scene: gltf_kun::graph::gltf::Scene,
+            _world: &mut World,
+        ) {
+        }
+    }
+
+    #[test]
+    fn compose_extensions() {
+        let mut app = headless_app();
+        app.register_type::<Imported>().add_plugins((
+            bevy_gltf_kun::GltfKunPlugin::default(),
+            VrmLoaderPlugin::<MarkerExtension>::default(),
+            VrmPlugins,
+        ));
+
+        let vrm = load_catbot(&app);
+        let root = spawn_vrm(&mut app, vrm);
+        let rig = app.world().get::<HumanoidRig>(root).unwrap();
+        assert!(rig.bones.contains_key(&BoneName::Head));
+
+        let imported = app
+            .world_mut()
+            .query_filtered::<(), With<Imported>>()
+            .iter(app.world())
+            .count();
+        assert!(imported > 0);
+    }
+
+    #[test]
+    fn load_from_bytes() {
+        let mut app = headless_app();
+        app.add_plugins(VrmPlugins);
+
+        let bytes = std::fs::read("../../assets/catbot.vrm").unwrap();
+        let vrm = bevy::tasks::block_on(Vrm::from_bytes(
+            app.world().resource::<AssetServer>(),
+            app.world().resource::<EmbeddedAssetRegistry>(),
+            "catbot",
+            bytes,
+        ))
+        .unwrap();
+
+        let root = spawn_vrm(&mut app, vrm.clone());
+        let rig = app.world().get::<HumanoidRig>(root).unwrap();
+        assert!(rig.bones.contains_key(&BoneName::Head));
+
+        // The bytes are released once loaded.
+        let asset_server = app.world().resource::<AssetServer>();
+        let path = asset_server.get_path(vrm.id()).unwrap().into_owned();
+        let reader = asset_server.get_source(path.source()).unwrap().reader();
+        let released = bevy::tasks::block_on(async {
+            let mut bytes = Vec::new();
+            let mut file = reader.read(path.path()).await.unwrap();
+            file.read_to_end(&mut bytes).await.unwrap();
+            bytes
+        });
+        assert!(released.is_empty());
+
+        // Loading the same name again loads a new asset.
+        let bytes = std::fs::read("../../assets/catbot.vrm").unwrap();
+        let again = bevy::tasks::block_on(Vrm::from_bytes(
+            app.world().resource::<AssetServer>(),
+            app.world().resource::<EmbeddedAssetRegistry>(),
+            "catbot",
+            bytes,
+        ))
+        .unwrap();
+        assert_ne!(again.id(), vrm.id());
+    }
+
+    #[test]
+    fn load_glb() {
+        let mut app = headless_app();
+        app.add_plugins((VrmPlugins, VrmGltfLoaderPlugin));
+
+        let bytes = std::fs::read("../../assets/catbot.vrm").unwrap();
+        let path = std::path::Path::new("catbot.glb");
+        app.world()
+            .resource::<EmbeddedAssetRegistry>()
+            .insert_asset(path.to_path_buf(), path, bytes);
+
+        let vrm = app
+            .world()
+            .resource::<AssetServer>()
+            .load::<Vrm>("embedded://catbot.glb");
+
+        let root = spawn_vrm(&mut app, vrm);
+        let rig = app.world().get::<HumanoidRig>(root).unwrap();
+        assert!(rig.bones.contains_key(&BoneName::Head));
+    }
+}
@@ -1,32 +1,172 @@
-use std::fmt::Debug;
+use std::{
+    fmt::Debug,
+    marker::PhantomData,
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
 use bevy::{
-    asset::{io::Reader, AssetLoader, LoadContext},
+    asset::{
+        io::{embedded::EmbeddedAssetRegistry, AssetSourceId, Reader, VecReader},
+        AssetLoadError, AssetLoader, AssetPath, AsyncReadExt, LoadContext,
+        UntypedAssetLoadFailedEvent,
+    },
     prelude::*,
+    utils::ConditionalSendFuture,
+};
+use bevy_gltf_kun::{
+    extensions::DefaultExtensions,
+    import::{
+        extensions::BevyExtensionImport,
+        gltf::{
+            loader::{GltfError, GltfLoader},
+            GltfKun,
+        },
+    },
 };
-use bevy_gltf_kun::import::gltf::{
-    loader::{GltfError, GltfLoader},
-    GltfKun,
+use gltf_kun::{
+    extensions::ExtensionImport, graph::gltf::GltfDocument, io::format::gltf::GltfFormat,
 };
+use serde::Deserialize;
 use thiserror::Error;
 
-use crate::extensions::VrmExtensions;
+use crate::extensions::VrmExtensionsWith;
 
 #[derive(Asset, TypePath, Debug)]
 pub struct Vrm {
     pub gltf: GltfKun,
 }
 
-#[derive(Default)]
-pub struct VrmLoader(pub GltfLoader<VrmExtensions>);
+/// Directory of the `embedded` asset source that [Vrm::from_bytes] passes bytes through.
+const BYTES_DIR: &str = "bevy_vrm/bytes";
+
+impl Vrm {
+    /// Loads a VRM from bytes in memory, such as a download, in VRM, GLB or glTF form.
+    ///
+    /// Bevy assets can only be loaded by the asset server, so the bytes are passed to it
+    /// through the `embedded` asset source.
+    /// Each call uses a new path, so loading the same `name` twice gives two separate assets,
+    /// and `name` only shows up in the asset path.
+    /// [VrmPlugin](crate::VrmPlugin) releases the bytes once the VRM has loaded or failed to load.
+    ///
+    /// The returned future resolves once the VRM has loaded.
+    pub fn from_bytes(
+        asset_server: &AssetServer,
+        registry: &EmbeddedAssetRegistry,
+        name: &str,
+        bytes: impl Into<Vec<u8>>,
+    ) -> impl ConditionalSendFuture<Output = Result<Handle<Vrm>, AssetLoadError>> + 'static {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+        let path = PathBuf::from(format!("{}/{}/{}.vrm", BYTES_DIR, id, name));
+        registry.insert_asset(path.clone(), &path, bytes.into());
+
+        let asset_server = asset_server.clone();
+        let asset_path = AssetPath::from(path).with_source("embedded");
+
+        async move {
+            let handle = asset_server.load_untyped_async(asset_path).await?;
+            Ok(handle.typed::<Vrm>())
+        }
+    }
+}
+
+/// Releases the bytes of VRMs loaded by [Vrm::from_bytes] once they have loaded.
+/// The [EmbeddedAssetRegistry] cannot remove assets, so the bytes are replaced with nothing.
+pub(crate) fn release_vrm_bytes(
+    mut loaded: EventReader<AssetEvent<Vrm>>,
+    mut failed: EventReader<UntypedAssetLoadFailedEvent>,
+    asset_server: Res<AssetServer>,
+    registry: Res<EmbeddedAssetRegistry>,
+) {
+    let loaded = loaded
+        .read()
+        .filter_map(|event| match event {
+            AssetEvent::LoadedWithDependencies { id } => asset_server.get_path(*id),
+            _ => None,
+        })
+        .map(|path| path.into_owned())
+        .collect::<Vec<_>>();
+    let failed = failed.read().map(|event| event.path.clone());
+
+    let embedded = AssetSourceId::from("embedded");
+
+    for path in loaded.into_iter().chain(failed) {
+        if path.source() == &embedded && path.path().starts_with(BYTES_DIR) {
+            registry.insert_asset(path.path().to_path_buf(), path.path(), Vec::new());
+        }
+    }
+}
+
+/// glTF extensions that mark a file as a VRM.
+/// VRM 1.0 (`VRMC_vrm`) is not listed until it can be imported.
+const VRM_EXTENSIONS: [&str; 1] = ["VRM"];
+
+#[derive(Deserialize)]
+struct ExtensionsUsed {
+    #[serde(default, rename = "extensionsUsed")]
+    extensions_used: Vec<String>,
+}
+
+/// Checks whether GLB or glTF bytes use a VRM extension.
+pub fn is_vrm(bytes: &[u8]) -> bool {
+    let json = if bytes.starts_with(b"glTF") {
+        // 12 byte header, followed by the JSON chunk's length and type.
+        let Some(header) = bytes.get(12..20) else {
+            return false;
+        };
+
+        if &header[4..8] != b"JSON" {
+            return false;
+        }
+
+        let length = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+
+        match bytes.get(20..20 + length) {
+            Some(json) => json,
+            None => return false,
+        }
+    } else {
+        bytes
+    };
+
+    serde_json::from_slice::<ExtensionsUsed>(json).is_ok_and(|used| {
+        used.extensions_used
+            .iter()
+            .any(|name| VRM_EXTENSIONS.contains(&name.as_str()))
+    })
+}
+
+/// Loads [Vrm] assets, importing other glTF extensions `E` together with the VRM extensions.
+pub struct VrmLoader<E: BevyExtensionImport<GltfDocument> = DefaultExtensions>(
+    pub GltfLoader<VrmExtensionsWith<E>>,
+);
+
+impl<E: BevyExtensionImport<GltfDocument>> Default for VrmLoader<E> {
+    fn default() -> Self {
+        Self(GltfLoader::default())
+    }
+}
 
 #[derive(Debug, Error)]
 pub enum VrmError {
     #[error(transparent)]
     Gltf(#[from] GltfError),
+    #[error("Failed to read file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("File does not use a VRM extension")]
+    NotVrm,
 }
 
-impl AssetLoader for VrmLoader {
+impl<E> AssetLoader for VrmLoader<E>
+where
+    E: BevyExtensionImport<GltfDocument>
+        + ExtensionImport<GltfDocument, GltfFormat>
+        + Send
+        + Sync
+        + 'static,
+{
     type Asset = Vrm;
     type Settings = ();
     type Error = VrmError;
@@ -47,3 +187,137 @@ impl AssetLoader for VrmLoader {
         &["vrm"]
     }
 }
+
+/// Loads [Vrm] assets from `.glb` and `.gltf` files that use a VRM extension.
+///
+/// Not registered by default, see [VrmGltfLoaderPlugin].
+pub struct VrmGltfLoader<E: BevyExtensionImport<GltfDocument> = DefaultExtensions>(
+    pub VrmLoader<E>,
+);
+
+impl<E: BevyExtensionImport<GltfDocument>> Default for VrmGltfLoader<E> {
+    fn default() -> Self {
+        Self(VrmLoader::default())
+    }
+}
+
+impl<E> AssetLoader for VrmGltfLoader<E>
+where
+    E: BevyExtensionImport<GltfDocument>
+        + ExtensionImport<GltfDocument, GltfFormat>
+        + Send
+        + Sync
+        + 'static,
+{
+    type Asset = Vrm;
+    type Settings = ();
+    type Error = VrmError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        settings: &'a Self::Settings,
+        load_context: &'a mut LoadContext,
+    ) -> impl bevy::utils::ConditionalSendFuture<Output = Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+
+            if !is_vrm(&bytes) {
+                return Err(VrmError::NotVrm);
+            }
+
+            let mut reader = VecReader::new(bytes);
+            self.0.load(&mut reader, settings, load_context).await
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["glb", "gltf"]
+    }
+}
+
+/// Registers a [VrmGltfLoader], to load VRM avatars from `.glb` and `.gltf` files.
+///
+/// Loads of `Handle<Vrm>` from these extensions then use it.
+/// Untyped loads use whichever `.glb` loader was registered last, and fail
+/// with [VrmError::NotVrm] for files without a VRM extension if that is this one.
+pub struct VrmGltfLoaderPlugin;
+
+impl Plugin for VrmGltfLoaderPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset_loader::<VrmGltfLoader>();
+    }
+}
+
+/// Registers a [VrmLoader] that also imports the glTF extensions `E`,
+/// such as the extensions of the app's own glTF loader.
+///
+/// Must be added before [VrmPlugin](crate::VrmPlugin), which otherwise registers
+/// a loader for the VRM extensions alone.
+pub struct VrmLoaderPlugin<E>(PhantomData<E>);
+
+impl<E> Default for VrmLoaderPlugin<E> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+/// Marks that a [VrmLoader] has been registered.
+#[derive(Resource)]
+pub(crate) struct VrmLoaderRegistered;
+
+impl<E> Plugin for VrmLoaderPlugin<E>
+where
+    E: BevyExtensionImport<GltfDocument>
+        + ExtensionImport<GltfDocument, GltfFormat>
+        + Send
+        + Sync
+        + 'static,
+{
+    fn build(&self, app: &mut App) {
+        if app.world().contains_resource::<VrmLoaderRegistered>() {
+            panic!("A VRM loader is already registered, add VrmLoaderPlugin before VrmPlugin");
+        }
+
+        app.insert_resource(VrmLoaderRegistered)
+            .init_asset_loader::<VrmLoader<E>>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glb(json: &[u8]) -> Vec<u8> {
+        let length = 20 + json.len() as u32;
+
+        let mut bytes = b"glTF".to_vec();
+        bytes.extend(2u32.to_le_bytes());
+        bytes.extend(length.to_le_bytes());
+        bytes.extend((json.len() as u32).to_le_bytes());
+        bytes.extend(b"JSON");
+        bytes.extend(json);
+        bytes
+    }
+
+    #[test]
+    fn sniff_vrm() {
+        let catbot = std::fs::read("../../assets/catbot.vrm").unwrap();
+        assert!(is_vrm(&catbot));
+
+        let vrm0 = br#"{"asset":{"version":"2.0"},"extensionsUsed":["VRM"]}"#;
+        assert!(is_vrm(vrm0));
+        assert!(is_vrm(&glb(vrm0)));
+
+        // VRM 1.0 avatars cannot be imported yet.
+        let vrm1 = br#"{"asset":{"version":"2.0"},"extensionsUsed":["VRMC_vrm"]}"#;
+        assert!(!is_vrm(vrm1));
+
+        let gltf = br#"{"asset":{"version":"2.0"}}"#;
+        assert!(!is_vrm(gltf));
+        assert!(!is_vrm(&glb(gltf)));
+
+        assert!(!is_vrm(b"glTF"));
+    }
+}
@@ -0,0 +1,345 @@
+use bevy::{
+    ecs::{entity::MapEntities, reflect::ReflectMapEntities},
+    prelude::*,
+};
+use serde_vrm::vrm0::{Curve, LookAtCurve, PresetName};
+
+use crate::expressions::VrmExpressions;
+
+/// Eye gaze for a VRM avatar.
+/// Inserted on the root entity of the VRM scene.
+///
+/// Set [VrmLookAt::target] to make the avatar look at something.
+/// Depending on [VrmLookAt::kind], this either rotates the eye bones or drives
+/// the `LookUp`, `LookDown`, `LookLeft` and `LookRight` expressions.
+#[derive(Component, Reflect)]
+#[reflect(Component, MapEntities)]
+pub struct VrmLookAt {
+    /// What to look at. The avatar looks straight ahead if `None`.
+    pub target: Option<LookAtTarget>,
+    pub kind: LookAtType,
+    /// Bone the gaze is calculated from, usually the head.
+    pub head: Entity,
+    /// Offset from the head bone to the point between the eyes, in the head bone's space.
+    pub offset: Vec3,
+    pub horizontal_inner: LookAtRangeMap,
+    pub horizontal_outer: LookAtRangeMap,
+    pub vertical_down: LookAtRangeMap,
+    pub vertical_up: LookAtRangeMap,
+    pub left_eye: Option<LookAtEye>,
+    pub right_eye: Option<LookAtEye>,
+    /// Rest rotation of the head bone, relative to the avatar root.
+    pub head_rest: Quat,
+    yaw: f32,
+    pitch: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Reflect)]
+pub enum LookAtTarget {
+    Entity(Entity),
+    /// A point in world space.
+    Point(Vec3),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect)]
+pub enum LookAtType {
+    #[default]
+    Bone,
+    BlendShape,
+}
+
+impl LookAtType {
+    pub fn from_type_name(name: &str) -> Option<Self> {
+        match name {
+            "Bone" => Some(Self::Bone),
+            "BlendShape" => Some(Self::BlendShape),
+            _ => None,
+        }
+    }
+}
+
+/// Maps a gaze angle to an eye rotation or expression weight,
+/// following UniVRM's `CurveMapper`.
+#[derive(Clone, Debug, PartialEq, Reflect)]
+pub struct LookAtRangeMap {
+    /// Maximum input angle, in degrees.
+    pub input_max_value: f32,
+    /// Output when the input is at [LookAtRangeMap::input_max_value].
+    /// Degrees for bones, a weight for blend shapes.
+    pub output_scale: f32,
+    /// Curve from the normalized input to the normalized output.
+    pub curve: Curve,
+}
+
+#[derive(Clone, Copy, Debug, Reflect)]
+pub struct LookAtEye {
+    pub entity: Entity,
+    /// Rest rotation of the eye, relative to the avatar root.
+    pub rest: Quat,
+    /// Rest rotation of the eye's parent, relative to the avatar root.
+    pub parent_rest: Quat,
+}
+
+impl LookAtRangeMap {
+    pub fn new(input_max_value: f32, output_scale: f32) -> Self {
+        Self {
+            input_max_value,
+            output_scale,
+            curve: Curve::linear(),
+        }
+    }
+
+    pub fn from_curve(curve: &LookAtCurve, default: Self) -> Self {
+        Self {
+            input_max_value: curve.x_range.unwrap_or(default.input_max_value),
+            output_scale: curve.y_range.unwrap_or(default.output_scale),
+            curve: curve.curve.clone().unwrap_or(default.curve),
+        }
+    }
+
+    pub fn map(&self, angle: f32) -> f32 {
+        if self.input_max_value <= 0.0 {
+            return 0.0;
+        }
+
+        let t = angle.clamp(0.0, self.input_max_value) / self.input_max_value;
+        self.curve.evaluate(t) * self.output_scale
+    }
+}
+
+impl LookAtType {
+    /// Default range map used when the VRM does not specify one.
+    pub fn default_range_map(&self) -> LookAtRangeMap {
+        match self {
+            Self::Bone => LookAtRangeMap::new(90.0, 10.0),
+            Self::BlendShape => LookAtRangeMap::new(90.0, 1.0),
+        }
+    }
+}
+
+impl VrmLookAt {
+    pub fn new(kind: LookAtType, head: Entity, head_rest: Quat) -> Self {
+        let range = kind.default_range_map();
+
+        Self {
+            target: None,
+            kind,
+            head,
+            offset: Vec3::ZERO,
+            horizontal_inner: range.clone(),
+            horizontal_outer: range.clone(),
+            vertical_down: range.clone(),
+            vertical_up: range,
+            left_eye: None,
+            right_eye: None,
+            head_rest,
+            yaw: 0.0,
+            pitch: 0.0,
+        }
+    }
+
+    /// Horizontal gaze angle in degrees, positive towards the avatar's right.
+    pub fn yaw(&self) -> f32 {
+        self.yaw
+    }
+
+    /// Vertical gaze angle in degrees, positive upwards.
+    pub fn pitch(&self) -> f32 {
+        self.pitch
+    }
+
+    /// Eye rotations for the current gaze, as (yaw, pitch) in degrees for the left and right eye.
+    pub fn eye_angles(&self) -> ((f32, f32), (f32, f32)) {
+        let (left_yaw, right_yaw) = if self.yaw < 0.0 {
+            (
+                -self.horizontal_outer.map(-self.yaw),
+                -self.horizontal_inner.map(-self.yaw),
+            )
+        } else {
+            (
+                self.horizontal_inner.map(self.yaw),
+                self.horizontal_outer.map(self.yaw),
+            )
+        };
+
+        let pitch = if self.pitch < 0.0 {
+            -self.vertical_down.map(-self.pitch)
+        } else {
+            self.vertical_up.map(self.pitch)
+        };
+
+        ((left_yaw, pitch), (right_yaw, pitch))
+    }
+}
+
+impl MapEntities for VrmLookAt {
+    fn map_entities<M: EntityMapper>(&mut self, entity_mapper: &mut M) {
+        self.head = entity_mapper.map_entity(self.head);
+
+        for eye in [&mut self.left_eye, &mut self.right_eye]
+            .into_iter()
+            .flatten()
+        {
+            eye.entity = entity_mapper.map_entity(eye.entity);
+        }
+    }
+}
+
+/// Returns the (yaw, pitch) in degrees of a direction relative to the avatar's rest orientation.
+/// VRM 0.0 avatars face -Z.
+pub fn yaw_pitch(dir: Vec3) -> (f32, f32) {
+    let yaw = dir.x.atan2(-dir.z);
+    let pitch = dir.y.atan2(Vec2::new(dir.x, dir.z).length());
+    (yaw.to_degrees(), pitch.to_degrees())
+}
+
+pub(crate) fn update_look_at(
+    globals: Query<&GlobalTransform>,
+    mut look_ats: Query<(&mut VrmLookAt, Option<&mut VrmExpressions>)>,
+    mut transforms: Query<&mut Transform>,
+) {
+    for (mut look_at, expressions) in look_ats.iter_mut() {
+        let Ok(head) = globals.get(look_at.head) else {
+            continue;
+        };
+
+        let (_, head_rotation, _) = head.to_scale_rotation_translation();
+        let origin = head.transform_point(look_at.offset);
+
+        let target = match look_at.target {
+            Some(LookAtTarget::Entity(entity)) => globals.get(entity).ok().map(|t| t.translation()),
+            Some(LookAtTarget::Point(point)) => Some(point),
+            None => None,
+        };
+
+        let (yaw, pitch) = match target {
+            Some(target) => {
+                let frame = head_rotation * look_at.head_rest.inverse();
+                let dir = frame.inverse() * (target - origin);
+
+                if dir.length_squared() > f32::EPSILON {
+                    yaw_pitch(dir)
+                } else {
+                    (0.0, 0.0)
+                }
+            }
+            None => (0.0, 0.0),
+        };
+
+        look_at.yaw = yaw;
+        look_at.pitch = pitch;
+
+        match look_at.kind {
+            LookAtType::Bone => {
+                let (left, right) = look_at.eye_angles();
+
+                for (eye, (yaw, pitch)) in [(look_at.left_eye, left), (look_at.right_eye, right)] {
+                    let Some(eye) = eye else {
+                        continue;
+                    };
+
+                    let Ok(mut transform) = transforms.get_mut(eye.entity) else {
+                        continue;
+                    };
+
+                    let rotation = Quat::from_rotation_y(-yaw.to_radians())
+                        * Quat::from_rotation_x(pitch.to_radians());
+
+                    transform.rotation = eye.parent_rest.inverse() * rotation * eye.rest;
+                }
+            }
+            LookAtType::BlendShape => {
+                let Some(mut expressions) = expressions else {
+                    continue;
+                };
+
+                let (left, right) = if yaw < 0.0 {
+                    (look_at.horizontal_outer.map(-yaw), 0.0)
+                } else {
+                    (0.0, look_at.horizontal_outer.map(yaw))
+                };
+
+                let (down, up) = if pitch < 0.0 {
+                    (look_at.vertical_down.map(-pitch), 0.0)
+                } else {
+                    (0.0, look_at.vertical_up.map(pitch))
+                };
+
+                expressions.set_weight(PresetName::LookLeft, left);
+                expressions.set_weight(PresetName::LookRight, right);
+                expressions.set_weight(PresetName::LookDown, down);
+                expressions.set_weight(PresetName::LookUp, up);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_vrm::vrm0::CurveKey;
+
+    use super::*;
+
+    #[test]
+    fn yaw_pitch_forward() {
+        let (yaw, pitch) = yaw_pitch(Vec3::NEG_Z);
+        assert!(yaw.abs() < 1e-4);
+        assert!(pitch.abs() < 1e-4);
+    }
+
+    #[test]
+    fn yaw_pitch_right_and_up() {
+        let (yaw, pitch) = yaw_pitch(Vec3::new(1.0, 0.0, -1.0));
+        assert!((yaw - 45.0).abs() < 1e-4);
+        assert!(pitch.abs() < 1e-4);
+
+        let (yaw, pitch) = yaw_pitch(Vec3::new(0.0, 1.0, -1.0));
+        assert!(yaw.abs() < 1e-4);
+        assert!((pitch - 45.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn range_map_clamps() {
+        let range = LookAtRangeMap::new(90.0, 10.0);
+        assert_eq!(range.map(45.0), 5.0);
+        assert_eq!(range.map(180.0), 10.0);
+        assert_eq!(range.map(-10.0), 0.0);
+    }
+
+    #[test]
+    fn range_map_evaluates_curve() {
+        // AnimationCurve.EaseInOut(0, 0, 1, 1), with UniVRM's default bone range.
+        let curve = LookAtCurve {
+            curve: Some(Curve {
+                keys: vec![
+                    CurveKey::new(0.0, 0.0, 0.0, 0.0),
+                    CurveKey::new(1.0, 1.0, 0.0, 0.0),
+                ],
+            }),
+            x_range: None,
+            y_range: None,
+        };
+
+        let range = LookAtRangeMap::from_curve(&curve, LookAtType::Bone.default_range_map());
+        assert!((range.map(22.5) - 1.5625).abs() < 1e-4);
+        assert!((range.map(45.0) - 5.0).abs() < 1e-4);
+        assert!((range.map(67.5) - 8.4375).abs() < 1e-4);
+    }
+
+    #[test]
+    fn eye_angles_use_inner_and_outer() {
+        let mut look_at = VrmLookAt::new(LookAtType::Bone, Entity::PLACEHOLDER, Quat::IDENTITY);
+        look_at.horizontal_inner = LookAtRangeMap::new(90.0, 5.0);
+        look_at.horizontal_outer = LookAtRangeMap::new(90.0, 10.0);
+
+        look_at.yaw = 90.0;
+        let ((left_yaw, _), (right_yaw, _)) = look_at.eye_angles();
+        assert_eq!(left_yaw, 5.0);
+        assert_eq!(right_yaw, 10.0);
+
+        look_at.yaw = -90.0;
+        let ((left_yaw, _), (right_yaw, _)) = look_at.eye_angles();
+        assert_eq!(left_yaw, -10.0);
+        assert_eq!(right_yaw, -5.0);
+    }
+}
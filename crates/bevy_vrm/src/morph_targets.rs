@@ -0,0 +1,178 @@
+#[cfg(feature = "vrm0")]
+use bevy::utils::HashMap;
+use bevy::{
+    ecs::{entity::MapEntities, reflect::ReflectMapEntities},
+    prelude::*,
+};
+use gltf_kun::graph::{gltf::Primitive, Graph, GraphNodeWeight};
+use serde::Deserialize;
+
+/// Names of the morph targets of a mesh primitive, from the glTF `extras.targetNames`.
+/// Inserted on entities with [MeshMorphWeights](bevy::render::mesh::morph::MeshMorphWeights).
+#[derive(Component, Default, Reflect, Debug)]
+#[reflect(Component)]
+pub struct MorphTargetNames(pub Vec<String>);
+
+/// The morph targets of every mesh in a VRM avatar.
+/// Inserted on the root entity of the VRM scene.
+#[derive(Component, Default, Reflect)]
+#[reflect(Component, MapEntities)]
+pub struct VrmMorphTargets(pub Vec<MorphTargetMesh>);
+
+#[derive(Reflect, Debug)]
+pub struct MorphTargetMesh {
+    /// Entity with the [MorphWeights] of the mesh.
+    pub entity: Entity,
+    /// Entities with the [MeshMorphWeights](bevy::render::mesh::morph::MeshMorphWeights)
+    /// of each primitive.
+    pub primitives: Vec<Entity>,
+    /// Morph target names, by index.
+    pub names: Vec<String>,
+}
+
+/// A single morph target of a mesh.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MorphTarget {
+    /// Entity with the [MorphWeights] of the mesh.
+    pub entity: Entity,
+    pub index: usize,
+}
+
+impl MorphTargetMesh {
+    pub fn index(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n == name)
+    }
+}
+
+impl VrmMorphTargets {
+    /// Returns the mesh with the [MorphWeights] on the given entity.
+    pub fn mesh(&self, entity: Entity) -> Option<&MorphTargetMesh> {
+        self.0.iter().find(|m| m.entity == entity)
+    }
+
+    /// Finds every morph target with the given name, across all meshes.
+    pub fn find<'a>(&'a self, name: &'a str) -> impl Iterator<Item = MorphTarget> + 'a {
+        self.0.iter().filter_map(move |mesh| {
+            mesh.index(name).map(|index| MorphTarget {
+                entity: mesh.entity,
+                index,
+            })
+        })
+    }
+
+    /// Returns the name of a morph target.
+    pub fn name(&self, target: MorphTarget) -> Option<&str> {
+        self.mesh(target.entity)
+            .and_then(|mesh| mesh.names.get(target.index))
+            .map(String::as_str)
+    }
+}
+
+impl MapEntities for VrmMorphTargets {
+    fn map_entities<M: EntityMapper>(&mut self, entity_mapper: &mut M) {
+        for mesh in &mut self.0 {
+            mesh.entity = entity_mapper.map_entity(mesh.entity);
+
+            for primitive in &mut mesh.primitives {
+                *primitive = entity_mapper.map_entity(*primitive);
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TargetNamesExtras {
+    target_names: Vec<String>,
+}
+
+/// Reads the morph target names of a primitive.
+/// Exporters write `targetNames` to either the primitive or mesh extras.
+pub(crate) fn read_target_names(graph: &Graph, primitive: Primitive) -> Option<Vec<String>> {
+    let parse = |extras: &Option<Box<serde_json::value::RawValue>>| {
+        extras
+            .as_ref()
+            .and_then(|raw| serde_json::from_str::<TargetNamesExtras>(raw.get()).ok())
+            .map(|extras| extras.target_names)
+    };
+
+    parse(&primitive.get(graph).extras).or_else(|| {
+        primitive
+            .mesh(graph)
+            .and_then(|mesh| parse(&mesh.get(graph).extras))
+    })
+}
+
+/// Collects the [MorphTargetNames] of the scene into [VrmMorphTargets] on the root.
+#[cfg(feature = "vrm0")]
+pub(crate) fn collect_morph_targets(world: &mut World) {
+    let mut primitives = world.query::<(Entity, &MorphTargetNames, &Parent)>();
+
+    let mut meshes = HashMap::<Entity, MorphTargetMesh>::default();
+
+    for (entity, names, parent) in primitives.iter(world) {
+        let mesh = meshes
+            .entry(parent.get())
+            .or_insert_with(|| MorphTargetMesh {
+                entity: parent.get(),
+                primitives: Vec::new(),
+                names: names.0.clone(),
+            });
+
+        mesh.primitives.push(entity);
+    }
+
+    let mut meshes = meshes.into_values().collect::<Vec<_>>();
+    meshes.sort_by_key(|m| m.entity);
+
+    let mut roots = world.query_filtered::<Entity, Without<Parent>>();
+    let root = roots.single(world);
+
+    world.entity_mut(root).insert(VrmMorphTargets(meshes));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_by_name() {
+        let a = Entity::from_raw(1);
+        let b = Entity::from_raw(2);
+
+        let targets = VrmMorphTargets(vec![
+            MorphTargetMesh {
+                entity: a,
+                primitives: Vec::new(),
+                names: vec!["Fcl_MTH_A".to_string(), "Fcl_MTH_I".to_string()],
+            },
+            MorphTargetMesh {
+                entity: b,
+                primitives: Vec::new(),
+                names: vec!["Fcl_MTH_I".to_string()],
+            },
+        ]);
+
+        assert_eq!(
+            targets.find("Fcl_MTH_I").collect::<Vec<_>>(),
+            vec![
+                MorphTarget {
+                    entity: a,
+                    index: 1
+                },
+                MorphTarget {
+                    entity: b,
+                    index: 0
+                },
+            ]
+        );
+        assert_eq!(targets.find("Fcl_MTH_U").count(), 0);
+        assert_eq!(
+            targets.name(MorphTarget {
+                entity: a,
+                index: 0
+            }),
+            Some("Fcl_MTH_A")
+        );
+    }
+}
@@ -1,6 +1,7 @@
 use bevy::{
     ecs::{entity::MapEntities, reflect::ReflectMapEntities},
     prelude::*,
+    transform::TransformSystem,
 };
 
 #[derive(Component, Default, Reflect)]
@@ -51,7 +52,10 @@ impl Plugin for SpringBonePlugin {
     fn build(&self, app: &mut App) {
         app.register_type::<SpringBoneLogicState>()
             .register_type::<SpringBones>()
-            .add_systems(Update, do_springbone_logic);
+            .add_systems(
+                PostUpdate,
+                do_springbone_logic.after(TransformSystem::TransformPropagate),
+            );
     }
 }
 
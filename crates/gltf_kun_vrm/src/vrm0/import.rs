@@ -4,6 +4,7 @@ use gltf_kun::{
     io::format::gltf::GltfFormat,
 };
 use thiserror::Error;
+use tracing::warn;
 
 use super::{
     bind::{Bind, BindWeight},
@@ -245,23 +246,28 @@ impl ExtensionImport<GltfDocument, GltfFormat> for Vrm {
 
             for group_json in blend_shape_groups {
                 let group = BlendShapeGroup::new(graph);
+                vrm.add_blend_shape_group(graph, group);
 
                 let binds = group_json.binds.unwrap_or_default();
 
                 for bind_json in binds {
-                    let bind = Bind::new(graph);
-
-                    if let Some(mesh_idx) = bind_json.mesh {
-                        if let Some(mesh) = doc.meshes(graph).get(mesh_idx as usize) {
-                            let index = bind_json.index.unwrap_or_default();
-
-                            if let Some(primitive) = mesh.primitives(graph).get(index as usize) {
-                                bind.set_primitive(graph, Some(*primitive));
+                    let mesh = match bind_json.mesh {
+                        Some(mesh_idx) => match doc.meshes(graph).get(mesh_idx as usize) {
+                            Some(mesh) => Some(*mesh),
+                            None => {
+                                warn!("Skipping blend shape bind, mesh not found: {}", mesh_idx);
+                                continue;
                             }
-                        }
-                    }
+                        },
+                        None => None,
+                    };
+
+                    let bind = Bind::new(graph);
+                    group.add_bind(graph, bind);
+                    bind.set_mesh(graph, mesh);
 
                     let weight = BindWeight {
+                        index: bind_json.index,
                         weight: bind_json.weight,
                     };
 
@@ -0,0 +1,108 @@
+use std::collections::BTreeMap;
+
+use gltf_kun::{
+    extensions::ExtensionExport,
+    graph::{gltf::GltfDocument, ByteNode, Extensions, Graph},
+    io::format::gltf::GltfFormat,
+};
+use serde_vrm::vrm1::vrmc_vrm_animation::{
+    Expression, Expressions, HumanBone, Humanoid, LookAt, VrmcVrmAnimation as VrmcVrmAnimationJson,
+};
+use thiserror::Error;
+
+use super::{VrmcVrmAnimation, EXTENSION_NAME};
+
+#[derive(Debug, Error)]
+pub enum VrmcVrmAnimationExportError {
+    #[error("Node not in document")]
+    NodeNotInDocument,
+}
+
+impl ExtensionExport<GltfDocument, GltfFormat> for VrmcVrmAnimation {
+    fn export(
+        graph: &mut Graph,
+        doc: &GltfDocument,
+        format: &mut GltfFormat,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(vrma) = doc.get_extension::<VrmcVrmAnimation>(graph) else {
+            return Ok(());
+        };
+
+        let node_index = |node| {
+            doc.node_index(graph, node)
+                .map(|idx| idx as u32)
+                .ok_or_else(|| Box::new(VrmcVrmAnimationExportError::NodeNotInDocument))
+        };
+
+        let mut human_bones = BTreeMap::new();
+
+        for bone in vrma.human_bones(graph) {
+            let Some(node) = bone.node(graph) else {
+                continue;
+            };
+
+            let weight = bone.read(graph);
+            human_bones.insert(
+                weight.name,
+                HumanBone {
+                    node: node_index(node)?,
+                },
+            );
+        }
+
+        let mut preset = BTreeMap::new();
+        let mut custom = BTreeMap::new();
+
+        for expression in vrma.expressions(graph) {
+            let Some(node) = expression.node(graph) else {
+                continue;
+            };
+
+            let weight = expression.read(graph);
+            let map = if weight.is_preset {
+                &mut preset
+            } else {
+                &mut custom
+            };
+
+            map.insert(
+                weight.name,
+                Expression {
+                    node: node_index(node)?,
+                },
+            );
+        }
+
+        let look_at = match vrma.look_at(graph) {
+            Some(node) => Some(LookAt {
+                node: node_index(node)?,
+            }),
+            None => None,
+        };
+
+        let json = VrmcVrmAnimationJson {
+            spec_version: vrma.read(graph).spec_version,
+            humanoid: Some(Humanoid { human_bones }),
+            expressions: if preset.is_empty() && custom.is_empty() {
+                None
+            } else {
+                Some(Expressions {
+                    preset: Some(preset),
+                    custom: Some(custom),
+                })
+            },
+            look_at,
+        };
+
+        format
+            .json
+            .extensions
+            .get_or_insert_with(Default::default)
+            .others
+            .insert(EXTENSION_NAME.to_string(), serde_json::to_value(json)?);
+
+        format.json.extensions_used.push(EXTENSION_NAME.to_string());
+
+        Ok(())
+    }
+}
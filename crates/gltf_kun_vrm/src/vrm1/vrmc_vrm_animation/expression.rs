@@ -0,0 +1,73 @@
+use std::fmt::Display;
+
+use gltf_kun::graph::{gltf::Node, ByteNode, Graph, NodeIndex, OtherEdgeHelpers, Weight};
+use serde::{Deserialize, Serialize};
+
+#[derive(Copy, Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum ExpressionEdges {
+    #[serde(rename = "VRMC_vrm_animation/Expression/Node")]
+    Node,
+}
+
+impl Display for ExpressionEdges {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let string = serde_json::to_string(self).unwrap();
+        f.write_str(&string)?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct ExpressionWeight {
+    /// VRM 1.0 preset name, such as `happy` or `blinkLeft`, or a custom name.
+    pub name: String,
+    pub is_preset: bool,
+}
+
+impl From<&Vec<u8>> for ExpressionWeight {
+    fn from(bytes: &Vec<u8>) -> Self {
+        if bytes.is_empty() {
+            return Self::default();
+        }
+        serde_json::from_slice(bytes).expect("Failed to deserialize weight")
+    }
+}
+
+impl From<&ExpressionWeight> for Vec<u8> {
+    fn from(value: &ExpressionWeight) -> Self {
+        serde_json::to_vec(value).expect("Failed to serialize weight")
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Expression(pub NodeIndex);
+
+impl From<NodeIndex> for Expression {
+    fn from(index: NodeIndex) -> Self {
+        Self(index)
+    }
+}
+
+impl From<Expression> for NodeIndex {
+    fn from(expression: Expression) -> Self {
+        expression.0
+    }
+}
+
+impl ByteNode<ExpressionWeight> for Expression {}
+impl OtherEdgeHelpers for Expression {}
+
+impl Expression {
+    pub fn new(graph: &mut Graph) -> Self {
+        let weight = &ExpressionWeight::default();
+        let node = graph.add_node(Weight::Bytes(weight.into()));
+        Self(node)
+    }
+
+    pub fn node(&self, graph: &Graph) -> Option<Node> {
+        self.find_property(graph, &ExpressionEdges::Node.to_string())
+    }
+    pub fn set_node(&self, graph: &mut Graph, node: Option<Node>) {
+        self.set_property(graph, ExpressionEdges::Node.to_string(), node);
+    }
+}
@@ -0,0 +1,72 @@
+use std::fmt::Display;
+
+use gltf_kun::graph::{gltf::Node, ByteNode, Graph, NodeIndex, OtherEdgeHelpers, Weight};
+use serde::{Deserialize, Serialize};
+
+#[derive(Copy, Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum HumanBoneEdges {
+    #[serde(rename = "VRMC_vrm_animation/HumanBone/Node")]
+    Node,
+}
+
+impl Display for HumanBoneEdges {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let string = serde_json::to_string(self).unwrap();
+        f.write_str(&string)?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct HumanBoneWeight {
+    /// VRM 1.0 human bone name, such as `hips` or `leftThumbMetacarpal`.
+    pub name: String,
+}
+
+impl From<&Vec<u8>> for HumanBoneWeight {
+    fn from(bytes: &Vec<u8>) -> Self {
+        if bytes.is_empty() {
+            return Self::default();
+        }
+        serde_json::from_slice(bytes).expect("Failed to deserialize weight")
+    }
+}
+
+impl From<&HumanBoneWeight> for Vec<u8> {
+    fn from(value: &HumanBoneWeight) -> Self {
+        serde_json::to_vec(value).expect("Failed to serialize weight")
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct HumanBone(pub NodeIndex);
+
+impl From<NodeIndex> for HumanBone {
+    fn from(index: NodeIndex) -> Self {
+        Self(index)
+    }
+}
+
+impl From<HumanBone> for NodeIndex {
+    fn from(bone: HumanBone) -> Self {
+        bone.0
+    }
+}
+
+impl ByteNode<HumanBoneWeight> for HumanBone {}
+impl OtherEdgeHelpers for HumanBone {}
+
+impl HumanBone {
+    pub fn new(graph: &mut Graph) -> Self {
+        let weight = &HumanBoneWeight::default();
+        let node = graph.add_node(Weight::Bytes(weight.into()));
+        Self(node)
+    }
+
+    pub fn node(&self, graph: &Graph) -> Option<Node> {
+        self.find_property(graph, &HumanBoneEdges::Node.to_string())
+    }
+    pub fn set_node(&self, graph: &mut Graph, node: Option<Node>) {
+        self.set_property(graph, HumanBoneEdges::Node.to_string(), node);
+    }
+}
@@ -0,0 +1,97 @@
+use gltf_kun::{
+    extensions::ExtensionImport,
+    graph::{gltf::GltfDocument, ByteNode, Extensions, Graph},
+    io::format::gltf::GltfFormat,
+};
+use thiserror::Error;
+
+use super::{
+    expression::{Expression, ExpressionWeight},
+    human_bone::{HumanBone, HumanBoneWeight},
+    VrmcVrmAnimation, VrmcVrmAnimationWeight, EXTENSION_NAME,
+};
+
+#[derive(Debug, Error)]
+pub enum VrmcVrmAnimationImportError {
+    #[error("Node not found: {0}")]
+    NodeNotFound(usize),
+}
+
+impl ExtensionImport<GltfDocument, GltfFormat> for VrmcVrmAnimation {
+    fn import(
+        graph: &mut Graph,
+        format: &mut GltfFormat,
+        doc: &GltfDocument,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let extensions = match &format.json.extensions {
+            Some(extensions) => extensions,
+            None => return Ok(()),
+        };
+
+        let ext = match extensions.others.get(EXTENSION_NAME) {
+            Some(ext) => ext,
+            None => return Ok(()),
+        };
+
+        let ext: serde_vrm::vrm1::vrmc_vrm_animation::VrmcVrmAnimation =
+            serde_json::from_value(ext.clone())?;
+
+        let vrma = VrmcVrmAnimation::new(graph);
+        doc.add_extension(graph, vrma);
+
+        vrma.write(
+            graph,
+            &VrmcVrmAnimationWeight {
+                spec_version: ext.spec_version,
+            },
+        );
+
+        let nodes = doc.nodes(graph);
+
+        let get_node = |idx: u32| {
+            nodes
+                .get(idx as usize)
+                .copied()
+                .ok_or_else(|| Box::new(VrmcVrmAnimationImportError::NodeNotFound(idx as usize)))
+        };
+
+        if let Some(humanoid) = ext.humanoid {
+            for (name, bone_json) in humanoid.human_bones {
+                let node = get_node(bone_json.node)?;
+
+                let bone = HumanBone::new(graph);
+                vrma.add_human_bone(graph, bone);
+
+                bone.write(graph, &HumanBoneWeight { name });
+                bone.set_node(graph, Some(node));
+            }
+        }
+
+        if let Some(expressions) = ext.expressions {
+            let preset = expressions.preset.unwrap_or_default();
+            let custom = expressions.custom.unwrap_or_default();
+
+            let all = preset
+                .into_iter()
+                .map(|(name, e)| (name, e, true))
+                .chain(custom.into_iter().map(|(name, e)| (name, e, false)));
+
+            for (name, expression_json, is_preset) in all {
+                let node = get_node(expression_json.node)?;
+
+                let expression = Expression::new(graph);
+                vrma.add_expression(graph, expression);
+
+                expression.write(graph, &ExpressionWeight { name, is_preset });
+                expression.set_node(graph, Some(node));
+            }
+        }
+
+        if let Some(look_at) = ext.look_at {
+            let node = get_node(look_at.node)?;
+            vrma.set_look_at(graph, Some(node));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+use std::fmt::Display;
+
+use gltf_kun::{
+    extensions::Extension,
+    graph::{gltf::Node, ByteNode, Graph, NodeIndex, OtherEdgeHelpers, Weight},
+};
+use serde::{Deserialize, Serialize};
+
+use self::{expression::Expression, human_bone::HumanBone};
+
+pub mod export;
+pub mod expression;
+pub mod human_bone;
+pub mod import;
+
+pub const EXTENSION_NAME: &str = "VRMC_vrm_animation";
+
+#[derive(Copy, Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum VrmcVrmAnimationEdge {
+    #[serde(rename = "VRMC_vrm_animation/Expression")]
+    Expression,
+    #[serde(rename = "VRMC_vrm_animation/HumanBone")]
+    HumanBone,
+    #[serde(rename = "VRMC_vrm_animation/LookAt")]
+    LookAt,
+}
+
+impl Display for VrmcVrmAnimationEdge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let string = serde_json::to_string(self).unwrap();
+        f.write_str(&string)?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct VrmcVrmAnimationWeight {
+    pub spec_version: Option<String>,
+}
+
+impl From<&Vec<u8>> for VrmcVrmAnimationWeight {
+    fn from(bytes: &Vec<u8>) -> Self {
+        if bytes.is_empty() {
+            return Self::default();
+        }
+        serde_json::from_slice(bytes).expect("Failed to deserialize weight")
+    }
+}
+
+impl From<&VrmcVrmAnimationWeight> for Vec<u8> {
+    fn from(value: &VrmcVrmAnimationWeight) -> Self {
+        serde_json::to_vec(value).expect("Failed to serialize weight")
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct VrmcVrmAnimation(pub NodeIndex);
+
+impl From<NodeIndex> for VrmcVrmAnimation {
+    fn from(index: NodeIndex) -> Self {
+        Self(index)
+    }
+}
+
+impl From<VrmcVrmAnimation> for NodeIndex {
+    fn from(vrma: VrmcVrmAnimation) -> Self {
+        vrma.0
+    }
+}
+
+impl ByteNode<VrmcVrmAnimationWeight> for VrmcVrmAnimation {}
+impl OtherEdgeHelpers for VrmcVrmAnimation {}
+
+impl Extension for VrmcVrmAnimation {
+    fn name() -> &'static str {
+        EXTENSION_NAME
+    }
+}
+
+impl VrmcVrmAnimation {
+    pub fn new(graph: &mut Graph) -> Self {
+        let weight = &VrmcVrmAnimationWeight::default();
+        let node = graph.add_node(Weight::Bytes(weight.into()));
+        Self(node)
+    }
+
+    pub fn expressions(&self, graph: &Graph) -> Vec<Expression> {
+        self.find_properties(graph, &VrmcVrmAnimationEdge::Expression.to_string())
+    }
+    pub fn add_expression(&self, graph: &mut Graph, expression: Expression) {
+        self.add_property(
+            graph,
+            VrmcVrmAnimationEdge::Expression.to_string(),
+            expression,
+        );
+    }
+    pub fn remove_expression(&self, graph: &mut Graph, expression: Expression) {
+        self.remove_property(
+            graph,
+            &VrmcVrmAnimationEdge::Expression.to_string(),
+            expression,
+        );
+    }
+
+    pub fn human_bones(&self, graph: &Graph) -> Vec<HumanBone> {
+        self.find_properties(graph, &VrmcVrmAnimationEdge::HumanBone.to_string())
+    }
+    pub fn add_human_bone(&self, graph: &mut Graph, bone: HumanBone) {
+        self.add_property(graph, VrmcVrmAnimationEdge::HumanBone.to_string(), bone);
+    }
+    pub fn remove_human_bone(&self, graph: &mut Graph, bone: HumanBone) {
+        self.remove_property(graph, &VrmcVrmAnimationEdge::HumanBone.to_string(), bone);
+    }
+
+    pub fn look_at(&self, graph: &Graph) -> Option<Node> {
+        self.find_property(graph, &VrmcVrmAnimationEdge::LookAt.to_string())
+    }
+    pub fn set_look_at(&self, graph: &mut Graph, node: Option<Node>) {
+        self.set_property(graph, VrmcVrmAnimationEdge::LookAt.to_string(), node);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gltf_kun::graph::GraphNodeWeight;
+
+    use super::*;
+
+    #[test]
+    fn human_bones() {
+        let mut graph = Graph::new();
+
+        let vrma = VrmcVrmAnimation::new(&mut graph);
+        let bone = HumanBone::new(&mut graph);
+
+        vrma.add_human_bone(&mut graph, bone);
+        assert_eq!(vrma.human_bones(&graph), vec![bone]);
+
+        let bone_2 = HumanBone::new(&mut graph);
+        vrma.add_human_bone(&mut graph, bone_2);
+        assert_eq!(vrma.human_bones(&graph), vec![bone, bone_2]);
+
+        vrma.remove_human_bone(&mut graph, bone);
+        assert_eq!(vrma.human_bones(&graph), vec![bone_2]);
+    }
+
+    #[test]
+    fn expressions() {
+        let mut graph = Graph::new();
+
+        let vrma = VrmcVrmAnimation::new(&mut graph);
+        let expression = Expression::new(&mut graph);
+
+        vrma.add_expression(&mut graph, expression);
+        assert_eq!(vrma.expressions(&graph), vec![expression]);
+
+        vrma.remove_expression(&mut graph, expression);
+        assert!(vrma.expressions(&graph).is_empty());
+    }
+
+    #[test]
+    fn look_at() {
+        let mut graph = Graph::new();
+
+        let vrma = VrmcVrmAnimation::new(&mut graph);
+        let node = Node::new(&mut graph);
+
+        vrma.set_look_at(&mut graph, Some(node));
+        assert_eq!(vrma.look_at(&graph), Some(node));
+
+        vrma.set_look_at(&mut graph, None);
+        assert_eq!(vrma.look_at(&graph), None);
+    }
+}
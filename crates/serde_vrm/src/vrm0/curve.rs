@@ -0,0 +1,261 @@
+//! Unity `AnimationCurve` as serialized by UniVRM.
+
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+/// A Unity `AnimationCurve`, used by [LookAtCurve](super::LookAtCurve).
+///
+/// Serialized as a flat array of floats, with four values per key:
+/// `[time, value, in_tangent, out_tangent, ...]`.
+#[cfg_attr(feature = "bevy", derive(bevy::reflect::Reflect))]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Curve {
+    pub keys: Vec<CurveKey>,
+}
+
+#[cfg_attr(feature = "bevy", derive(bevy::reflect::Reflect))]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct CurveKey {
+    pub time: f32,
+    pub value: f32,
+    pub in_tangent: f32,
+    pub out_tangent: f32,
+}
+
+impl CurveKey {
+    pub fn new(time: f32, value: f32, in_tangent: f32, out_tangent: f32) -> Self {
+        Self {
+            time,
+            value,
+            in_tangent,
+            out_tangent,
+        }
+    }
+}
+
+impl Curve {
+    /// The curve UniVRM writes by default, a straight line from (0, 0) to (1, 1).
+    ///
+    /// Serialized as `[0, 0, 0, 1, 1, 1, 1, 0]`.
+    pub fn linear() -> Self {
+        Self {
+            keys: vec![
+                CurveKey::new(0.0, 0.0, 0.0, 1.0),
+                CurveKey::new(1.0, 1.0, 1.0, 0.0),
+            ],
+        }
+    }
+
+    /// Evaluates the curve at `time`, like Unity's `AnimationCurve.Evaluate`.
+    ///
+    /// Keys are interpolated with cubic Hermite splines using each key's tangents.
+    /// Times outside the curve are clamped to the first or last key.
+    pub fn evaluate(&self, time: f32) -> f32 {
+        let (first, last) = match (self.keys.first(), self.keys.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return 0.0,
+        };
+
+        if time <= first.time {
+            return first.value;
+        }
+
+        if time >= last.time {
+            return last.value;
+        }
+
+        let i = self
+            .keys
+            .windows(2)
+            .position(|w| time < w[1].time)
+            .unwrap_or(self.keys.len() - 2);
+
+        let k0 = &self.keys[i];
+        let k1 = &self.keys[i + 1];
+
+        let dt = k1.time - k0.time;
+
+        if dt <= 0.0 {
+            return k1.value;
+        }
+
+        // Unity treats infinite tangents as a step.
+        if !k0.out_tangent.is_finite() || !k1.in_tangent.is_finite() {
+            return k0.value;
+        }
+
+        let t = (time - k0.time) / dt;
+        let t2 = t * t;
+        let t3 = t2 * t;
+
+        let m0 = k0.out_tangent * dt;
+        let m1 = k1.in_tangent * dt;
+
+        (2.0 * t3 - 3.0 * t2 + 1.0) * k0.value
+            + (t3 - 2.0 * t2 + t) * m0
+            + (-2.0 * t3 + 3.0 * t2) * k1.value
+            + (t3 - t2) * m1
+    }
+}
+
+impl Serialize for Curve {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.keys
+            .iter()
+            .flat_map(|k| [k.time, k.value, k.in_tangent, k.out_tangent])
+            .collect::<Vec<_>>()
+            .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Curve {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let values = Vec::<f32>::deserialize(deserializer)?;
+
+        if values.len() % 4 != 0 {
+            return Err(D::Error::custom(format!(
+                "curve length must be a multiple of 4, got {}",
+                values.len()
+            )));
+        }
+
+        let keys = values
+            .chunks_exact(4)
+            .map(|c| CurveKey::new(c[0], c[1], c[2], c[3]))
+            .collect();
+
+        Ok(Self { keys })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn deserialize_univrm_default() {
+        // Curve written by UniVRM for a default CurveMapper.
+        let curve: Curve = serde_json::from_str("[0, 0, 0, 1, 1, 1, 1, 0]").unwrap();
+
+        assert_eq!(
+            curve.keys,
+            vec![
+                CurveKey::new(0.0, 0.0, 0.0, 1.0),
+                CurveKey::new(1.0, 1.0, 1.0, 0.0),
+            ]
+        );
+
+        let json = serde_json::to_string(&curve).unwrap();
+        assert_eq!(json, "[0.0,0.0,0.0,1.0,1.0,1.0,1.0,0.0]");
+    }
+
+    #[test]
+    fn univrm_export() {
+        // Look-at curves of an avatar exported by UniVRM 0.99.4.
+        let bytes = std::fs::read("../../assets/catbot.vrm").unwrap();
+        let length = u32::from_le_bytes(bytes[12..16].try_into().unwrap()) as usize;
+        let json: serde_json::Value = serde_json::from_slice(&bytes[20..20 + length]).unwrap();
+        let vrm: crate::vrm0::Vrm =
+            serde_json::from_value(json["extensions"]["VRM"].clone()).unwrap();
+
+        let first_person = vrm.first_person.unwrap();
+
+        for look_at in [
+            first_person.look_at_horizontal_inner,
+            first_person.look_at_horizontal_outer,
+            first_person.look_at_vertical_down,
+            first_person.look_at_vertical_up,
+        ] {
+            let curve = look_at.unwrap().curve.unwrap();
+            assert_eq!(curve, Curve::linear());
+
+            // CurveMapper.Map normalizes the input by xRange before evaluating.
+            for (degrees, expected) in [(0.0, 0.0), (22.5, 0.25), (45.0, 0.5), (90.0, 1.0)] {
+                assert_close(curve.evaluate(degrees / 90.0), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn deserialize_invalid_length() {
+        assert!(serde_json::from_str::<Curve>("[0, 0, 0]").is_err());
+    }
+
+    #[test]
+    fn evaluate_univrm_default() {
+        let curve: Curve = serde_json::from_str("[0, 0, 0, 1, 1, 1, 1, 0]").unwrap();
+
+        for (time, expected) in [
+            (0.0, 0.0),
+            (0.25, 0.25),
+            (0.5, 0.5),
+            (0.75, 0.75),
+            (1.0, 1.0),
+        ] {
+            assert_close(curve.evaluate(time), expected);
+        }
+    }
+
+    #[test]
+    fn evaluate_ease_in_out() {
+        // AnimationCurve.EaseInOut(0, 0, 1, 1)
+        let curve: Curve = serde_json::from_str("[0, 0, 0, 0, 1, 1, 0, 0]").unwrap();
+
+        assert_close(curve.evaluate(0.25), 0.15625);
+        assert_close(curve.evaluate(0.5), 0.5);
+        assert_close(curve.evaluate(0.75), 0.84375);
+    }
+
+    #[test]
+    fn evaluate_scaled_time() {
+        // Tangents are per unit of time, so a key spacing of 2 scales them.
+        let curve = Curve {
+            keys: vec![
+                CurveKey::new(0.0, 0.0, 0.0, 0.5),
+                CurveKey::new(2.0, 1.0, 0.5, 0.0),
+            ],
+        };
+
+        assert_close(curve.evaluate(1.0), 0.5);
+        assert_close(curve.evaluate(0.5), 0.25);
+    }
+
+    #[test]
+    fn evaluate_multiple_keys() {
+        let curve = Curve {
+            keys: vec![
+                CurveKey::new(0.0, 0.0, 0.0, 0.0),
+                CurveKey::new(0.5, 1.0, 0.0, 0.0),
+                CurveKey::new(1.0, 0.0, 0.0, 0.0),
+            ],
+        };
+
+        assert_close(curve.evaluate(0.5), 1.0);
+        assert_close(curve.evaluate(0.25), 0.5);
+        assert_close(curve.evaluate(0.75), 0.5);
+    }
+
+    #[test]
+    fn evaluate_clamps() {
+        let curve = Curve::linear();
+
+        assert_close(curve.evaluate(-1.0), 0.0);
+        assert_close(curve.evaluate(2.0), 1.0);
+        assert_close(Curve::default().evaluate(0.5), 0.0);
+    }
+
+    #[test]
+    fn evaluate_step() {
+        let curve = Curve {
+            keys: vec![
+                CurveKey::new(0.0, 0.0, 0.0, f32::INFINITY),
+                CurveKey::new(1.0, 1.0, f32::INFINITY, 0.0),
+            ],
+        };
+
+        assert_close(curve.evaluate(0.9), 0.0);
+    }
+}
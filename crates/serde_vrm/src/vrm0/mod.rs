@@ -4,6 +4,10 @@ use std::fmt::Display;
 
 use serde::{Deserialize, Serialize};
 
+mod curve;
+
+pub use curve::{Curve, CurveKey};
+
 #[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Vrm {
@@ -240,7 +244,7 @@ pub enum FirstPersonFlag {
 #[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct LookAtCurve {
-    pub curve: Option<[f32; 8]>,
+    pub curve: Option<Curve>,
     pub x_range: Option<f32>,
     pub y_range: Option<f32>,
 }
@@ -261,7 +265,8 @@ pub struct BlendShapeGroup {
     pub is_binary: Option<bool>,
 }
 
-#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[cfg_attr(feature = "bevy", derive(bevy::reflect::Reflect))]
+#[derive(Copy, Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub enum PresetName {
     #[serde(rename = "unknown")]
     Unknown,
@@ -301,6 +306,15 @@ pub enum PresetName {
     BlinkRight,
 }
 
+impl Display for PresetName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match serde_json::to_value(self) {
+            Ok(serde_json::Value::String(name)) => f.write_str(&name),
+            _ => Err(std::fmt::Error),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct MaterialBind {
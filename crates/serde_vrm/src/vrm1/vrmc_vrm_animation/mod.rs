@@ -0,0 +1,97 @@
+//! Types for the [VRMC_vrm_animation](https://github.com/vrm-c/vrm-specification/tree/master/specification/VRMC_vrm_animation-1.0) extension.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VrmcVrmAnimation {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spec_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub humanoid: Option<Humanoid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expressions: Option<Expressions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub look_at: Option<LookAt>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Humanoid {
+    /// VRM 1.0 human bone names, such as `hips` or `leftThumbMetacarpal`.
+    pub human_bones: BTreeMap<String, HumanBone>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct HumanBone {
+    pub node: u32,
+}
+
+/// Expressions are animated using the X translation of their node.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Expressions {
+    /// VRM 1.0 preset names, such as `happy` or `blinkLeft`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preset: Option<BTreeMap<String, Expression>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub custom: Option<BTreeMap<String, Expression>>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Expression {
+    pub node: u32,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct LookAt {
+    pub node: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deserialize() {
+        let json = r#"{
+            "specVersion": "1.0",
+            "humanoid": {
+                "humanBones": {
+                    "hips": { "node": 1 },
+                    "leftThumbMetacarpal": { "node": 2 }
+                }
+            },
+            "expressions": {
+                "preset": { "happy": { "node": 3 } },
+                "custom": { "wink": { "node": 4 } }
+            },
+            "lookAt": { "node": 5 }
+        }"#;
+
+        let ext: VrmcVrmAnimation = serde_json::from_str(json).unwrap();
+
+        let humanoid = ext.humanoid.unwrap();
+        assert_eq!(humanoid.human_bones["hips"].node, 1);
+        assert_eq!(humanoid.human_bones["leftThumbMetacarpal"].node, 2);
+
+        let expressions = ext.expressions.unwrap();
+        assert_eq!(expressions.preset.unwrap()["happy"].node, 3);
+        assert_eq!(expressions.custom.unwrap()["wink"].node, 4);
+
+        assert_eq!(ext.look_at.unwrap().node, 5);
+    }
+
+    #[test]
+    fn serialize_skips_missing() {
+        let ext = VrmcVrmAnimation {
+            spec_version: Some("1.0".to_string()),
+            expressions: Some(Expressions::default()),
+            ..Default::default()
+        };
+
+        let json = serde_json::to_string(&ext).unwrap();
+        assert_eq!(json, r#"{"specVersion":"1.0","expressions":{}}"#);
+    }
+}